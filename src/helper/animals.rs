@@ -1,15 +1 @@
-use std::sync::LazyLock;
-
-use rand::rngs::OsRng;
-use rand::seq::SliceRandom;
-
-pub fn get_animal_name() -> &'static str {
-    static ANIMALS: LazyLock<Vec<&'static str>> = LazyLock::new(|| {
-        let animals = include_str!("./animals.txt");
-        animals.lines().collect()
-    });
-
-    ANIMALS
-        .choose(&mut OsRng)
-        .expect("List should not be empty")
-}
+pub const ANIMALS: &str = include_str!("./animals.txt");
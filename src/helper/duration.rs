@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+/// Format a duration using its two most significant units, e.g. `2h13m`
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (days, hours, minutes, seconds) = (
+        seconds / 86400,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+    );
+
+    if days > 0 {
+        format!("{days}d{hours}h")
+    } else if hours > 0 {
+        format!("{hours}h{minutes}m")
+    } else if minutes > 0 {
+        format!("{minutes}m{seconds}s")
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+/// Parse a duration made up of numbers followed by a unit (`d`, `h`, `m` or `s`), e.g. `1h30m`. A
+/// number without a unit is interpreted as seconds.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("duration is empty".into());
+    }
+
+    let mut seconds: u64 = 0;
+    let mut number = String::new();
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let multiplier = match c {
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("invalid unit '{c}' in duration '{input}'")),
+        };
+
+        let value: u64 = std::mem::take(&mut number)
+            .parse()
+            .map_err(|_| format!("missing number before '{c}' in duration '{input}'"))?;
+        seconds = value
+            .checked_mul(multiplier)
+            .and_then(|value| seconds.checked_add(value))
+            .ok_or_else(|| format!("duration '{input}' is too long"))?;
+    }
+
+    if !number.is_empty() {
+        let value: u64 = number
+            .parse()
+            .map_err(|_| format!("duration '{input}' is too long"))?;
+        seconds = seconds
+            .checked_add(value)
+            .ok_or_else(|| format!("duration '{input}' is too long"))?;
+    }
+
+    Ok(Duration::from_secs(seconds))
+}
@@ -1,5 +1,9 @@
 mod animals;
+mod duration;
+mod qr;
 mod units;
 
-pub use animals::get_animal_name;
+pub use animals::ANIMALS;
+pub use duration::{format_duration, parse_duration};
+pub use qr::render_qr;
 pub use units::Unit;
@@ -0,0 +1,15 @@
+use qrcode::QrCode;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::types::QrError;
+
+/// Render the text as a QR code using unicode half blocks, two rows of modules per line. The
+/// colors are inverted, so the code scans correctly on terminals with a dark background.
+pub fn render_qr(text: &str) -> Result<String, QrError> {
+    let code = QrCode::new(text)?;
+
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
@@ -3,19 +3,50 @@ use std::fmt;
 pub struct Unit {
     value: usize,
     unit: String,
+    binary: bool,
 }
 
 impl Unit {
+    /// Format using the 1000-based SI prefixes (kB, MB, ...)
     pub fn new(value: usize, unit: impl Into<String>) -> Self {
         Self {
             value,
             unit: unit.into(),
+            binary: false,
         }
     }
+
+    /// Format using the 1024-based binary prefixes (KiB, MiB, ...)
+    pub fn binary(value: usize, unit: impl Into<String>) -> Self {
+        Self {
+            binary: true,
+            ..Self::new(value, unit)
+        }
+    }
+
+    /// Binary prefixes switch as soon as the value reaches 1024, so one decimal place is kept to
+    /// not lose precision, e.g. 1.5 KiB
+    fn fmt_binary(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut value = self.value as f64;
+        let mut prefix = UnitPrefix::None;
+
+        while value >= 1024.0 {
+            value /= 1024.0;
+            prefix = prefix.next();
+        }
+
+        let value = format!("{value:.1}");
+        let value = value.strip_suffix(".0").unwrap_or(&value);
+        write!(f, "{} {}{}", value, prefix.symbol(true), self.unit)
+    }
 }
 
 impl fmt::Display for Unit {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.binary {
+            return self.fmt_binary(f);
+        }
+
         let mut value = self.value;
         let mut prefix = UnitPrefix::None;
 
@@ -24,7 +55,7 @@ impl fmt::Display for Unit {
             prefix = prefix.next();
         }
 
-        write!(f, "{} {}{}", value, prefix, self.unit)
+        write!(f, "{} {}{}", value, prefix.symbol(false), self.unit)
     }
 }
 
@@ -51,20 +82,23 @@ impl UnitPrefix {
             UnitPrefix::Exa | UnitPrefix::Impossible => UnitPrefix::Impossible,
         }
     }
-}
 
-impl fmt::Display for UnitPrefix {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let prefix = match self {
-            UnitPrefix::None => "",
-            UnitPrefix::Kilo => "k",
-            UnitPrefix::Mega => "M",
-            UnitPrefix::Giga => "G",
-            UnitPrefix::Tera => "T",
-            UnitPrefix::Peta => "P",
-            UnitPrefix::Exa => "E",
-            UnitPrefix::Impossible => "x",
-        };
-        f.write_str(prefix)
+    fn symbol(&self, binary: bool) -> &'static str {
+        match (self, binary) {
+            (UnitPrefix::None, _) => "",
+            (UnitPrefix::Kilo, false) => "k",
+            (UnitPrefix::Kilo, true) => "Ki",
+            (UnitPrefix::Mega, false) => "M",
+            (UnitPrefix::Mega, true) => "Mi",
+            (UnitPrefix::Giga, false) => "G",
+            (UnitPrefix::Giga, true) => "Gi",
+            (UnitPrefix::Tera, false) => "T",
+            (UnitPrefix::Tera, true) => "Ti",
+            (UnitPrefix::Peta, false) => "P",
+            (UnitPrefix::Peta, true) => "Pi",
+            (UnitPrefix::Exa, false) => "E",
+            (UnitPrefix::Exa, true) => "Ei",
+            (UnitPrefix::Impossible, _) => "x",
+        }
     }
 }
@@ -5,14 +5,82 @@ pub enum Input {
     Char(char),
     Up,
     Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
     Delete,
     Esc,
     Enter,
     Backspace,
     CtrlP,
+    CtrlR,
     Other,
 }
 
+impl From<char> for Input {
+    fn from(c: char) -> Self {
+        if c.is_ascii() {
+            Input::from([c as u8].as_slice())
+        } else if c.is_control() {
+            Input::Other
+        } else {
+            Input::Char(c)
+        }
+    }
+}
+
+/// Turns the data received from the client into input, multi-byte characters can be split over
+/// multiple messages so incomplete characters are kept until the rest arrives
+#[derive(Debug, Default)]
+pub struct InputDecoder {
+    pending: Vec<u8>,
+}
+
+impl InputDecoder {
+    pub fn decode(&mut self, data: &[u8]) -> Vec<Input> {
+        // Escape sequences and other control input always arrive as a whole, an incomplete
+        // character before it is never going to be finished
+        if data.first().is_some_and(u8::is_ascii_control) {
+            self.pending.clear();
+            return vec![Input::from(data)];
+        }
+
+        self.pending.extend_from_slice(data);
+
+        let mut inputs = Vec::new();
+        loop {
+            let (valid, invalid) = match std::str::from_utf8(&self.pending) {
+                Ok(text) => {
+                    inputs.extend(text.chars().map(Input::from));
+                    self.pending.clear();
+                    break;
+                }
+                Err(err) => (err.valid_up_to(), err.error_len()),
+            };
+
+            let text = std::str::from_utf8(&self.pending[..valid])
+                .expect("bytes should be valid up to this point");
+            inputs.extend(text.chars().map(Input::from));
+
+            match invalid {
+                // Invalid bytes are skipped
+                Some(len) => {
+                    trace!(bytes = ?&self.pending[valid..valid + len], "Invalid utf-8");
+                    self.pending.drain(..valid + len);
+                }
+                // The last character is incomplete, wait for the rest
+                None => {
+                    self.pending.drain(..valid);
+                    break;
+                }
+            }
+        }
+
+        inputs
+    }
+}
+
 impl From<&[u8]> for Input {
     fn from(value: &[u8]) -> Self {
         match value {
@@ -20,10 +88,17 @@ impl From<&[u8]> for Input {
             [27] => Input::Esc,
             [27, 91, 65] => Input::Up,
             [27, 91, 66] => Input::Down,
+            [27, 91, 53, 126] => Input::PageUp,
+            [27, 91, 54, 126] => Input::PageDown,
+            // Terminals do not agree on the sequences for home and end
+            [27, 91, 72] | [27, 79, 72] | [27, 91, 49, 126] | [27, 91, 55, 126] => Input::Home,
+            [27, 91, 70] | [27, 79, 70] | [27, 91, 52, 126] | [27, 91, 56, 126] => Input::End,
             [27, 91, 51, 126] => Input::Delete,
             [13] => Input::Enter,
             // NOTE: Actual char is DLE, this happens to map to ctrl-p
             [16] => Input::CtrlP,
+            // NOTE: Actual char is DC2, this happens to map to ctrl-r
+            [18] => Input::CtrlR,
             [127] => Input::Backspace,
             other => {
                 trace!("{other:?}");
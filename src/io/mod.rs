@@ -2,6 +2,6 @@ mod input;
 mod stats;
 mod terminal_handle;
 
-pub use input::Input;
+pub use input::{Input, InputDecoder};
 pub use stats::{Stats, TrackStats};
-pub use terminal_handle::TerminalHandle;
+pub use terminal_handle::{TerminalHandle, is_dumb_terminal};
@@ -1,7 +1,8 @@
 use std::pin::Pin;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use pin_project_lite::pin_project;
 use russh::ChannelStream;
@@ -14,9 +15,19 @@ pub struct Stats {
     connections: AtomicUsize,
     rx: AtomicUsize,
     tx: AtomicUsize,
+    requests: AtomicUsize,
+    // Milliseconds since the unix epoch, zero if the tunnel has never been used
+    last_seen: AtomicU64,
     failed: AtomicBool,
 }
 
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
 impl Stats {
     pub fn add_connection(&self) {
         self.connections.fetch_add(1, Ordering::Relaxed);
@@ -30,6 +41,25 @@ impl Stats {
         self.tx.fetch_add(n, Ordering::Relaxed);
     }
 
+    pub fn add_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.last_seen.store(now_millis(), Ordering::Relaxed);
+    }
+
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Time since the last request, `None` if there has not been a request yet
+    pub fn idle(&self) -> Option<Duration> {
+        match self.last_seen.load(Ordering::Relaxed) {
+            0 => None,
+            last_seen => Some(Duration::from_millis(
+                now_millis().saturating_sub(last_seen),
+            )),
+        }
+    }
+
     pub fn connections(&self) -> usize {
         self.connections.load(Ordering::Relaxed)
     }
@@ -42,12 +72,28 @@ impl Stats {
         self.failed.store(failed, Ordering::Relaxed);
     }
 
+    pub fn rx_bytes(&self) -> usize {
+        self.rx.load(Ordering::Relaxed)
+    }
+
+    pub fn tx_bytes(&self) -> usize {
+        self.tx.load(Ordering::Relaxed)
+    }
+
     pub fn rx(&self) -> Unit {
-        Unit::new(self.rx.load(Ordering::Relaxed), "B")
+        Unit::new(self.rx_bytes(), "B")
     }
 
     pub fn tx(&self) -> Unit {
-        Unit::new(self.tx.load(Ordering::Relaxed), "B")
+        Unit::new(self.tx_bytes(), "B")
+    }
+
+    pub fn rx_binary(&self) -> Unit {
+        Unit::binary(self.rx_bytes(), "B")
+    }
+
+    pub fn tx_binary(&self) -> Unit {
+        Unit::binary(self.tx_bytes(), "B")
     }
 }
 
@@ -5,13 +5,23 @@ use russh::server::Handle;
 use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
 use tracing::error;
 
+/// Terminals that can not be expected to handle escape codes beyond the bare minimum
+pub fn is_dumb_terminal(term: &str) -> bool {
+    matches!(term, "" | "dumb" | "unknown")
+}
+
 pub struct TerminalHandle {
     sender: UnboundedSender<Vec<u8>>,
     sink: Vec<u8>,
+    alternate_screen: bool,
 }
 
 impl TerminalHandle {
-    pub async fn start(handle: Handle, channel_id: ChannelId) -> std::io::Result<Self> {
+    pub async fn start(
+        handle: Handle,
+        channel_id: ChannelId,
+        alternate_screen: bool,
+    ) -> std::io::Result<Self> {
         let (sender, mut receiver) = unbounded_channel::<Vec<u8>>();
 
         tokio::spawn(async move {
@@ -31,15 +41,23 @@ impl TerminalHandle {
         let mut terminal_handle = Self {
             sender,
             sink: Vec::new(),
+            alternate_screen,
         };
 
-        execute!(terminal_handle, EnterAlternateScreen)?;
+        if alternate_screen {
+            execute!(terminal_handle, EnterAlternateScreen)?;
+        }
         execute!(terminal_handle, Clear(ClearType::All))?;
 
         Ok(terminal_handle)
     }
 
     pub fn leave_alternate_screen(&mut self) -> std::io::Result<()> {
+        if !self.alternate_screen {
+            return Ok(());
+        }
+
+        self.alternate_screen = false;
         execute!(self, LeaveAlternateScreen)
     }
 }
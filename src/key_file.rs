@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use russh::keys::PublicKey;
+use tokio::sync::RwLock;
+use tracing::{debug, info, warn};
+
+use crate::ssh::{KeyProvider, KeyProviderError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyFileError {
+    #[error("Could not read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Line {0} does not contain a username and a key")]
+    MissingKey(usize),
+    #[error("Invalid key on line {0}: {1}")]
+    InvalidKey(usize, russh::Error),
+    #[error("Invalid username: {0}")]
+    InvalidUser(String),
+}
+
+/// Parse `authorized_keys` style lines, empty lines and comments are skipped. Options in front of
+/// the key are not supported.
+pub fn parse_authorized_keys(contents: &str) -> Result<Vec<PublicKey>, KeyFileError> {
+    lines(contents)
+        .map(|(number, line)| parse_key(number, line))
+        .collect()
+}
+
+/// Parse lines of `<user> <key>`, a user can have multiple lines
+pub fn parse_key_file(contents: &str) -> Result<HashMap<String, Vec<PublicKey>>, KeyFileError> {
+    let mut keys: HashMap<String, Vec<PublicKey>> = HashMap::new();
+    for (number, line) in lines(contents) {
+        let Some((user, key)) = line.split_once(char::is_whitespace) else {
+            return Err(KeyFileError::MissingKey(number));
+        };
+
+        keys.entry(user.into())
+            .or_default()
+            .push(parse_key(number, key.trim_start())?);
+    }
+
+    Ok(keys)
+}
+
+/// Lines that are not empty or a comment, together with their line number
+fn lines(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+}
+
+fn parse_key(number: usize, key: &str) -> Result<PublicKey, KeyFileError> {
+    PublicKey::from_openssh(key)
+        .map_err(|err| KeyFileError::InvalidKey(number, russh::Error::from(err)))
+}
+
+#[derive(Debug)]
+struct Cache {
+    modified: SystemTime,
+    keys: HashMap<String, Vec<PublicKey>>,
+}
+
+#[derive(Debug, Clone)]
+enum Source {
+    /// Single file with a line per key
+    File(Arc<RwLock<Cache>>),
+    /// A file per user, named after the user
+    Directory,
+}
+
+/// Reads the keys of users from disk instead of LDAP. The path is either a file with lines of
+/// `<user> <key>`, or a directory with a file per user in the `authorized_keys` format. Changes are
+/// picked up on the next login.
+#[derive(Debug, Clone)]
+pub struct KeyFile {
+    path: PathBuf,
+    source: Source,
+}
+
+impl KeyFile {
+    /// Open the file or directory, a file is read right away so mistakes are found at startup
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, KeyFileError> {
+        let path = path.into();
+        let metadata =
+            std::fs::metadata(&path).map_err(|err| KeyFileError::Io(path.clone(), err))?;
+
+        let source = if metadata.is_dir() {
+            Source::Directory
+        } else {
+            let modified = metadata
+                .modified()
+                .map_err(|err| KeyFileError::Io(path.clone(), err))?;
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|err| KeyFileError::Io(path.clone(), err))?;
+            let keys = parse_key_file(&contents)?;
+            info!(path = %path.display(), users = keys.len(), "Loaded key file");
+
+            Source::File(Arc::new(RwLock::new(Cache { modified, keys })))
+        };
+
+        Ok(Self { path, source })
+    }
+
+    /// Read the file again if it changed since it was last read. An invalid file is ignored, so a
+    /// mistake does not lock out every user.
+    async fn refresh(&self, cache: &RwLock<Cache>) -> Result<(), KeyFileError> {
+        let modified = tokio::fs::metadata(&self.path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .map_err(|err| KeyFileError::Io(self.path.clone(), err))?;
+        if cache.read().await.modified == modified {
+            return Ok(());
+        }
+
+        let contents = tokio::fs::read_to_string(&self.path)
+            .await
+            .map_err(|err| KeyFileError::Io(self.path.clone(), err))?;
+        let keys = parse_key_file(&contents)?;
+        info!(path = %self.path.display(), users = keys.len(), "Reloaded key file");
+
+        *cache.write().await = Cache { modified, keys };
+
+        Ok(())
+    }
+
+    pub async fn get_ssh_keys(&self, user: &str) -> Result<Vec<PublicKey>, KeyFileError> {
+        match &self.source {
+            Source::File(cache) => {
+                if let Err(err) = self.refresh(cache).await {
+                    warn!(path = %self.path.display(), "Keeping current keys: {err}");
+                }
+
+                Ok(cache
+                    .read()
+                    .await
+                    .keys
+                    .get(user)
+                    .cloned()
+                    .unwrap_or_default())
+            }
+            Source::Directory => {
+                // The username becomes part of the path, so it should not be able to leave the
+                // directory
+                if user.is_empty() || user.starts_with('.') || user.contains(['/', '\\']) {
+                    return Err(KeyFileError::InvalidUser(user.into()));
+                }
+
+                let path = self.path.join(user);
+                match tokio::fs::read_to_string(&path).await {
+                    Ok(contents) => parse_authorized_keys(&contents),
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        debug!(user, "User has no key file");
+                        Ok(vec![])
+                    }
+                    Err(err) => Err(KeyFileError::Io(path, err)),
+                }
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl KeyProvider for KeyFile {
+    fn get_ssh_keys<'a>(
+        &'a self,
+        user: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<PublicKey>, KeyProviderError>> + Send + 'a>> {
+        Box::pin(async move {
+            KeyFile::get_ssh_keys(self, user)
+                .await
+                .map_err(KeyProviderError::new)
+        })
+    }
+}
@@ -1,16 +1,22 @@
-use ldap3::{LdapConnAsync, SearchEntry};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use ldap3::{LdapConnAsync, LdapConnSettings, SearchEntry};
 use leon::{Template, vals};
 use russh::keys::PublicKey;
 use tokio::select;
+use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
+
+use crate::ssh::{KeyProvider, KeyProviderError};
 
 #[derive(Debug, Clone)]
 pub struct Ldap {
     base: String,
     ldap: ldap3::Ldap,
-    search_filter: String,
+    search_filter: Arc<RwLock<String>>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -27,35 +33,148 @@ pub enum LdapError {
     FailedToParseSearchFilter(#[from] leon::ParseError),
     #[error("Failed to render search filter: {0}")]
     FailedToRenderSearchFilter(#[from] leon::RenderError),
+    #[error("Could not read CA file {0}: {1}")]
+    CouldNotReadCaFile(String, std::io::Error),
+    #[error("TLS error: {0}")]
+    Tls(#[from] native_tls::Error),
+    #[error("Invalid value for {0}: {1}")]
+    InvalidEnvironmentVariable(&'static str, String),
+    #[error("LDAP_TLS_CA is set, but {0} is not encrypted, use ldaps:// or set LDAP_STARTTLS=true")]
+    TlsNotEnabled(String),
+    #[error("LDAP_STARTTLS can not be used with ldaps://")]
+    StartTlsWithLdaps,
+}
+
+fn render_search_filter(search_filter: &str, user: &str) -> Result<String, LdapError> {
+    let search_filter = Template::parse(search_filter)?;
+
+    Ok(search_filter.render(&&vals(|key| {
+        if key == "username" {
+            Some(user.to_string().into())
+        } else {
+            None
+        }
+    }))?)
+}
+
+/// Build the connection settings from `LDAP_STARTTLS` and `LDAP_TLS_CA`, fails if TLS is
+/// configured but the connection would not be encrypted
+fn settings_from_env(address: &str) -> Result<LdapConnSettings, LdapError> {
+    let starttls = std::env::var("LDAP_STARTTLS")
+        .map(|v| {
+            v.parse()
+                .map_err(|_| LdapError::InvalidEnvironmentVariable("LDAP_STARTTLS", v))
+        })
+        .unwrap_or(Ok(false))?;
+    let ldaps = address.starts_with("ldaps://");
+
+    if starttls && ldaps {
+        return Err(LdapError::StartTlsWithLdaps);
+    }
+
+    let mut settings = LdapConnSettings::new().set_starttls(starttls);
+
+    if let Ok(path) = std::env::var("LDAP_TLS_CA") {
+        if !starttls && !ldaps {
+            return Err(LdapError::TlsNotEnabled(address.into()));
+        }
+
+        let pem = std::fs::read(&path).map_err(|err| LdapError::CouldNotReadCaFile(path, err))?;
+        let connector = native_tls::TlsConnector::builder()
+            .add_root_certificate(native_tls::Certificate::from_pem(&pem)?)
+            .build()?;
+        settings = settings.set_connector(connector);
+    }
+
+    if !starttls && !ldaps {
+        warn!("Connection to LDAP is not encrypted, use ldaps:// or set LDAP_STARTTLS=true");
+    }
+
+    Ok(settings)
+}
+
+/// Everything needed to connect to LDAP
+pub struct LdapConfig {
+    address: String,
+    base: String,
+    bind_dn: String,
+    search_filter: String,
+    password: String,
+    settings: LdapConnSettings,
+}
+
+fn required_env(name: &'static str) -> Result<String, LdapError> {
+    std::env::var(name).map_err(|_| LdapError::MissingEnvironmentVariable(name))
+}
+
+impl LdapConfig {
+    /// Read the configuration from the environment, all problems are reported instead of only
+    /// the first one
+    pub fn from_env() -> Result<Self, Vec<LdapError>> {
+        let mut errors = Vec::new();
+
+        let address = required_env("LDAP_ADDRESS").map_err(|err| errors.push(err));
+        let base = required_env("LDAP_BASE").map_err(|err| errors.push(err));
+        let bind_dn = required_env("LDAP_BIND_DN").map_err(|err| errors.push(err));
+        let search_filter = required_env("LDAP_SEARCH_FILTER")
+            .and_then(|search_filter| {
+                render_search_filter(&search_filter, "username")?;
+                Ok(search_filter)
+            })
+            .map_err(|err| errors.push(err));
+
+        let password = std::env::var("LDAP_PASSWORD_FILE")
+            .map_or_else(
+                |_| {
+                    std::env::var("LDAP_PASSWORD").map_err(|_| {
+                        LdapError::MissingEnvironmentVariable("LDAP_PASSWORD or LDAP_PASSWORD_FILE")
+                    })
+                },
+                |path| {
+                    std::fs::read_to_string(path)
+                        .map(|v| v.trim().into())
+                        .map_err(|err| err.into())
+                },
+            )
+            .map_err(|err| errors.push(err));
+
+        let settings = address
+            .as_ref()
+            .map_err(|_| ())
+            .and_then(|address| settings_from_env(address).map_err(|err| errors.push(err)));
+
+        match (address, base, bind_dn, search_filter, password, settings) {
+            (Ok(address), Ok(base), Ok(bind_dn), Ok(search_filter), Ok(password), Ok(settings)) => {
+                Ok(Self {
+                    address,
+                    base,
+                    bind_dn,
+                    search_filter,
+                    password,
+                    settings,
+                })
+            }
+            _ => Err(errors),
+        }
+    }
 }
 
 impl Ldap {
-    pub async fn start_from_env(
+    pub async fn start(
+        config: LdapConfig,
         token: CancellationToken,
     ) -> Result<(Ldap, JoinHandle<()>), LdapError> {
-        let address = std::env::var("LDAP_ADDRESS")
-            .map_err(|_| LdapError::MissingEnvironmentVariable("LDAP_ADDRESS"))?;
-        let base = std::env::var("LDAP_BASE")
-            .map_err(|_| LdapError::MissingEnvironmentVariable("LDAP_BASE"))?;
-        let bind_dn = std::env::var("LDAP_BIND_DN")
-            .map_err(|_| LdapError::MissingEnvironmentVariable("LDAP_BIND_DN"))?;
-        let search_filter = std::env::var("LDAP_SEARCH_FILTER")
-            .map_err(|_| LdapError::MissingEnvironmentVariable("LDAP_SEARCH_FILTER"))?;
-
-        let password = std::env::var("LDAP_PASSWORD_FILE").map_or_else(
-            |_| {
-                std::env::var("LDAP_PASSWORD").map_err(|_| {
-                    LdapError::MissingEnvironmentVariable("LDAP_PASSWORD or LDAP_PASSWORD_FILE")
-                })
-            },
-            |path| {
-                std::fs::read_to_string(path)
-                    .map(|v| v.trim().into())
-                    .map_err(|err| err.into())
-            },
-        )?;
+        let LdapConfig {
+            address,
+            base,
+            bind_dn,
+            search_filter,
+            password,
+            settings,
+        } = config;
 
-        let (conn, mut ldap) = LdapConnAsync::new(&address).await?;
+        // Fails if the server does not support StartTLS or the certificate can not be verified
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &address).await?;
         let handle = tokio::spawn(async move {
             select! {
                 res = conn.drive() => {
@@ -78,30 +197,34 @@ impl Ldap {
             Self {
                 base,
                 ldap,
-                search_filter,
+                search_filter: Arc::new(RwLock::new(search_filter)),
             },
             handle,
         ))
     }
 
-    pub async fn get_ssh_keys(
-        &mut self,
-        user: impl AsRef<str>,
-    ) -> Result<Vec<PublicKey>, LdapError> {
-        let search_filter = Template::parse(&self.search_filter)?;
-
-        let search_filter = search_filter.render(&&vals(|key| {
-            if key == "username" {
-                Some(user.as_ref().to_string().into())
-            } else {
-                None
-            }
-        }))?;
+    /// Replace the search filter, the new filter is rejected if it can not be rendered. Returns
+    /// true if the filter changed.
+    pub async fn set_search_filter(&self, search_filter: String) -> Result<bool, LdapError> {
+        render_search_filter(&search_filter, "username")?;
+
+        let mut current = self.search_filter.write().await;
+        if *current == search_filter {
+            return Ok(false);
+        }
+
+        *current = search_filter;
+        Ok(true)
+    }
+
+    pub async fn get_ssh_keys(&self, user: impl AsRef<str>) -> Result<Vec<PublicKey>, LdapError> {
+        let search_filter = render_search_filter(&self.search_filter.read().await, user.as_ref())?;
 
         debug!("search_filter = {search_filter}");
 
-        Ok(self
-            .ldap
+        // Clones share the same connection
+        let mut ldap = self.ldap.clone();
+        Ok(ldap
             .search(
                 &self.base,
                 ldap3::Scope::Subtree,
@@ -124,3 +247,16 @@ impl Ldap {
             .map_err(russh::Error::from)?)
     }
 }
+
+impl KeyProvider for Ldap {
+    fn get_ssh_keys<'a>(
+        &'a self,
+        user: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<PublicKey>, KeyProviderError>> + Send + 'a>> {
+        Box::pin(async move {
+            Ldap::get_ssh_keys(self, user)
+                .await
+                .map_err(KeyProviderError::new)
+        })
+    }
+}
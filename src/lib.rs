@@ -3,10 +3,12 @@
 #![feature(future_join)]
 mod helper;
 mod io;
+pub mod key_file;
 pub mod ldap;
 pub mod ssh;
 pub mod tunnel;
 mod version;
 pub mod web;
 
-pub use version::VERSION;
+pub use helper::Unit;
+pub use version::{BUILD_TIME, GIT_HASH, VERSION};
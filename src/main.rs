@@ -1,21 +1,32 @@
 #![feature(future_join)]
 use std::future::join;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
+use std::str::FromStr;
 use std::time::Duration;
 
 use axum::routing::get;
 use axum::{Json, Router};
-use color_eyre::eyre::Context;
+use clap::Parser;
+use color_eyre::eyre::{Context, eyre};
 use dotenvy::dotenv;
+use hyper::StatusCode;
+use hyper::header::HeaderName;
 use rand::rngs::OsRng;
+use russh::keys::{Algorithm, PrivateKey, PublicKey};
 use siranga::VERSION;
-use siranga::ldap::Ldap;
-use siranga::ssh::Server;
-use siranga::tunnel::Registry;
-use siranga::web::{ForwardAuth, Service};
-use tokio::net::TcpListener;
+use siranga::key_file::KeyFile;
+use siranga::ldap::{Ldap, LdapConfig};
+use siranga::ssh::{KeyPolicy, Server, SessionConfig};
+use siranga::tunnel::{Registry, RetryPolicy, Webhook, WordList, parse_ip_net};
+use siranga::web::{
+    AdminToken, AuthProviders, BodyLimits, ConnectionLimit, ForwardAuth, HeaderLimits, LandingPage,
+    Maintenance, OfflinePage, ReverseDns, SensitiveHeaders, Service, ServiceConfig, TrustedProxies,
+    load_tls_from_env,
+};
+use tokio::net::{TcpListener, TcpSocket};
 use tokio::select;
+use tokio_rustls::TlsAcceptor;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
@@ -56,26 +67,1032 @@ async fn shutdown_task(token: CancellationToken) {
     }
 }
 
+/// Reload the settings that can be changed without restarting, invalid values are rejected
+async fn reload(ldap: Option<&Ldap>, auth: &AuthProviders) {
+    // Pick up changes made to the .env file
+    dotenvy::dotenv_override().ok();
+
+    if let Some(ldap) = ldap {
+        match std::env::var("LDAP_SEARCH_FILTER") {
+            Ok(search_filter) => match ldap.set_search_filter(search_filter.clone()).await {
+                Ok(true) => info!(search_filter, "Updated LDAP search filter"),
+                Ok(false) => debug!("LDAP search filter is unchanged"),
+                Err(err) => error!("Keeping current LDAP search filter: {err}"),
+            },
+            Err(_) => error!("LDAP_SEARCH_FILTER is not set, keeping current LDAP search filter"),
+        }
+    }
+
+    reload_auth_endpoint("AUTHZ_ENDPOINT", auth.default_provider()).await;
+    for (name, provider) in auth.named() {
+        let var = format!("{}_ENDPOINT", AuthzConfig::env_prefix(name));
+        reload_auth_endpoint(&var, provider).await;
+    }
+}
+
+async fn reload_auth_endpoint(var: &str, auth: &ForwardAuth) {
+    match std::env::var(var) {
+        Ok(address) => match auth.set_address(address.clone()).await {
+            Ok(true) => info!(address, "Updated auth endpoint"),
+            Ok(false) => debug!(var, "Auth endpoint is unchanged"),
+            Err(err) => error!("Keeping current auth endpoint: {err}"),
+        },
+        Err(_) => error!("{var} is not set, keeping current auth endpoint"),
+    }
+}
+
+#[cfg(unix)]
+async fn reload_task(ldap: Option<Ldap>, auth: AuthProviders) {
+    use tokio::signal::unix::SignalKind;
+
+    let mut sighup =
+        tokio::signal::unix::signal(SignalKind::hangup()).expect("should be able to initialize");
+    while sighup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading configuration");
+        reload(ldap.as_ref(), &auth).await;
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_task(_ldap: Option<Ldap>, _auth: AuthProviders) {
+    std::future::pending::<()>().await;
+}
+
+#[cfg(unix)]
+async fn maintenance_task(maintenance: Maintenance) {
+    use tokio::signal::unix::SignalKind;
+
+    let mut sigusr1 = tokio::signal::unix::signal(SignalKind::user_defined1())
+        .expect("should be able to initialize");
+    while sigusr1.recv().await.is_some() {
+        if maintenance.toggle() {
+            info!("Received SIGUSR1, entering maintenance mode");
+        } else {
+            info!("Received SIGUSR1, leaving maintenance mode");
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn maintenance_task(_maintenance: Maintenance) {
+    std::future::pending::<()>().await;
+}
+
+#[cfg(unix)]
+async fn revoke_task(registry: Registry) {
+    use tokio::signal::unix::SignalKind;
+
+    let mut sigusr2 = tokio::signal::unix::signal(SignalKind::user_defined2())
+        .expect("should be able to initialize");
+    while sigusr2.recv().await.is_some() {
+        let revoked = registry.revoke_public_access().await;
+        warn!(
+            revoked,
+            "Received SIGUSR2, revoked public access of all tunnels"
+        );
+    }
+}
+
+#[cfg(not(unix))]
+async fn revoke_task(_registry: Registry) {
+    std::future::pending::<()>().await;
+}
+
 async fn axum_graceful_shutdown(token: CancellationToken) {
     token.cancelled().await;
 }
 
+/// Read a duration in seconds from the environment, results in `Some(None)` if it is set to zero
+fn optional_duration_from_env(name: &str) -> color_eyre::Result<Option<Option<Duration>>> {
+    std::env::var(name)
+        .ok()
+        .map(|secs| {
+            secs.parse()
+                .map(|secs| (secs != 0).then(|| Duration::from_secs(secs)))
+                .wrap_err_with(|| format!("{name}={secs}"))
+        })
+        .transpose()
+}
+
+/// Listen on the address with the given backlog, `TcpListener::bind` always uses a backlog of 1024
+fn bind_listener(addr: SocketAddr, backlog: u32) -> std::io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    // Same as `TcpListener::bind`, otherwise restarting fails while old connections linger
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(backlog)
+}
+
+/// Address to listen on, the environment variable can contain either an ip address or a full socket
+/// address
+fn bind_address_from_env(name: &str, port: u16) -> color_eyre::Result<SocketAddr> {
+    let Ok(bind) = std::env::var(name) else {
+        return Ok(SocketAddr::from(([0, 0, 0, 0], port)));
+    };
+
+    if let Ok(addr) = bind.parse() {
+        return Ok(addr);
+    }
+
+    bind.parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, port))
+        .wrap_err_with(|| format!("{name}={bind}"))
+}
+
+/// Expose ports forwarded over ssh as http tunnels
+#[derive(Debug, Parser)]
+#[command(version = VERSION, about, long_about = None)]
+struct Cli {
+    /// Check if the configuration is valid and all services are reachable, then exit
+    #[arg(long)]
+    check_config: bool,
+}
+
+/// Read the host keys from a comma separated list of files and directories, every file in a
+/// directory, except for public keys, is read as a key
+fn read_host_keys(paths: &str) -> color_eyre::Result<Vec<PrivateKey>> {
+    let mut files = Vec::new();
+    for path in paths
+        .split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+    {
+        let path = Path::new(path);
+        if path.is_dir() {
+            let mut entries = std::fs::read_dir(path)
+                .wrap_err_with(|| format!("failed to read ssh key directory: {}", path.display()))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+                .wrap_err_with(|| {
+                    format!("failed to read ssh key directory: {}", path.display())
+                })?;
+            entries.retain(|entry| {
+                entry.is_file() && entry.extension().is_none_or(|extension| extension != "pub")
+            });
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.to_owned());
+        }
+    }
+
+    if files.is_empty() {
+        color_eyre::eyre::bail!("no ssh keys found in PRIVATE_KEY_FILE={paths}");
+    }
+
+    files
+        .into_iter()
+        .map(|path| {
+            PrivateKey::read_openssh_file(&path)
+                .wrap_err_with(|| format!("failed to read ssh key: {}", path.display()))
+        })
+        .collect()
+}
+
+/// Where the keys that users log in with come from
+enum KeySource {
+    Ldap(LdapConfig),
+    File(KeyFile),
+}
+
+/// Configuration read from the environment, shared between normal startup and `--check-config`
+struct Config {
+    keys: Vec<PrivateKey>,
+    ssh_addr: SocketAddr,
+    http_addr: SocketAddr,
+    http_backlog: u32,
+    metrics_addr: SocketAddr,
+    domain: String,
+    authz: AuthzConfig,
+    // Named providers that tunnels can pick instead of the default provider
+    authz_providers: Vec<(String, AuthzConfig)>,
+    names: WordList,
+    webhook: Option<Webhook>,
+    namespace_by_user: bool,
+    generated_name_addresses: Option<Vec<String>>,
+    key_source: KeySource,
+    session_config: SessionConfig,
+    service_config: ServiceConfig,
+    offline_grace: Option<Option<Duration>>,
+    idle_reap: Option<Duration>,
+    idle_reap_interval: Duration,
+    max_lifetime: Option<Duration>,
+    tls: Option<TlsAcceptor>,
+}
+
+/// Exit code when the configuration is invalid, `EX_CONFIG` from sysexits.h
+const EXIT_CONFIG: i32 = 78;
+
+/// Collects all problems with the configuration, so they can be fixed in one go instead of one
+/// restart at a time
+#[derive(Default)]
+struct ConfigErrors(Vec<color_eyre::Report>);
+
+impl ConfigErrors {
+    /// Remember the error and continue, the value is `None` if there was an error
+    fn check<T>(&mut self, result: color_eyre::Result<T>) -> Option<T> {
+        result.map_err(|err| self.0.push(err)).ok()
+    }
+
+    fn report(&self) {
+        for err in &self.0 {
+            error!("Invalid configuration: {err:#}");
+        }
+        error!(
+            errors = self.0.len(),
+            "Configuration is invalid, fix the errors above and restart"
+        );
+    }
+}
+
+/// Settings of an auth endpoint
+#[derive(Debug, Clone)]
+struct AuthzConfig {
+    address: String,
+    timeout: Duration,
+    redirect_param: Option<String>,
+    user_header: HeaderName,
+}
+
+impl Default for AuthzConfig {
+    fn default() -> Self {
+        Self {
+            address: String::new(),
+            timeout: ForwardAuth::DEFAULT_TIMEOUT,
+            redirect_param: Some(ForwardAuth::DEFAULT_REDIRECT_PARAM.into()),
+            user_header: ForwardAuth::DEFAULT_USER_HEADER,
+        }
+    }
+}
+
+impl AuthzConfig {
+    /// Prefix of the variables of a named provider, e.g. `AUTHZ_PARTNER_ENDPOINT`
+    fn env_prefix(name: &str) -> String {
+        format!("AUTHZ_{}", name.to_ascii_uppercase().replace('-', "_"))
+    }
+
+    /// Read `<prefix>_ENDPOINT` and the optional settings, which fall back to `defaults`
+    fn from_env(errors: &mut ConfigErrors, prefix: &str, defaults: &Self) -> Self {
+        let address = errors
+            .check(
+                url_from_env(&format!("{prefix}_ENDPOINT"))
+                    .unwrap_or_else(|| Err(eyre!("{prefix}_ENDPOINT is not set"))),
+            )
+            .unwrap_or_default();
+        let timeout = errors
+            .check(
+                std::env::var(format!("{prefix}_TIMEOUT"))
+                    .map(|secs| {
+                        secs.parse()
+                            .map(Duration::from_secs)
+                            .wrap_err_with(|| format!("{prefix}_TIMEOUT={secs}"))
+                    })
+                    .unwrap_or(Ok(defaults.timeout)),
+            )
+            .unwrap_or_default();
+        let redirect_param = match std::env::var(format!("{prefix}_REDIRECT_PARAM")) {
+            Ok(param) => (!param.is_empty()).then_some(param),
+            Err(_) => defaults.redirect_param.clone(),
+        };
+        let user_header = errors
+            .check(
+                std::env::var(format!("{prefix}_USER_HEADER"))
+                    .map(|header| {
+                        HeaderName::from_str(&header)
+                            .wrap_err_with(|| format!("{prefix}_USER_HEADER={header}"))
+                    })
+                    .unwrap_or(Ok(defaults.user_header.clone())),
+            )
+            .unwrap_or(ForwardAuth::DEFAULT_USER_HEADER);
+
+        Self {
+            address,
+            timeout,
+            redirect_param,
+            user_header,
+        }
+    }
+
+    fn build(&self) -> ForwardAuth {
+        ForwardAuth::new(
+            &self.address,
+            self.timeout,
+            self.redirect_param.clone(),
+            self.user_header.clone(),
+        )
+    }
+}
+
+/// Check that the variable contains an absolute url
+fn url_from_env(name: &str) -> Option<color_eyre::Result<String>> {
+    let url = std::env::var(name).ok()?;
+
+    Some(
+        reqwest::Url::parse(&url)
+            .map(|_| url.clone())
+            .wrap_err_with(|| format!("{name}={url}")),
+    )
+}
+
+impl Config {
+    fn from_env() -> Result<Self, ConfigErrors> {
+        let mut errors = ConfigErrors::default();
+
+        let keys = if let Ok(paths) = std::env::var("PRIVATE_KEY_FILE") {
+            errors.check(read_host_keys(&paths)).unwrap_or_default()
+        } else {
+            warn!("No private key file specified, generating a new key");
+            errors
+                .check(
+                    russh::keys::PrivateKey::random(&mut OsRng, russh::keys::Algorithm::Ed25519)
+                        .map_err(Into::into),
+                )
+                .into_iter()
+                .collect()
+        };
+
+        let ssh_port = errors
+            .check(
+                std::env::var("SSH_PORT")
+                    .map(|port| port.parse().wrap_err_with(|| format!("SSH_PORT={port}")))
+                    .unwrap_or(Ok(2222)),
+            )
+            .unwrap_or_default();
+        let http_port = errors
+            .check(
+                std::env::var("HTTP_PORT")
+                    .map(|port| port.parse().wrap_err_with(|| format!("HTTP_PORT={port}")))
+                    .unwrap_or(Ok(3000)),
+            )
+            .unwrap_or_default();
+        let metrics_port = errors
+            .check(
+                std::env::var("METRICS_PORT")
+                    .map(|port| {
+                        port.parse()
+                            .wrap_err_with(|| format!("METRICS_PORT={port}"))
+                    })
+                    .unwrap_or(Ok(4000)),
+            )
+            .unwrap_or_default();
+
+        let http_backlog = errors
+            .check(
+                std::env::var("HTTP_LISTEN_BACKLOG")
+                    .map(|backlog| {
+                        backlog
+                            .parse()
+                            .wrap_err_with(|| format!("HTTP_LISTEN_BACKLOG={backlog}"))
+                    })
+                    .unwrap_or(Ok(1024)),
+            )
+            .unwrap_or_default();
+
+        let domain =
+            std::env::var("TUNNEL_DOMAIN").unwrap_or_else(|_| format!("localhost:{http_port}"));
+        let authz = AuthzConfig::from_env(&mut errors, "AUTHZ", &AuthzConfig::default());
+        let mut authz_providers: Vec<(String, AuthzConfig)> = Vec::new();
+        if let Ok(names) = std::env::var("AUTHZ_PROVIDERS") {
+            for name in names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+            {
+                if !name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                {
+                    errors.0.push(eyre!(
+                        "AUTHZ_PROVIDERS={names}: '{name}' can only contain lowercase letters, digits, and dashes"
+                    ));
+                    continue;
+                }
+                if authz_providers.iter().any(|(existing, _)| existing == name) {
+                    errors
+                        .0
+                        .push(eyre!("AUTHZ_PROVIDERS={names}: '{name}' is listed twice"));
+                    continue;
+                }
+
+                let provider =
+                    AuthzConfig::from_env(&mut errors, &AuthzConfig::env_prefix(name), &authz);
+                authz_providers.push((name.to_owned(), provider));
+            }
+        }
+
+        let names = if let Ok(path) = std::env::var("TUNNEL_NAME_WORDLIST") {
+            errors.check(
+                WordList::from_file(&path)
+                    .wrap_err_with(|| format!("failed to read word list: {path}")),
+            )
+        } else {
+            None
+        }
+        .unwrap_or_else(WordList::animals);
+
+        let webhook_url = url_from_env("WEBHOOK_URL").and_then(|url| errors.check(url));
+
+        let key_source = match std::env::var("KEY_PROVIDER").as_deref() {
+            Ok("ldap") | Err(_) => LdapConfig::from_env()
+                .map(KeySource::Ldap)
+                .map_err(|ldap_errors| {
+                    errors
+                        .0
+                        .extend(ldap_errors.into_iter().map(color_eyre::Report::from))
+                })
+                .ok(),
+            Ok("file") => errors
+                .check(
+                    std::env::var("KEY_FILE")
+                        .map_err(|_| eyre!("KEY_FILE is not set"))
+                        .and_then(|path| {
+                            KeyFile::open(&path).wrap_err_with(|| format!("KEY_FILE={path}"))
+                        }),
+                )
+                .map(KeySource::File),
+            Ok(provider) => {
+                errors
+                    .0
+                    .push(eyre!("KEY_PROVIDER={provider}: expected 'ldap' or 'file'"));
+                None
+            }
+        };
+
+        let mut session_config = SessionConfig::default();
+        session_config.auth_providers = authz_providers
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        if let Some(timeout) = errors
+            .check(optional_duration_from_env("SSH_INACTIVITY_TIMEOUT"))
+            .flatten()
+        {
+            session_config.inactivity_timeout = timeout;
+        }
+        if let Ok(secs) = std::env::var("SSH_AUTH_REJECTION_TIME")
+            && let Some(secs) = errors.check(
+                secs.parse()
+                    .wrap_err_with(|| format!("SSH_AUTH_REJECTION_TIME={secs}")),
+            )
+        {
+            session_config.auth_rejection_time = Duration::from_secs(secs);
+        }
+        if let Some(interval) = errors
+            .check(optional_duration_from_env("SSH_KEEPALIVE_INTERVAL"))
+            .flatten()
+        {
+            session_config.keepalive_interval = interval;
+        }
+        if let Ok(max) = std::env::var("SSH_KEEPALIVE_MAX")
+            && let Some(max) = errors.check(
+                max.parse()
+                    .wrap_err_with(|| format!("SSH_KEEPALIVE_MAX={max}")),
+            )
+        {
+            session_config.keepalive_max = max;
+        }
+        if let Ok(retries) = std::env::var("TUNNEL_OPEN_RETRIES")
+            && let Some(retries) = errors.check(
+                retries
+                    .parse()
+                    .wrap_err_with(|| format!("TUNNEL_OPEN_RETRIES={retries}")),
+            )
+        {
+            let delay = session_config.open_retry.base_delay();
+            session_config.open_retry = RetryPolicy::new(retries, delay);
+        }
+        if let Ok(delay) = std::env::var("TUNNEL_OPEN_RETRY_DELAY")
+            && let Some(delay) = errors.check(
+                delay
+                    .parse()
+                    .wrap_err_with(|| format!("TUNNEL_OPEN_RETRY_DELAY={delay}")),
+            )
+        {
+            let retries = session_config.open_retry.retries();
+            session_config.open_retry = RetryPolicy::new(retries, Duration::from_millis(delay));
+        }
+        if let Ok(ports) = std::env::var("ALLOWED_PORTS")
+            && let Some(ports) = errors.check(
+                ports
+                    .parse()
+                    .map_err(|err| eyre!("ALLOWED_PORTS={ports}: {err}")),
+            )
+        {
+            session_config.allowed_ports = ports;
+        }
+        let key_algorithms = errors
+            .check(
+                std::env::var("SSH_KEY_ALGORITHMS")
+                    .map(|algorithms| {
+                        algorithms
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|algorithm| !algorithm.is_empty())
+                            .map(Algorithm::from_str)
+                            .collect::<Result<_, _>>()
+                            .wrap_err_with(|| format!("SSH_KEY_ALGORITHMS={algorithms}"))
+                    })
+                    .unwrap_or_else(|_| Ok(KeyPolicy::default_algorithms())),
+            )
+            .unwrap_or_default();
+        let min_rsa_bits = errors
+            .check(
+                std::env::var("SSH_MIN_RSA_BITS")
+                    .map(|bits| {
+                        bits.parse()
+                            .wrap_err_with(|| format!("SSH_MIN_RSA_BITS={bits}"))
+                    })
+                    .unwrap_or(Ok(KeyPolicy::DEFAULT_MIN_RSA_BITS)),
+            )
+            .unwrap_or_default();
+        session_config.key_policy = KeyPolicy::new(key_algorithms, min_rsa_bits);
+        let banner = match std::env::var("SSH_BANNER_FILE") {
+            Ok(path) => errors.check(
+                std::fs::read_to_string(&path).wrap_err_with(|| format!("SSH_BANNER_FILE={path}")),
+            ),
+            Err(_) => std::env::var("SSH_BANNER").ok(),
+        };
+        session_config.banner = banner.filter(|banner| !banner.trim().is_empty());
+        if let Ok(spacing) = std::env::var("TUI_COLUMN_SPACING")
+            && let Some(spacing) = errors.check(
+                spacing
+                    .parse()
+                    .wrap_err_with(|| format!("TUI_COLUMN_SPACING={spacing}")),
+            )
+        {
+            session_config.table_style.column_spacing = spacing;
+        }
+        if let Ok(symbol) = std::env::var("TUI_HIGHLIGHT_SYMBOL") {
+            if symbol.chars().any(char::is_control) {
+                errors.0.push(eyre!(
+                    "TUI_HIGHLIGHT_SYMBOL={symbol:?}: can not contain control characters"
+                ));
+            } else {
+                session_config.table_style.highlight_symbol = symbol;
+            }
+        }
+        if let Ok(charset) = std::env::var("TUNNEL_NAME_CHARSET")
+            && let Some(charset) = errors.check(
+                charset
+                    .parse()
+                    .map_err(|err| eyre!("TUNNEL_NAME_CHARSET={charset}: {err}")),
+            )
+        {
+            session_config.name_charset = charset;
+        }
+        if let Ok(policy) = std::env::var("TUNNEL_NAME_POLICY")
+            && let Some(policy) = errors.check(
+                policy
+                    .parse()
+                    .map_err(|err| eyre!("TUNNEL_NAME_POLICY={policy}: {err}")),
+            )
+        {
+            session_config.name_policy = policy;
+        }
+        if let Ok(ca_key) = std::env::var("SSH_CA_KEY") {
+            session_config.ca_key = errors.check(
+                PublicKey::from_openssh(&ca_key).wrap_err_with(|| format!("SSH_CA_KEY={ca_key}")),
+            );
+        }
+        if let SessionConfig {
+            inactivity_timeout: Some(timeout),
+            keepalive_interval: Some(interval),
+            ..
+        } = session_config
+            && interval >= timeout
+        {
+            warn!(
+                ?interval,
+                ?timeout,
+                "SSH keepalive interval is not shorter than the inactivity timeout, idle sessions will be closed before a keepalive is sent"
+            );
+        }
+
+        let mut body_limits = BodyLimits::default();
+        if let Ok(max_size) = std::env::var("MAX_BODY_SIZE")
+            && let Some(max_size) = errors.check(
+                max_size
+                    .parse()
+                    .wrap_err_with(|| format!("MAX_BODY_SIZE={max_size}")),
+            )
+        {
+            body_limits = BodyLimits::new(max_size, body_limits.timeout());
+        }
+        if let Ok(timeout) = std::env::var("BODY_TIMEOUT")
+            && let Some(timeout) = errors.check(
+                timeout
+                    .parse()
+                    .wrap_err_with(|| format!("BODY_TIMEOUT={timeout}")),
+            )
+        {
+            body_limits = BodyLimits::new(body_limits.max_size(), Duration::from_secs(timeout));
+        }
+
+        let mut header_limits = HeaderLimits::default();
+        if let Ok(max_size) = std::env::var("MAX_HEADER_SIZE")
+            && let Some(max_size) = errors.check(
+                max_size
+                    .parse()
+                    .wrap_err_with(|| format!("MAX_HEADER_SIZE={max_size}")),
+            )
+        {
+            header_limits =
+                HeaderLimits::new(max_size, header_limits.max_count(), header_limits.timeout());
+        }
+        if let Ok(max_count) = std::env::var("MAX_HEADERS")
+            && let Some(max_count) = errors.check(
+                max_count
+                    .parse()
+                    .wrap_err_with(|| format!("MAX_HEADERS={max_count}")),
+            )
+        {
+            header_limits =
+                HeaderLimits::new(header_limits.max_size(), max_count, header_limits.timeout());
+        }
+        if let Ok(timeout) = std::env::var("HTTP_HEADER_TIMEOUT")
+            && let Some(timeout) = errors.check(
+                timeout
+                    .parse()
+                    .wrap_err_with(|| format!("HTTP_HEADER_TIMEOUT={timeout}")),
+            )
+        {
+            header_limits = HeaderLimits::new(
+                header_limits.max_size(),
+                header_limits.max_count(),
+                Duration::from_secs(timeout),
+            );
+        }
+
+        let reverse_dns = errors
+            .check(
+                std::env::var("ACCESS_LOG_REVERSE_DNS")
+                    .map(|enabled| {
+                        enabled
+                            .parse::<bool>()
+                            .wrap_err_with(|| format!("ACCESS_LOG_REVERSE_DNS={enabled}"))
+                    })
+                    .unwrap_or(Ok(false)),
+            )
+            .unwrap_or_default()
+            .then(ReverseDns::default);
+
+        let namespace_by_user = errors
+            .check(
+                std::env::var("NAMESPACE_BY_USER")
+                    .map(|enabled| {
+                        enabled
+                            .parse()
+                            .wrap_err_with(|| format!("NAMESPACE_BY_USER={enabled}"))
+                    })
+                    .unwrap_or(Ok(false)),
+            )
+            .unwrap_or_default();
+
+        // Replaces the defaults of the registry
+        let generated_name_addresses =
+            std::env::var("GENERATED_NAME_ADDRESSES")
+                .ok()
+                .map(|addresses| {
+                    addresses
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|address| !address.is_empty())
+                        .map(Into::into)
+                        .collect()
+                });
+
+        let compression = errors
+            .check(
+                std::env::var("COMPRESSION")
+                    .map(|enabled| {
+                        enabled
+                            .parse()
+                            .wrap_err_with(|| format!("COMPRESSION={enabled}"))
+                    })
+                    .unwrap_or(Ok(true)),
+            )
+            .unwrap_or_default();
+
+        let offline_grace = errors
+            .check(optional_duration_from_env("TUNNEL_OFFLINE_GRACE"))
+            .flatten();
+
+        let idle_reap = errors
+            .check(optional_duration_from_env("TUNNEL_IDLE_REAP"))
+            .flatten()
+            .flatten();
+        let idle_reap_interval = errors
+            .check(
+                std::env::var("TUNNEL_IDLE_REAP_INTERVAL")
+                    .map(|secs| {
+                        secs.parse()
+                            .map(Duration::from_secs)
+                            .wrap_err_with(|| format!("TUNNEL_IDLE_REAP_INTERVAL={secs}"))
+                    })
+                    .unwrap_or(Ok(Duration::from_secs(60))),
+            )
+            .unwrap_or_default();
+        if idle_reap_interval.is_zero() {
+            errors.0.push(eyre!(
+                "TUNNEL_IDLE_REAP_INTERVAL has to be at least 1 second"
+            ));
+        }
+        let max_lifetime = errors
+            .check(optional_duration_from_env("TUNNEL_MAX_LIFETIME"))
+            .flatten()
+            .flatten();
+        let offline_status = errors
+            .check(
+                std::env::var("TUNNEL_OFFLINE_STATUS")
+                    .map(|status| {
+                        StatusCode::from_bytes(status.as_bytes())
+                            .wrap_err_with(|| format!("TUNNEL_OFFLINE_STATUS={status}"))
+                    })
+                    .unwrap_or(Ok(OfflinePage::DEFAULT_STATUS)),
+            )
+            .unwrap_or_default();
+        let offline_html = std::env::var("TUNNEL_OFFLINE_PAGE_FILE")
+            .ok()
+            .and_then(|path| {
+                errors.check(
+                    std::fs::read_to_string(&path)
+                        .wrap_err_with(|| format!("TUNNEL_OFFLINE_PAGE_FILE={path}")),
+                )
+            });
+        let offline_page = OfflinePage::new(offline_status, offline_html);
+
+        let landing_page = match std::env::var("LANDING_PAGE_FILE") {
+            Ok(path) => LandingPage::new(
+                errors
+                    .check(
+                        std::fs::read_to_string(&path)
+                            .wrap_err_with(|| format!("LANDING_PAGE_FILE={path}")),
+                    )
+                    .unwrap_or_default(),
+            ),
+            Err(_) => LandingPage::builtin(&domain, ssh_port),
+        };
+
+        let admin_token = match std::env::var("ADMIN_TOKEN") {
+            Ok(token) if token.trim().is_empty() => {
+                errors.0.push(eyre!("ADMIN_TOKEN can not be empty"));
+                None
+            }
+            Ok(token) => Some(AdminToken::new(token.trim())),
+            Err(_) => None,
+        };
+
+        let upstream_timeout = errors
+            .check(optional_duration_from_env("UPSTREAM_TIMEOUT"))
+            .flatten()
+            .unwrap_or(Some(ServiceConfig::DEFAULT_UPSTREAM_TIMEOUT));
+
+        let trusted_proxies = errors
+            .check(
+                std::env::var("TRUSTED_PROXIES")
+                    .map(|proxies| {
+                        proxies
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|proxy| !proxy.is_empty())
+                            .map(parse_ip_net)
+                            .collect::<Result<Vec<_>, _>>()
+                            .map(TrustedProxies::new)
+                            .map_err(|err| eyre!("TRUSTED_PROXIES={proxies}: {err}"))
+                    })
+                    .unwrap_or(Ok(TrustedProxies::default())),
+            )
+            .unwrap_or_default();
+        let sensitive_headers = errors
+            .check(
+                std::env::var("SENSITIVE_HEADERS")
+                    .map(|headers| {
+                        headers
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|header| !header.is_empty())
+                            .map(HeaderName::from_str)
+                            .collect::<Result<Vec<_>, _>>()
+                            .map(SensitiveHeaders::new)
+                            .wrap_err_with(|| format!("SENSITIVE_HEADERS={headers}"))
+                    })
+                    .unwrap_or(Ok(SensitiveHeaders::default())),
+            )
+            .unwrap_or_default();
+        let connection_limit = match std::env::var("MAX_CONNECTIONS_PER_IP") {
+            Ok(max) => errors
+                .check(
+                    max.parse::<usize>()
+                        .wrap_err_with(|| format!("MAX_CONNECTIONS_PER_IP={max}")),
+                )
+                .filter(|&max| max != 0)
+                .map(|max| ConnectionLimit::new(max, trusted_proxies.clone())),
+            Err(_) => None,
+        };
+
+        let tls = errors
+            .check(load_tls_from_env().map_err(Into::into))
+            .flatten();
+
+        let default_addr = SocketAddr::from(([0, 0, 0, 0], 0));
+        let ssh_addr = errors
+            .check(bind_address_from_env("SSH_BIND", ssh_port))
+            .unwrap_or(default_addr);
+        let http_addr = errors
+            .check(bind_address_from_env("HTTP_BIND", http_port))
+            .unwrap_or(default_addr);
+        let metrics_addr = errors
+            .check(bind_address_from_env("METRICS_BIND", metrics_port))
+            .unwrap_or(default_addr);
+
+        // Only set when the key provider is configured correctly, so also an error in that case
+        let (true, Some(key_source)) = (errors.0.is_empty(), key_source) else {
+            return Err(errors);
+        };
+
+        Ok(Self {
+            keys,
+            ssh_addr,
+            http_addr,
+            http_backlog,
+            metrics_addr,
+            domain,
+            authz,
+            authz_providers,
+            names,
+            webhook: webhook_url.map(Webhook::new),
+            namespace_by_user,
+            generated_name_addresses,
+            key_source,
+            session_config,
+            service_config: ServiceConfig {
+                body_limits,
+                header_limits,
+                reverse_dns,
+                compression,
+                offline_page,
+                landing_page,
+                admin_token,
+                upstream_timeout,
+                connection_limit,
+                trusted_proxies,
+                sensitive_headers,
+            },
+            offline_grace,
+            idle_reap,
+            idle_reap_interval,
+            max_lifetime,
+            tls,
+        })
+    }
+}
+
+/// Try out the parts of the configuration that can only fail at runtime
+async fn check_config(config: Config) -> color_eyre::Result<()> {
+    let mut failed = false;
+    let mut report = |check: &str, result: color_eyre::Result<()>| match result {
+        Ok(()) => println!("ok      {check}"),
+        Err(err) => {
+            failed = true;
+            println!("failed  {check}: {err:#}");
+        }
+    };
+
+    for (name, addr) in [
+        ("SSH", config.ssh_addr),
+        ("HTTP", config.http_addr),
+        ("metrics", config.metrics_addr),
+    ] {
+        // The listener is dropped right away, we only want to know if we are able to bind
+        let result = TcpListener::bind(addr)
+            .await
+            .map(drop)
+            .wrap_err_with(|| format!("failed to bind to {addr}"));
+        report(&format!("bind {name} to {addr}"), result);
+    }
+
+    match config.key_source {
+        KeySource::Ldap(ldap) => {
+            let token = CancellationToken::new();
+            let result = Ldap::start(ldap, token.clone()).await;
+            token.cancel();
+            let result = match result {
+                Ok((_, handle)) => handle.await.map_err(Into::into),
+                Err(err) => Err(err.into()),
+            };
+            report("LDAP bind", result);
+        }
+        // The file is already read while loading the configuration
+        KeySource::File(key_file) => {
+            report(&format!("key file {}", key_file.path().display()), Ok(()));
+        }
+    }
+
+    let result = config
+        .authz
+        .build()
+        .probe()
+        .await
+        .wrap_err_with(|| format!("failed to reach {}", config.authz.address));
+    report("auth endpoint", result);
+    for (name, provider) in &config.authz_providers {
+        let result = provider
+            .build()
+            .probe()
+            .await
+            .wrap_err_with(|| format!("failed to reach {}", provider.address));
+        report(&format!("auth provider {name}"), result);
+    }
+
+    if failed {
+        color_eyre::eyre::bail!("configuration is invalid");
+    }
+
+    println!("Configuration is valid");
+
+    Ok(())
+}
+
+#[cfg(feature = "otel")]
+type TracerProvider = opentelemetry_sdk::trace::SdkTracerProvider;
+
+#[cfg(feature = "otel")]
+type TelemetryLayer = tracing_opentelemetry::OpenTelemetryLayer<
+    tracing_subscriber::Registry,
+    opentelemetry_sdk::trace::Tracer,
+>;
+
+/// Export spans using OTLP if `OTEL_EXPORTER_OTLP_ENDPOINT` is set, the exporter picks up the
+/// endpoint and the other `OTEL_*` variables by itself
+#[cfg(feature = "otel")]
+fn telemetry() -> color_eyre::Result<Option<(TracerProvider, TelemetryLayer)>> {
+    use opentelemetry::trace::TracerProvider as _;
+
+    if std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_err() {
+        return Ok(None);
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()?;
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name(std::env!("CARGO_PKG_NAME"))
+        .build();
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let layer =
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(std::env!("CARGO_PKG_NAME")));
+
+    Ok(Some((provider, layer)))
+}
+
+#[cfg(not(feature = "otel"))]
+fn telemetry() -> color_eyre::Result<Option<((), tracing_subscriber::layer::Identity)>> {
+    Ok(None)
+}
+
+/// Make sure all spans are exported before exiting
+#[cfg(feature = "otel")]
+fn shutdown_telemetry(provider: Option<TracerProvider>) {
+    if let Some(provider) = provider
+        && let Err(err) = provider.shutdown()
+    {
+        error!("Failed to export remaining spans: {err}");
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+fn shutdown_telemetry(_provider: Option<()>) {}
+
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
+    let cli = Cli::parse();
+
     color_eyre::install()?;
     dotenv().ok();
 
     let env_filter = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info"))?;
 
+    let (tracer_provider, telemetry) = telemetry()?.unzip();
+
     if std::env::var("CARGO").is_ok() {
         let logger = tracing_subscriber::fmt::layer().compact();
         tracing_subscriber::Registry::default()
+            .with(telemetry)
             .with(logger)
             .with(env_filter)
             .init();
     } else {
         let logger = tracing_subscriber::fmt::layer().json();
         tracing_subscriber::Registry::default()
+            .with(telemetry)
             .with(logger)
             .with(env_filter)
             .init();
@@ -83,58 +1100,104 @@ async fn main() -> color_eyre::Result<()> {
 
     info!(version = VERSION, "Starting",);
 
-    let key = if let Ok(path) = std::env::var("PRIVATE_KEY_FILE") {
-        russh::keys::PrivateKey::read_openssh_file(Path::new(&path))
-            .wrap_err_with(|| format!("failed to read ssh key: {path}"))?
-    } else {
-        warn!("No private key file specified, generating a new key");
-        russh::keys::PrivateKey::random(&mut OsRng, russh::keys::Algorithm::Ed25519)?
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(errors) => {
+            errors.report();
+            std::process::exit(EXIT_CONFIG);
+        }
     };
 
-    let ssh_port = std::env::var("SSH_PORT")
-        .map(|port| port.parse().wrap_err_with(|| format!("SSH_PORT={port}")))
-        .unwrap_or(Ok(2222))?;
-    let http_port = std::env::var("HTTP_PORT")
-        .map(|port| port.parse().wrap_err_with(|| format!("HTTP_PORT={port}")))
-        .unwrap_or(Ok(3000))?;
-    let metrics_port = std::env::var("METRICS_PORT")
-        .map(|port| {
-            port.parse()
-                .wrap_err_with(|| format!("METRICS_PORT={port}"))
-        })
-        .unwrap_or(Ok(4000))?;
-
-    let domain =
-        std::env::var("TUNNEL_DOMAIN").unwrap_or_else(|_| format!("localhost:{http_port}"));
-    let authz_address = std::env::var("AUTHZ_ENDPOINT").wrap_err("AUTHZ_ENDPOINT is not set")?;
+    if cli.check_config {
+        return check_config(config).await;
+    }
 
-    let registry = Registry::new(domain);
+    let mut registry = Registry::new(
+        config.domain,
+        config.names,
+        config.webhook,
+        config.namespace_by_user,
+    );
+    if let Some(grace) = config.offline_grace {
+        registry = registry.remember_offline(grace);
+    }
+    if let Some(lifetime) = config.max_lifetime {
+        registry = registry.limit_lifetime(lifetime);
+    }
+    if let Some(addresses) = config.generated_name_addresses {
+        registry = registry.generate_names_for(addresses);
+    }
+    if config.tls.is_some() {
+        registry = registry.use_https();
+    }
 
     let token = CancellationToken::new();
 
-    let (ldap, ldap_handle) = Ldap::start_from_env(token.clone()).await?;
+    if let Some(threshold) = config.idle_reap {
+        registry.reap_idle(threshold, config.idle_reap_interval, token.clone());
+    }
+    registry.expire_tunnels(token.clone());
+
+    let auth = AuthProviders::new(
+        config.authz.build(),
+        config
+            .authz_providers
+            .iter()
+            .map(|(name, provider)| (name.clone(), provider.build())),
+    );
+    tokio::spawn(revoke_task(registry.clone()));
+
+    let (ssh, ldap_handle) = match config.key_source {
+        KeySource::Ldap(ldap) => {
+            let (ldap, handle) = Ldap::start(ldap, token.clone()).await?;
+            tokio::spawn(reload_task(Some(ldap.clone()), auth.clone()));
+            let ssh = Server::new(ldap, registry.clone(), config.session_config, token.clone());
+
+            (ssh, Some(handle))
+        }
+        KeySource::File(key_file) => {
+            tokio::spawn(reload_task(None, auth.clone()));
+            let ssh = Server::new(
+                key_file,
+                registry.clone(),
+                config.session_config,
+                token.clone(),
+            );
+
+            (ssh, None)
+        }
+    };
+    let ldap_task = async {
+        if let Some(handle) = ldap_handle {
+            let _ = handle.await;
+        }
+    };
+    let ssh_task = ssh.run(config.keys, config.ssh_addr);
+    info!("SSH is available on {}", config.ssh_addr);
 
-    let ssh = Server::new(ldap, registry.clone(), token.clone());
-    let ssh_addr = SocketAddr::from(([0, 0, 0, 0], ssh_port));
-    let ssh_task = ssh.run(key, ssh_addr);
-    info!("SSH is available on {ssh_addr}");
+    let https = config.tls.is_some();
 
-    let auth = ForwardAuth::new(authz_address);
-    let service = Service::new(registry, auth);
-    let http_addr = SocketAddr::from(([0, 0, 0, 0], http_port));
-    let http_listener = TcpListener::bind(http_addr).await?;
+    let service = Service::new(registry, auth, config.tls, config.service_config);
+    tokio::spawn(maintenance_task(service.maintenance()));
+    let http_addr = config.http_addr;
+    let http_listener = bind_listener(http_addr, config.http_backlog)
+        .wrap_err_with(|| format!("failed to listen on {http_addr}"))?;
     let http_task = service.serve(http_listener, token.clone());
-    info!("HTTP is available on {http_addr}");
+    if https {
+        info!("HTTPS is available on {http_addr}");
+    } else {
+        info!("HTTP is available on {http_addr}");
+    }
 
     let metrics_app = Router::new().route("/health", get(async || Json("healthy")));
-    let metrics_addr = SocketAddr::from(([0, 0, 0, 0], metrics_port));
+    let metrics_addr = config.metrics_addr;
     let metrics_listener = TcpListener::bind(metrics_addr).await?;
     let metrics = axum::serve(metrics_listener, metrics_app)
         .with_graceful_shutdown(axum_graceful_shutdown(token.clone()));
-    info!("Metrics are available on {http_addr}");
+    info!("Metrics are available on {metrics_addr}");
 
     select! {
-        _ = join!(ldap_handle, ssh_task, http_task, metrics.into_future()) => {
+        _ = join!(ldap_task, ssh_task, http_task, metrics.into_future()) => {
             info!("Shutdown gracefully");
         }
         _ = shutdown_task(token.clone()) => {
@@ -142,5 +1205,7 @@ async fn main() -> color_eyre::Result<()> {
         }
     };
 
+    shutdown_telemetry(tracer_provider);
+
     Ok(())
 }
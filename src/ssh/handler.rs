@@ -1,21 +1,39 @@
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::fmt;
 use std::iter::once;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use futures::StreamExt as _;
+use hyper::header::{HeaderName, HeaderValue};
+use ipnet::IpNet;
 use ratatui::layout::Rect;
 use ratatui::prelude::CrosstermBackend;
 use ratatui::{Terminal, TerminalOptions, Viewport};
-use russh::ChannelId;
-use russh::keys::ssh_key::PublicKey;
+use russh::keys::ssh_key::{Certificate, HashAlg, PublicKey};
 use russh::server::{Auth, Msg, Session};
+use russh::{ChannelId, CryptoVec};
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tokio_rustls::rustls::pki_types::ServerName;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, trace, warn};
+use uuid::Uuid;
 
 use super::renderer::Renderer;
+use super::{KeyPolicy, KeyProvider, KeyProviderError, PortPolicy, SessionConfig};
 use crate::VERSION;
-use crate::io::{Input, TerminalHandle};
-use crate::ldap::{Ldap, LdapError};
-use crate::tunnel::{Registry, Tunnel, TunnelAccess};
+use crate::helper::{parse_duration, render_qr};
+use crate::io::{Input, InputDecoder, TerminalHandle, is_dumb_terminal};
+use crate::tunnel::{
+    BasicAuth, Cors, HeaderRule, HeaderRules, IpFilter, ListedTunnel, NameCharset, NamePolicy,
+    ReapReason, Reaped, Registry, RetryPolicy, Route, SessionGuard, TableView, Tunnel,
+    TunnelAccess, TunnelInfo, UnixSocket, Upstream, UpstreamTls, ViewedTunnel, WebSocketPolicy,
+    parse_header, parse_header_name, parse_ip_net, parse_server_name,
+};
 
 /// Quickly create http tunnels for development
 #[derive(Parser, Debug)]
@@ -27,6 +45,273 @@ pub struct Args {
 
     #[arg(long, group = "access")]
     protected: bool,
+
+    /// Temporarily make all tunnels public, afterwards they go back to their previous access
+    #[arg(long, group = "access", value_name = "DURATION", value_parser = parse_duration)]
+    public_for: Option<Duration>,
+
+    /// Let another auth provider of the server decide who can access the tunnels that are not
+    /// public, e.g. the identity provider of a partner
+    #[arg(long, value_name = "PROVIDER")]
+    auth: Option<String>,
+
+    /// Print the tunnels as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Show a label next to the tunnels to tell them apart, e.g. the branch they belong to. It
+    /// can also be changed in the interface
+    #[arg(long, value_name = "LABEL", value_parser = parse_label)]
+    label: Option<String>,
+
+    /// Print a QR code of the url of every tunnel
+    #[arg(long)]
+    qr: bool,
+
+    /// Require HTTP basic auth to access the tunnels
+    #[arg(long, value_name = "USER:PASSWORD")]
+    basic: Option<BasicAuth>,
+
+    /// Allow browsers to call the tunnels from any origin, preflight requests are answered
+    /// directly and never reach the forwarded port
+    #[arg(long)]
+    cors: bool,
+
+    /// Methods that are allowed by CORS, defaults to all common methods
+    #[arg(long, value_name = "METHODS", requires = "cors", value_parser = HeaderValue::from_str)]
+    cors_methods: Option<HeaderValue>,
+
+    /// Headers that are allowed by CORS, defaults to the headers the browser asks for
+    #[arg(long, value_name = "HEADERS", requires = "cors", value_parser = HeaderValue::from_str)]
+    cors_headers: Option<HeaderValue>,
+
+    /// Only allow access to the tunnels from the given address range, can be repeated
+    #[arg(long, value_name = "CIDR", value_parser = parse_ip_net)]
+    allow: Vec<IpNet>,
+
+    /// Deny access to the tunnels from the given address range, can be repeated
+    #[arg(long, value_name = "CIDR", value_parser = parse_ip_net)]
+    deny: Vec<IpNet>,
+
+    /// Only allow browsers on the given origin (e.g. `https://example.com`) to open WebSockets,
+    /// can be repeated
+    #[arg(long, value_name = "ORIGIN")]
+    ws_origin: Vec<String>,
+
+    /// Only allow WebSockets that request one of the given subprotocols, can be repeated
+    #[arg(long, value_name = "PROTOCOL")]
+    ws_protocol: Vec<String>,
+
+    /// Allow the user to see the stats of the tunnels using the status command, without giving
+    /// them access to the tunnels, can be repeated
+    #[arg(long, value_name = "USER")]
+    viewer: Vec<String>,
+
+    /// Keep the last requests send through the tunnels, they can be viewed using the inspect
+    /// command
+    #[arg(long)]
+    inspect: bool,
+
+    /// Talk HTTP/2 to the forwarded ports, needed for servers that only support HTTP/2
+    #[arg(long)]
+    h2: bool,
+
+    /// Answer with 504 Gateway Timeout if the forwarded ports do not respond within this time,
+    /// overrides the default of the server. Use 0 to wait indefinitely
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    timeout: Option<Duration>,
+
+    /// Compress responses with gzip or brotli if the browser supports it, costs some CPU but
+    /// helps on slow connections
+    #[arg(long)]
+    compress: bool,
+
+    /// Talk HTTPS to the forwarded ports, for servers that only support HTTPS. The certificate
+    /// has to be valid for the name of the tunnel, unless --upstream-sni is given
+    #[arg(long)]
+    upstream_tls: bool,
+
+    /// Server name to send to the forwarded ports and to verify their certificate against
+    #[arg(long, value_name = "NAME", value_parser = parse_server_name, requires = "upstream_tls")]
+    upstream_sni: Option<ServerName<'static>>,
+
+    /// Accept any certificate from the forwarded ports, for development servers with a
+    /// self-signed certificate. This is insecure
+    #[arg(long, requires = "upstream_tls")]
+    upstream_tls_insecure: bool,
+
+    /// Keep retrying to register tunnels whose address is already in use in the background,
+    /// until the address becomes available
+    #[arg(long)]
+    auto_retry: bool,
+
+    /// Share tunnels with the tunnels of the same name in your other sessions instead of failing
+    /// to register, requests are spread over all of them. The other sessions also need --pool
+    #[arg(long)]
+    pool: bool,
+
+    /// Send requests with a path starting with PREFIX to a different forwarded port, the port
+    /// also needs to be forwarded by the client
+    #[arg(long, value_name = "PREFIX=[ADDRESS:]PORT")]
+    route: Vec<Route>,
+
+    /// Send requests to a different forwarded port instead of the port of the tunnel, the port
+    /// also needs to be forwarded by the client and the address is resolved by the client
+    #[arg(long, value_name = "[ADDRESS:]PORT")]
+    upstream: Option<Upstream>,
+
+    /// Show that the tunnels are forwarded to a unix socket, the socket itself has to be
+    /// forwarded by the client using `-R <name>:<port>:<path>`. Without a name it applies to all
+    /// tunnels, can be repeated
+    #[arg(long, value_name = "[NAME=]PATH")]
+    uds: Vec<UnixSocket>,
+
+    /// Add a header to requests send through the tunnels
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_header)]
+    add_header: Vec<(HeaderName, HeaderValue)>,
+
+    /// Set a header on requests send through the tunnels, replacing existing values
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_header)]
+    set_header: Vec<(HeaderName, HeaderValue)>,
+
+    /// Remove a header from requests send through the tunnels
+    #[arg(long, value_name = "NAME", value_parser = parse_header_name)]
+    remove_header: Vec<HeaderName>,
+
+    /// Add a header to responses send back from the tunnels
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_header)]
+    add_response_header: Vec<(HeaderName, HeaderValue)>,
+
+    /// Set a header on responses send back from the tunnels, replacing existing values
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_header)]
+    set_response_header: Vec<(HeaderName, HeaderValue)>,
+
+    /// Remove a header from responses send back from the tunnels
+    #[arg(long, value_name = "NAME", value_parser = parse_header_name)]
+    remove_response_header: Vec<HeaderName>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Close one of your tunnels
+    Close {
+        /// Name of the tunnel
+        name: String,
+    },
+    /// Give one of your private tunnels to another user
+    Chown {
+        /// Name of the tunnel
+        name: String,
+        /// User that gets access to the tunnel
+        user: String,
+    },
+    /// Show who you are logged in as and which tunnels you have open
+    Status,
+    /// List all tunnels you can see, including public tunnels of other users
+    List,
+    /// Show the last requests that were send through one of your tunnels, requires the tunnel to
+    /// be opened with --inspect
+    Inspect {
+        /// Name of the tunnel
+        name: String,
+    },
+}
+
+/// Output of the status command
+#[derive(Debug, Serialize)]
+struct Status<'a> {
+    user: &'a str,
+    version: &'static str,
+    tunnels: Vec<TunnelInfo>,
+    viewing: Vec<ViewedTunnel>,
+}
+
+impl fmt::Display for Status<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Logged in as: {}", self.user)?;
+        writeln!(f, "Server version: {}", self.version)?;
+
+        if self.tunnels.is_empty() {
+            writeln!(f, "No open tunnels")?;
+        } else {
+            writeln!(f, "Tunnels:")?;
+            for tunnel in &self.tunnels {
+                writeln!(f, "  {tunnel}")?;
+            }
+        }
+
+        if !self.viewing.is_empty() {
+            writeln!(f, "Shared with you:")?;
+            for tunnel in &self.viewing {
+                writeln!(f, "  {tunnel}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn start_terminal(
+    handle: russh::server::Handle,
+    channel: ChannelId,
+    rect: Rect,
+    alternate_screen: bool,
+) -> std::io::Result<Terminal<CrosstermBackend<TerminalHandle>>> {
+    let terminal_handle = TerminalHandle::start(handle, channel, alternate_screen).await?;
+    let backend = CrosstermBackend::new(terminal_handle);
+    let options = TerminalOptions {
+        viewport: Viewport::Fixed(rect),
+    };
+
+    Terminal::with_options(backend, options)
+}
+
+/// Labels longer than this are cut off in the interface anyway
+const MAX_LABEL_LENGTH: usize = 32;
+
+fn parse_label(label: &str) -> Result<String, String> {
+    if label.chars().count() > MAX_LABEL_LENGTH {
+        return Err(format!(
+            "label is too long, at most {MAX_LABEL_LENGTH} characters are allowed"
+        ));
+    }
+    if label.chars().any(char::is_control) {
+        return Err("label can not contain control characters".into());
+    }
+
+    Ok(label.trim().to_owned())
+}
+
+/// Format the tunnels as a table with aligned columns
+fn format_table(tunnels: &[ListedTunnel]) -> String {
+    let header = ListedTunnel::header().map(String::from);
+    let rows: Vec<_> = once(header)
+        .chain(tunnels.iter().map(ListedTunnel::columns))
+        .collect();
+
+    let mut widths = [0; 10];
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = max(*width, column.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in rows {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(column, width)| format!("{column:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+
+    table
 }
 
 impl Args {
@@ -37,6 +322,131 @@ impl Args {
     pub fn make_protected(&self) -> bool {
         self.protected
     }
+
+    pub fn public_for(&self) -> Option<Duration> {
+        self.public_for
+    }
+
+    pub fn auth_provider(&self) -> Option<&str> {
+        self.auth.as_deref()
+    }
+
+    pub fn json(&self) -> bool {
+        self.json
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn qr(&self) -> bool {
+        self.qr
+    }
+
+    pub fn basic_auth(&self) -> Option<&BasicAuth> {
+        self.basic.as_ref()
+    }
+
+    pub fn cors(&self) -> Option<Cors> {
+        self.cors
+            .then(|| Cors::new(self.cors_methods.clone(), self.cors_headers.clone()))
+    }
+
+    pub fn ip_filter(&self) -> IpFilter {
+        IpFilter::new(self.allow.clone(), self.deny.clone())
+    }
+
+    pub fn websocket_policy(&self) -> WebSocketPolicy {
+        WebSocketPolicy::new(self.ws_origin.clone(), self.ws_protocol.clone())
+    }
+
+    pub fn inspect(&self) -> bool {
+        self.inspect
+    }
+
+    pub fn http2(&self) -> bool {
+        self.h2
+    }
+
+    pub fn upstream_timeout(&self) -> Option<Option<Duration>> {
+        self.timeout
+            .map(|timeout| (!timeout.is_zero()).then_some(timeout))
+    }
+
+    pub fn compress(&self) -> bool {
+        self.compress
+    }
+
+    pub fn upstream_tls(&self) -> Option<UpstreamTls> {
+        self.upstream_tls.then(|| {
+            UpstreamTls::new(
+                self.upstream_sni.clone(),
+                self.upstream_tls_insecure,
+                self.h2,
+            )
+        })
+    }
+
+    pub fn auto_retry(&self) -> bool {
+        self.auto_retry
+    }
+
+    pub fn pool(&self) -> bool {
+        self.pool
+    }
+
+    pub fn viewers(&self) -> &[String] {
+        &self.viewer
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.route
+    }
+
+    pub fn upstream(&self) -> Option<&Upstream> {
+        self.upstream.as_ref()
+    }
+
+    pub fn unix_sockets(&self) -> &[UnixSocket] {
+        &self.uds
+    }
+
+    pub fn header_rules(&self) -> HeaderRules {
+        fn rules(
+            remove: &[HeaderName],
+            set: &[(HeaderName, HeaderValue)],
+            add: &[(HeaderName, HeaderValue)],
+        ) -> Vec<HeaderRule> {
+            remove
+                .iter()
+                .cloned()
+                .map(HeaderRule::Remove)
+                .chain(
+                    set.iter()
+                        .cloned()
+                        .map(|(name, value)| HeaderRule::Set(name, value)),
+                )
+                .chain(
+                    add.iter()
+                        .cloned()
+                        .map(|(name, value)| HeaderRule::Add(name, value)),
+                )
+                .collect()
+        }
+
+        HeaderRules::new(
+            rules(&self.remove_header, &self.set_header, &self.add_header),
+            rules(
+                &self.remove_response_header,
+                &self.set_response_header,
+                &self.add_response_header,
+            ),
+        )
+    }
+
+    pub fn command(&self) -> Option<&Command> {
+        self.command.as_ref()
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -44,42 +454,201 @@ pub enum HandlerError {
     #[error(transparent)]
     Russh(#[from] russh::Error),
     #[error(transparent)]
-    Ldap(#[from] LdapError),
+    KeyProvider(#[from] KeyProviderError),
     #[error(transparent)]
     IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
 }
 
 pub struct Handler {
-    ldap: Ldap,
+    key_provider: Arc<dyn KeyProvider>,
 
     registry: Registry,
     tunnels: Vec<Tunnel>,
 
     user: Option<String>,
+    peer_addr: Option<SocketAddr>,
+    // The tunnels of the session are removed using this id once the session ends
+    session_id: Uuid,
+    // Lists the session in the registry once the user is logged in
+    session: Option<SessionGuard>,
     pty_channel: Option<ChannelId>,
+    // Shell channel of a client that did not request a pty, tunnels are printed on it as plain
+    // text instead of rendering the TUI
+    headless_channel: Option<ChannelId>,
 
     renderer: super::Renderer,
     selected: Option<usize>,
+    input_decoder: InputDecoder,
     rename_input: Option<String>,
+    label_input: Option<String>,
+    name_charset: NameCharset,
+    name_policy: NamePolicy,
+    filter_input: Option<String>,
+    // Name of the tunnel that is about to be deleted, the index can change while confirming
+    confirm_delete: Option<String>,
+    // Access of the tunnels before the last access change, so it can be undone. Cleared when a
+    // tunnel is removed, since the indices are no longer valid.
+    previous_access: Vec<(usize, TunnelAccess)>,
+    showing_popup: bool,
+    // Showing the selected tunnel on its own instead of the table
+    showing_details: bool,
+    banner: Option<String>,
+    view: TableView,
+
+    // Access of newly created tunnels, private to the user if not set
+    default_access: Option<TunnelAccess>,
+    // Tunnels are public until this time, set by --public-for
+    public_until: Option<Instant>,
+    // Once public access is revoked, tunnels created afterwards are no longer public either
+    public_revoked: watch::Receiver<()>,
+    header_rules: HeaderRules,
+    basic_auth: Option<BasicAuth>,
+    cors: Option<Cors>,
+    routes: Vec<Route>,
+    upstream: Option<Upstream>,
+    unix_sockets: Vec<UnixSocket>,
+    ip_filter: IpFilter,
+    websocket_policy: WebSocketPolicy,
+    viewers: Vec<String>,
+    inspect: bool,
+    http2: bool,
+    upstream_timeout: Option<Option<Duration>>,
+    auth_provider: Option<String>,
+    compress: bool,
+    upstream_tls: Option<UpstreamTls>,
+    auto_retry: bool,
+    pool: bool,
+    label: Option<String>,
+
+    retry_policy: RetryPolicy,
+    allowed_ports: PortPolicy,
+    key_policy: KeyPolicy,
+    // Named auth providers that tunnels can pick
+    auth_providers: Vec<String>,
+    // Certificate authority that is trusted to sign user keys
+    ca_key: Option<PublicKey>,
+    // Errors that happened before the TUI was started or the command was received
+    pending_errors: Vec<String>,
 }
 
 impl Handler {
-    pub fn new(ldap: Ldap, registry: Registry, token: CancellationToken) -> Self {
+    pub fn new(
+        key_provider: Arc<dyn KeyProvider>,
+        registry: Registry,
+        session_config: &SessionConfig,
+        peer_addr: Option<SocketAddr>,
+        session_id: Uuid,
+        token: CancellationToken,
+    ) -> Self {
+        let public_revoked = registry.watch_public_revoked();
+
         Self {
-            ldap,
+            key_provider,
             registry,
             tunnels: Default::default(),
             user: None,
+            peer_addr,
+            session_id,
+            session: None,
             pty_channel: None,
+            headless_channel: None,
 
-            renderer: Renderer::new(token),
+            renderer: Renderer::new(session_config.table_style.clone(), token),
             selected: None,
+            input_decoder: Default::default(),
             rename_input: None,
+            label_input: None,
+            name_charset: session_config.name_charset,
+            name_policy: session_config.name_policy,
+            filter_input: None,
+            confirm_delete: None,
+            previous_access: Vec::new(),
+            showing_popup: false,
+            showing_details: false,
+            banner: session_config.banner.clone(),
+            view: Default::default(),
+
+            default_access: None,
+            public_until: None,
+            public_revoked,
+            header_rules: Default::default(),
+            basic_auth: None,
+            cors: None,
+            routes: Default::default(),
+            upstream: None,
+            unix_sockets: Default::default(),
+            ip_filter: Default::default(),
+            websocket_policy: Default::default(),
+            viewers: Default::default(),
+            inspect: false,
+            http2: false,
+            upstream_timeout: None,
+            auth_provider: None,
+            compress: false,
+            upstream_tls: None,
+            auto_retry: false,
+            pool: false,
+            label: None,
+
+            retry_policy: session_config.open_retry,
+            allowed_ports: session_config.allowed_ports.clone(),
+            key_policy: session_config.key_policy.clone(),
+            auth_providers: session_config.auth_providers.clone(),
+            ca_key: session_config.ca_key.clone(),
+            pending_errors: Default::default(),
         }
     }
 
+    /// Unix socket the tunnel is forwarded to, later arguments win
+    fn unix_socket_for(&self, name: &str) -> Option<String> {
+        self.unix_sockets
+            .iter()
+            .rev()
+            .find(|socket| socket.applies_to(name))
+            .map(|socket| socket.path().to_owned())
+    }
+
+    /// Tell the owner when the tunnel is closed for being idle, stops once the tunnel is dropped
+    fn watch_reaped(&self, tunnel: &Tunnel, session: &Session) {
+        let mut reaped = tunnel.watch_reaped();
+        let renderer = self.renderer.clone();
+        let headless_channel = self.headless_channel;
+        let handle = session.handle();
+        tokio::spawn(async move {
+            while reaped.changed().await.is_ok() {
+                let Some(Reaped { name, reason }) = reaped.borrow_and_update().clone() else {
+                    continue;
+                };
+
+                let (message, hint) = match reason {
+                    ReapReason::Idle => (
+                        format!("Closed {name} after being idle"),
+                        ", press shift-r to reopen it",
+                    ),
+                    ReapReason::Expired => (
+                        format!("Closed {name}, it reached the maximum lifetime"),
+                        "",
+                    ),
+                };
+                if let Some(channel) = headless_channel {
+                    let message = CryptoVec::from(format!("{message}\n"));
+                    handle.extended_data(channel, 1, message).await.ok();
+                } else {
+                    renderer.notify(format!("{message}{hint}"));
+                }
+            }
+        });
+    }
+
     async fn set_access_all(&mut self, access: TunnelAccess) {
-        for tunnel in &self.tunnels {
+        // Changing the access by hand ends a temporary --public-for
+        self.public_until = None;
+        self.previous_access.clear();
+        for (index, tunnel) in self.tunnels.iter().enumerate() {
+            self.previous_access
+                .push((index, tunnel.get_access().await));
             tunnel.set_access(access.clone()).await;
         }
     }
@@ -87,6 +656,8 @@ impl Handler {
     async fn set_access_selection(&mut self, access: TunnelAccess) {
         if let Some(selected) = self.selected {
             if let Some(tunnel) = self.tunnels.get_mut(selected) {
+                self.public_until = None;
+                self.previous_access = vec![(selected, tunnel.get_access().await)];
                 tunnel.set_access(access).await;
             }
         } else {
@@ -94,14 +665,98 @@ impl Handler {
         }
     }
 
+    /// Restore the access the tunnels had before the last access change
+    async fn undo_access(&mut self) {
+        let previous = std::mem::take(&mut self.previous_access);
+        for (index, access) in &previous {
+            if let Some(tunnel) = self.tunnels.get(*index) {
+                tunnel.set_access(access.clone()).await;
+            }
+        }
+        self.renderer.rows(&self.tunnels).await;
+
+        match previous.as_slice() {
+            [] => self.renderer.notify("Nothing to undo"),
+            [(index, access)] => {
+                let name = self
+                    .tunnels
+                    .get(*index)
+                    .map_or("", |tunnel| tunnel.get_name());
+                let access = match access {
+                    TunnelAccess::Private(_) => "private",
+                    TunnelAccess::Protected => "protected",
+                    TunnelAccess::Public => "public",
+                };
+                self.renderer.notify(format!("Made {name} {access} again"));
+            }
+            previous => self
+                .renderer
+                .notify(format!("Restored the access of {} tunnels", previous.len())),
+        }
+    }
+
+    /// Send a message to the client and close the channel afterwards.
+    fn reply(
+        &self,
+        channel: ChannelId,
+        session: &mut Session,
+        message: impl Into<String>,
+        success: bool,
+    ) -> Result<(), HandlerError> {
+        let message = message.into();
+
+        if self.pty_channel.is_some() {
+            self.renderer.help(message);
+            return Ok(());
+        }
+
+        session.data(channel, CryptoVec::from(message.into_bytes()))?;
+        session.exit_status_request(channel, if success { 0 } else { 1 })?;
+        session.eof(channel)?;
+        session.close(channel)?;
+
+        Ok(())
+    }
+
+    /// Show an error in the TUI, or keep it until the client has opened a channel we can show it on
+    fn error(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        if self.pty_channel.is_some() {
+            self.renderer.error(message);
+        } else {
+            self.pending_errors.push(message);
+        }
+    }
+
+    /// Send a message to the client while keeping the channel, and thereby the tunnels, open.
+    fn write(
+        &self,
+        channel: ChannelId,
+        session: &mut Session,
+        message: impl Into<String>,
+    ) -> Result<(), HandlerError> {
+        let message = message.into();
+
+        // The TUI keeps running, so the message is shown as a notification instead
+        if self.pty_channel.is_some() {
+            self.renderer.notify(message.trim_end());
+            return Ok(());
+        }
+
+        session.data(channel, CryptoVec::from(message.into_bytes()))?;
+        session.eof(channel)?;
+
+        Ok(())
+    }
+
     async fn handle_input(&mut self, input: Input) -> std::io::Result<()> {
         if self.rename_input.is_some() {
             match input {
-                Input::Char(c) if c.is_alphanumeric() => {
+                Input::Char(c) if self.name_charset.allows(c) => {
                     self.rename_input
                         .as_mut()
                         .expect("input buffer should be some")
-                        .push(c.to_ascii_lowercase());
+                        .extend(c.to_lowercase());
                 }
                 Input::Backspace => {
                     self.rename_input
@@ -110,12 +765,28 @@ impl Handler {
                         .pop();
                 }
                 Input::Enter => {
+                    let buffer = self
+                        .rename_input
+                        .as_deref()
+                        .expect("input buffer should be some");
+                    let name = match self.name_charset.to_label(buffer) {
+                        Ok(name) => name,
+                        Err(err) => {
+                            debug!("Input is not a valid name: {err}");
+                            self.renderer.error(err);
+                            return Ok(());
+                        }
+                    };
+
                     debug!("Input accepted");
+                    self.rename_input = None;
                     if let Some(selected) = self.selected
                         && let Some(tunnel) = self.tunnels.get_mut(selected)
-                        && let Some(buffer) = self.rename_input.take()
                     {
-                        tunnel.set_name(buffer).await;
+                        tunnel.set_name(name).await;
+                        if self.auto_retry {
+                            tunnel.retry_in_background();
+                        }
                         self.renderer.rows(&self.tunnels).await;
                     } else {
                         warn!("Trying to rename invalid tunnel");
@@ -129,6 +800,92 @@ impl Handler {
             }
             debug!("Input: {:?}", self.rename_input);
             self.renderer.rename(&self.rename_input);
+        } else if let Some(buffer) = &mut self.label_input {
+            match input {
+                Input::Char(c) if !c.is_control() && buffer.chars().count() < MAX_LABEL_LENGTH => {
+                    buffer.push(c);
+                }
+                Input::Backspace => {
+                    buffer.pop();
+                }
+                Input::Enter => {
+                    let label = Some(buffer.trim().to_owned()).filter(|label| !label.is_empty());
+
+                    debug!("Label accepted");
+                    self.label_input = None;
+                    if let Some(selected) = self.selected
+                        && let Some(tunnel) = self.tunnels.get(selected)
+                    {
+                        tunnel.set_label(label).await;
+                        self.renderer.rows(&self.tunnels).await;
+                    } else {
+                        warn!("Trying to label invalid tunnel");
+                    }
+                }
+                Input::Esc => {
+                    debug!("Label rejected");
+                    self.label_input = None;
+                }
+                _ => return Ok(()),
+            }
+            debug!("Label: {:?}", self.label_input);
+            self.renderer.label(&self.label_input);
+        } else if self.filter_input.is_some() {
+            match input {
+                Input::Char(c) => {
+                    self.filter_input
+                        .as_mut()
+                        .expect("input buffer should be some")
+                        .push(c.to_ascii_lowercase());
+                }
+                Input::Backspace => {
+                    self.filter_input
+                        .as_mut()
+                        .expect("input buffer should be some")
+                        .pop();
+                }
+                Input::Enter => {
+                    debug!("Filter accepted");
+                    if let Some(filter) = self.filter_input.take() {
+                        self.view.set_filter(filter);
+                    }
+                    self.update_view();
+                }
+                Input::Esc => {
+                    debug!("Filter cleared");
+                    self.filter_input = None;
+                    self.view.set_filter("");
+                    self.update_view();
+                }
+                _ => return Ok(()),
+            }
+            debug!("Filter: {:?}", self.filter_input);
+            self.renderer.filter(&self.filter_input);
+        } else if self.showing_popup {
+            // Any key closes the popup
+            self.showing_popup = false;
+            self.renderer.popup(None);
+        } else if let Some(name) = &self.confirm_delete {
+            match input {
+                Input::Char('y') | Input::Enter => {
+                    debug!("Delete confirmed");
+                    let index = self
+                        .tunnels
+                        .iter()
+                        .position(|tunnel| tunnel.get_name() == name);
+                    self.confirm_delete = None;
+                    match index {
+                        Some(index) => self.delete_tunnel(index).await,
+                        None => warn!("Tunnel to delete no longer exists"),
+                    }
+                }
+                Input::Char('n') | Input::Esc => {
+                    debug!("Delete cancelled");
+                    self.confirm_delete = None;
+                }
+                _ => return Ok(()),
+            }
+            self.renderer.confirm_delete(None);
         } else {
             match input {
                 Input::Char('q') => {
@@ -142,10 +899,34 @@ impl Handler {
                     self.next_row();
                     self.renderer.select(self.selected);
                 }
+                Input::PageUp => {
+                    self.previous_page();
+                    self.renderer.select(self.selected);
+                }
+                Input::PageDown => {
+                    self.next_page();
+                    self.renderer.select(self.selected);
+                }
+                Input::Home => {
+                    self.first_row();
+                    self.renderer.select(self.selected);
+                }
+                Input::End => {
+                    self.last_row();
+                    self.renderer.select(self.selected);
+                }
+                Input::Esc if self.showing_details => {
+                    self.showing_details = false;
+                    self.renderer.details(false);
+                }
                 Input::Esc => {
                     self.selected = None;
                     self.renderer.select(self.selected);
                 }
+                Input::Char('i') | Input::Enter if self.selected.is_some() => {
+                    self.showing_details = !self.showing_details;
+                    self.renderer.details(self.showing_details);
+                }
                 Input::Char('P') => {
                     self.set_access_selection(TunnelAccess::Public).await;
                     self.renderer.rows(&self.tunnels).await;
@@ -171,6 +952,48 @@ impl Handler {
                     tunnel.retry().await;
                     self.renderer.rows(&self.tunnels).await;
                 }
+                Input::Char('y') => {
+                    let Some(selected) = self.selected else {
+                        return Ok(());
+                    };
+
+                    let Some(tunnel) = self.tunnels.get(selected) else {
+                        warn!("Trying to copy invalid tunnel");
+                        return Ok(());
+                    };
+
+                    if let Some(url) = tunnel.get_url() {
+                        self.renderer.copy(url);
+                    } else {
+                        self.renderer.error("Tunnel does not have an address");
+                    }
+                }
+                Input::Char('c') => {
+                    let Some(selected) = self.selected else {
+                        return Ok(());
+                    };
+
+                    let Some(tunnel) = self.tunnels.get(selected) else {
+                        warn!("Trying to show qr code of invalid tunnel");
+                        return Ok(());
+                    };
+
+                    let Some(url) = tunnel.get_url() else {
+                        self.renderer.error("Tunnel does not have an address");
+                        return Ok(());
+                    };
+
+                    match render_qr(&url) {
+                        Ok(code) => {
+                            self.showing_popup = true;
+                            self.renderer.popup(Some((url, code)));
+                        }
+                        Err(err) => {
+                            warn!(url, "Failed to create qr code: {err}");
+                            self.renderer.error("Failed to create QR code");
+                        }
+                    }
+                }
                 Input::Char('r') => {
                     if self.selected.is_some() {
                         trace!("Renaming tunnel");
@@ -178,29 +1001,72 @@ impl Handler {
                         self.renderer.rename(&self.rename_input);
                     }
                 }
+                Input::Char('l') => {
+                    if let Some(selected) = self.selected
+                        && let Some(tunnel) = self.tunnels.get(selected)
+                    {
+                        trace!("Labeling tunnel");
+                        self.label_input = Some(tunnel.get_label().await.unwrap_or_default());
+                        self.renderer.label(&self.label_input);
+                    }
+                }
                 Input::Delete => {
                     let Some(selected) = self.selected else {
                         return Ok(());
                     };
 
-                    if selected >= self.tunnels.len() {
+                    let Some(tunnel) = self.tunnels.get(selected) else {
                         warn!("Trying to delete tunnel out of bounds");
                         return Ok(());
-                    }
+                    };
 
-                    self.tunnels.remove(selected);
+                    trace!("Confirming delete");
+                    let name = tunnel.get_name().to_owned();
+                    self.confirm_delete = Some(name.clone());
+                    self.renderer.confirm_delete(Some(name));
+                }
+                Input::CtrlP => {
+                    self.set_access_selection(TunnelAccess::Protected).await;
                     self.renderer.rows(&self.tunnels).await;
+                }
+                Input::Char('u') => {
+                    self.undo_access().await;
+                }
+                Input::CtrlR => {
+                    let mut failed = 0;
+                    let mut registered = 0;
+                    for tunnel in &mut self.tunnels {
+                        if tunnel.get_address().is_some() {
+                            continue;
+                        }
 
-                    if self.tunnels.is_empty() {
-                        self.selected = None;
+                        failed += 1;
+                        tunnel.retry().await;
+                        if tunnel.get_address().is_some() {
+                            registered += 1;
+                        }
+                    }
+                    self.renderer.rows(&self.tunnels).await;
+
+                    if failed == 0 {
+                        self.renderer.notify("No failed tunnels");
+                    } else if registered == failed {
+                        self.renderer
+                            .notify(format!("Registered {registered} tunnel(s)"));
                     } else {
-                        self.selected = Some(min(self.tunnels.len() - 1, selected));
+                        self.renderer.error(format!(
+                            "Registered {registered} of {failed} failed tunnel(s)"
+                        ));
                     }
-                    self.renderer.select(self.selected);
                 }
-                Input::CtrlP => {
-                    self.set_access_selection(TunnelAccess::Protected).await;
-                    self.renderer.rows(&self.tunnels).await;
+                Input::Char('s') => {
+                    self.view.cycle_sort();
+                    self.update_view();
+                }
+                Input::Char('/') => {
+                    trace!("Filtering tunnels");
+                    self.filter_input = Some(self.view.filter().to_owned());
+                    self.renderer.filter(&self.filter_input);
                 }
                 _ => {}
             };
@@ -209,13 +1075,53 @@ impl Handler {
         Ok(())
     }
 
+    async fn delete_tunnel(&mut self, index: usize) {
+        if index >= self.tunnels.len() {
+            warn!("Trying to delete tunnel out of bounds");
+            return;
+        }
+
+        let position = self
+            .view
+            .tunnels(&self.tunnels)
+            .iter()
+            .position(|&i| i == index);
+
+        self.tunnels.remove(index).close().await;
+        self.previous_access.clear();
+        self.renderer.rows(&self.tunnels).await;
+
+        let view = self.view.tunnels(&self.tunnels);
+        self.selected = position
+            .and_then(|position| view.get(min(position, view.len().saturating_sub(1))))
+            .copied();
+        self.renderer.select(self.selected);
+    }
+
+    /// Position of the selected tunnel in the table
+    fn selected_position(&self, view: &[usize]) -> Option<usize> {
+        self.selected
+            .and_then(|selected| view.iter().position(|&index| index == selected))
+    }
+
+    fn update_view(&mut self) {
+        let view = self.view.tunnels(&self.tunnels);
+        if self.selected_position(&view).is_none() {
+            self.selected = None;
+        }
+
+        self.renderer.view(&self.view);
+        self.renderer.select(self.selected);
+    }
+
     fn next_row(&mut self) {
-        if self.tunnels.is_empty() {
+        let view = self.view.tunnels(&self.tunnels);
+        if view.is_empty() {
             return;
         }
-        let i = match self.selected {
+        let i = match self.selected_position(&view) {
             Some(i) => {
-                if i < self.tunnels.len() - 1 {
+                if i < view.len() - 1 {
                     i + 1
                 } else {
                     i
@@ -223,14 +1129,15 @@ impl Handler {
             }
             None => 0,
         };
-        self.selected = Some(i);
+        self.selected = Some(view[i]);
     }
 
     fn previous_row(&mut self) {
-        if self.tunnels.is_empty() {
+        let view = self.view.tunnels(&self.tunnels);
+        if view.is_empty() {
             return;
         }
-        let i = match self.selected {
+        let i = match self.selected_position(&view) {
             Some(i) => {
                 if i > 0 {
                     i - 1
@@ -238,9 +1145,43 @@ impl Handler {
                     i
                 }
             }
-            None => self.tunnels.len() - 1,
+            None => view.len() - 1,
+        };
+        self.selected = Some(view[i]);
+    }
+
+    fn next_page(&mut self) {
+        let view = self.view.tunnels(&self.tunnels);
+        if view.is_empty() {
+            return;
+        }
+        let i = match self.selected_position(&view) {
+            Some(i) => min(i + self.renderer.page_size(), view.len() - 1),
+            None => 0,
+        };
+        self.selected = Some(view[i]);
+    }
+
+    fn previous_page(&mut self) {
+        let view = self.view.tunnels(&self.tunnels);
+        if view.is_empty() {
+            return;
+        }
+        let i = match self.selected_position(&view) {
+            Some(i) => i.saturating_sub(self.renderer.page_size()),
+            None => view.len() - 1,
         };
-        self.selected = Some(i);
+        self.selected = Some(view[i]);
+    }
+
+    fn first_row(&mut self) {
+        let view = self.view.tunnels(&self.tunnels);
+        self.selected = view.first().copied();
+    }
+
+    fn last_row(&mut self) {
+        let view = self.view.tunnels(&self.tunnels);
+        self.selected = view.last().copied();
     }
 }
 
@@ -257,6 +1198,75 @@ impl russh::server::Handler for Handler {
         Ok(true)
     }
 
+    // Only sessions (pty, shell, and exec) and remote forwarding are supported, everything else is
+    // rejected explicitly so clients fail right away instead of waiting for a reply
+
+    async fn channel_open_direct_tcpip(
+        &mut self,
+        _channel: russh::Channel<Msg>,
+        host_to_connect: &str,
+        port_to_connect: u32,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        debug!(
+            host_to_connect,
+            port_to_connect, "Rejected local forwarding, only remote forwarding is supported"
+        );
+
+        Ok(false)
+    }
+
+    async fn channel_open_x11(
+        &mut self,
+        _channel: russh::Channel<Msg>,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        debug!("Rejected X11 channel");
+
+        Ok(false)
+    }
+
+    async fn x11_request(
+        &mut self,
+        channel: ChannelId,
+        _single_connection: bool,
+        _x11_auth_protocol: &str,
+        _x11_auth_cookie: &str,
+        _x11_screen_number: u32,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        debug!(?channel, "Rejected X11 forwarding");
+        session.channel_failure(channel)?;
+
+        Ok(())
+    }
+
+    async fn agent_request(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        debug!(?channel, "Rejected agent forwarding");
+
+        Ok(false)
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        debug!(?channel, name, "Rejected subsystem request");
+        session.channel_failure(channel)?;
+
+        Ok(())
+    }
+
     async fn auth_publickey(
         &mut self,
         user: &str,
@@ -267,7 +1277,12 @@ impl russh::server::Handler for Handler {
 
         self.user = Some(user.into());
 
-        for key in self.ldap.get_ssh_keys(user).await? {
+        if let Err(reason) = self.key_policy.check(public_key) {
+            warn!(user, "Rejected public key: {reason}");
+            return Ok(Auth::reject());
+        }
+
+        for key in self.key_provider.get_ssh_keys(user).await? {
             trace!("{key:?}");
             if key.key_data() == public_key.key_data() {
                 return Ok(Auth::Accept);
@@ -277,6 +1292,69 @@ impl russh::server::Handler for Handler {
         Ok(Auth::reject())
     }
 
+    async fn auth_openssh_certificate(
+        &mut self,
+        user: &str,
+        certificate: &Certificate,
+    ) -> Result<Auth, Self::Error> {
+        debug!("Login from {user} using a certificate");
+        trace!("{certificate:?}");
+
+        self.user = Some(user.into());
+
+        let Some(ca_key) = &self.ca_key else {
+            debug!(
+                user,
+                "Rejected certificate, no certificate authority is configured"
+            );
+            return Ok(Auth::reject());
+        };
+
+        let public_key = PublicKey::new(certificate.public_key().clone(), certificate.comment());
+        if let Err(reason) = self.key_policy.check(&public_key) {
+            warn!(user, "Rejected certificate: {reason}");
+            return Ok(Auth::reject());
+        }
+
+        // Checks the signature of the CA and that the certificate is currently valid
+        if let Err(err) = certificate.validate([&ca_key.fingerprint(HashAlg::Sha256)]) {
+            warn!(user, "Rejected certificate: {err}");
+            return Ok(Auth::reject());
+        }
+
+        if !certificate.cert_type().is_user() {
+            warn!(user, "Rejected certificate: not a user certificate");
+            return Ok(Auth::reject());
+        }
+
+        if !certificate
+            .valid_principals()
+            .iter()
+            .any(|principal| principal == user)
+        {
+            warn!(
+                user,
+                "Rejected certificate: user is not one of the principals"
+            );
+            return Ok(Auth::reject());
+        }
+
+        Ok(Auth::Accept)
+    }
+
+    async fn auth_succeeded(&mut self, session: &mut Session) -> Result<(), Self::Error> {
+        if let Some(user) = &self.user {
+            self.session = Some(self.registry.sessions().add(
+                self.session_id,
+                user,
+                self.peer_addr,
+                session.handle(),
+            ));
+        }
+
+        Ok(())
+    }
+
     async fn data(
         &mut self,
         channel: ChannelId,
@@ -287,10 +1365,11 @@ impl russh::server::Handler for Handler {
         if let Some(pty_channel) = self.pty_channel
             && pty_channel == channel
         {
-            let input: Input = data.into();
-            trace!(?input, "input");
+            for input in self.input_decoder.decode(data) {
+                trace!(?input, "input");
 
-            self.handle_input(input).await?;
+                self.handle_input(input).await?;
+            }
         }
 
         Ok(())
@@ -307,27 +1386,307 @@ impl russh::server::Handler for Handler {
         trace!(?cmd, "exec_request");
 
         let cmd = once("<ssh command> --").chain(cmd.split_whitespace());
+
+        session.channel_success(channel)?;
+
+        // Without a TUI the only way to tell the client about earlier errors is over stderr
+        if self.pty_channel.is_none() {
+            for message in std::mem::take(&mut self.pending_errors) {
+                session.extended_data(channel, 1, CryptoVec::from(format!("{message}\n")))?;
+            }
+        }
+
         match Args::try_parse_from(cmd) {
             Ok(args) => {
                 debug!("{args:?}");
+                // Silently falling back to the default provider would lock out the intended
+                // visitors, so the command is refused instead
+                if let Some(provider) = args.auth_provider()
+                    && !self.auth_providers.iter().any(|name| name == provider)
+                {
+                    warn!(
+                        user = self.user.as_deref(),
+                        provider, "Unknown auth provider"
+                    );
+                    let available = if self.auth_providers.is_empty() {
+                        "this server only has a default provider".to_owned()
+                    } else {
+                        format!("available providers: {}", self.auth_providers.join(", "))
+                    };
+                    self.reply(
+                        channel,
+                        session,
+                        format!("Unknown auth provider '{provider}', {available}\n"),
+                        false,
+                    )?;
+
+                    return Ok(());
+                }
+
                 if args.make_public() {
                     trace!("Making tunnels public");
+                    self.default_access = Some(TunnelAccess::Public);
+                    self.public_until = None;
                     self.set_access_all(TunnelAccess::Public).await;
                     self.renderer.rows(&self.tunnels).await;
                 } else if args.make_protected() {
                     trace!("Making tunnels protected");
+                    self.default_access = Some(TunnelAccess::Protected);
+                    self.public_until = None;
                     self.set_access_all(TunnelAccess::Protected).await;
                     self.renderer.rows(&self.tunnels).await;
+                } else if let Some(duration) = args.public_for() {
+                    trace!(?duration, "Temporarily making tunnels public");
+                    let expires = Instant::now() + duration;
+                    self.public_until = Some(expires);
+                    for tunnel in &self.tunnels {
+                        tunnel.set_access_until(TunnelAccess::Public, expires).await;
+                    }
+                    self.renderer.rows(&self.tunnels).await;
+                }
+
+                self.header_rules = args.header_rules();
+                self.basic_auth = args.basic_auth().cloned();
+                self.cors = args.cors();
+                self.routes = args.routes().to_vec();
+                self.upstream = args.upstream().cloned();
+                self.unix_sockets = args.unix_sockets().to_vec();
+                self.ip_filter = args.ip_filter();
+                self.websocket_policy = args.websocket_policy();
+                self.viewers = args.viewers().to_vec();
+                self.inspect = args.inspect();
+                self.http2 = args.http2();
+                self.upstream_timeout = args.upstream_timeout();
+                self.auth_provider = args.auth_provider().map(Into::into);
+                self.compress = args.compress();
+                self.upstream_tls = args.upstream_tls();
+                if self
+                    .upstream_tls
+                    .as_ref()
+                    .is_some_and(UpstreamTls::is_insecure)
+                {
+                    warn!(
+                        user = self.user.as_deref(),
+                        "Certificate verification of the forwarded ports is disabled, connections to them are insecure"
+                    );
+                }
+                self.auto_retry = args.auto_retry();
+                self.pool = args.pool();
+                // Only replace labels that were set in the interface if a new label is given
+                if let Some(label) = args.label() {
+                    self.label = Some(label.to_owned()).filter(|label| !label.is_empty());
+                    for tunnel in &self.tunnels {
+                        tunnel.set_label(self.label.clone()).await;
+                    }
+                    self.renderer.rows(&self.tunnels).await;
+                }
+                for tunnel in &self.tunnels {
+                    tunnel.set_header_rules(self.header_rules.clone()).await;
+                    tunnel.set_basic_auth(self.basic_auth.clone()).await;
+                    tunnel.set_cors(self.cors.clone()).await;
+                    tunnel.set_routes(self.routes.clone()).await;
+                    tunnel.set_upstream(self.upstream.clone()).await;
+                    tunnel
+                        .set_unix_socket(self.unix_socket_for(tunnel.get_name()))
+                        .await;
+                    tunnel.set_ip_filter(self.ip_filter.clone()).await;
+                    tunnel
+                        .set_websocket_policy(self.websocket_policy.clone())
+                        .await;
+                    tunnel.set_viewers(self.viewers.clone()).await;
+                    tunnel.set_inspect(self.inspect).await;
+                    tunnel.set_http2(self.http2).await;
+                    tunnel.set_upstream_timeout(self.upstream_timeout).await;
+                    tunnel.set_auth_provider(self.auth_provider.clone()).await;
+                    tunnel.set_compress(self.compress).await;
+                    tunnel.set_upstream_tls(self.upstream_tls.clone()).await;
+                    tunnel.set_pool(self.pool);
+                }
+                // Tunnels that failed to register might be able to join a pool now
+                if self.pool {
+                    for tunnel in &mut self.tunnels {
+                        tunnel.retry().await;
+                    }
+                    self.renderer.rows(&self.tunnels).await;
+                }
+                if self.auto_retry {
+                    for tunnel in &mut self.tunnels {
+                        tunnel.retry_in_background();
+                    }
+                    self.renderer.rows(&self.tunnels).await;
+                }
+
+                if args.json() && args.command().is_none() {
+                    let tunnels = futures::stream::iter(&self.tunnels)
+                        .then(Tunnel::info)
+                        .collect::<Vec<_>>()
+                        .await;
+
+                    let json = serde_json::to_string(&tunnels)?;
+                    self.write(channel, session, format!("{json}\n"))?;
+                }
+
+                if args.qr() && args.command().is_none() {
+                    let mut message = String::new();
+                    for tunnel in &self.tunnels {
+                        let name = tunnel.get_name();
+                        let Some(url) = tunnel.get_url() else {
+                            message.push_str(&format!("{name}: no address\n\n"));
+                            continue;
+                        };
+
+                        match render_qr(&url) {
+                            Ok(code) => message.push_str(&format!("{url}\n{code}\n\n")),
+                            Err(err) => {
+                                warn!(url, "Failed to create qr code: {err}");
+                                message.push_str(&format!("{url}: failed to create QR code\n\n"));
+                            }
+                        }
+                    }
+                    if message.is_empty() {
+                        message = "No open tunnels\n".into();
+                    }
+
+                    self.write(channel, session, message)?;
+                }
+
+                match args.command() {
+                    Some(Command::Close { name }) => {
+                        let Some(user) = self.user.clone() else {
+                            return Err(russh::Error::Inconsistent.into());
+                        };
+
+                        match self.registry.close(name, &user).await {
+                            Ok(()) => {
+                                debug!(name, user, "Closed tunnel");
+                                self.reply(
+                                    channel,
+                                    session,
+                                    format!("Closed tunnel: {name}\n"),
+                                    true,
+                                )?;
+                            }
+                            Err(err) => {
+                                debug!(name, user, "Failed to close tunnel: {err}");
+                                self.reply(channel, session, format!("{err}\n"), false)?;
+                            }
+                        }
+                    }
+                    Some(Command::Chown {
+                        name,
+                        user: new_owner,
+                    }) => {
+                        let Some(user) = self.user.clone() else {
+                            return Err(russh::Error::Inconsistent.into());
+                        };
+
+                        // Users without keys can never log in, so they are not able to use the
+                        // tunnel
+                        let result = match self.key_provider.get_ssh_keys(new_owner).await {
+                            Ok(keys) if keys.is_empty() => {
+                                Err(format!("Unknown user: {new_owner}"))
+                            }
+                            Ok(_) => self
+                                .registry
+                                .transfer(name, &user, new_owner)
+                                .await
+                                .map_err(|err| err.to_string()),
+                            Err(err) => {
+                                warn!(new_owner, "Failed to look up user: {err}");
+                                Err(format!("Failed to look up user: {new_owner}"))
+                            }
+                        };
+
+                        match result {
+                            Ok(()) => {
+                                debug!(name, user, new_owner, "Transferred tunnel");
+                                self.reply(
+                                    channel,
+                                    session,
+                                    format!("Transferred tunnel {name} to {new_owner}\n"),
+                                    true,
+                                )?;
+                            }
+                            Err(err) => {
+                                debug!(name, user, new_owner, "Failed to transfer tunnel: {err}");
+                                self.reply(channel, session, format!("{err}\n"), false)?;
+                            }
+                        }
+                    }
+                    Some(Command::List) => {
+                        let Some(user) = self.user.as_deref() else {
+                            return Err(russh::Error::Inconsistent.into());
+                        };
+
+                        let tunnels = self.registry.visible_to(user).await;
+                        let message = if args.json() {
+                            format!("{}\n", serde_json::to_string(&tunnels)?)
+                        } else {
+                            format_table(&tunnels)
+                        };
+                        self.write(channel, session, message)?;
+                    }
+                    Some(Command::Inspect { name }) => {
+                        let Some(user) = self.user.clone() else {
+                            return Err(russh::Error::Inconsistent.into());
+                        };
+
+                        match self.registry.inspect(name, &user).await {
+                            Ok(requests) if args.json() => {
+                                let json = serde_json::to_string(&requests)?;
+                                self.write(channel, session, format!("{json}\n"))?;
+                            }
+                            Ok(requests) if requests.is_empty() => {
+                                self.write(channel, session, "No requests captured\n".into())?;
+                            }
+                            Ok(requests) => {
+                                let message = requests
+                                    .iter()
+                                    .map(ToString::to_string)
+                                    .collect::<Vec<_>>()
+                                    .join("\n");
+                                self.write(channel, session, message)?;
+                            }
+                            Err(err) => {
+                                debug!(name, user, "Failed to inspect tunnel: {err}");
+                                self.reply(channel, session, format!("{err}\n"), false)?;
+                            }
+                        }
+                    }
+                    Some(Command::Status) => {
+                        let Some(user) = self.user.as_deref() else {
+                            return Err(russh::Error::Inconsistent.into());
+                        };
+
+                        let status = Status {
+                            user,
+                            version: VERSION,
+                            tunnels: futures::stream::iter(&self.tunnels)
+                                .then(Tunnel::info)
+                                .collect()
+                                .await,
+                            viewing: self.registry.viewable_by(user).await,
+                        };
+
+                        let message = if args.json() {
+                            format!("{}\n", serde_json::to_string(&status)?)
+                        } else {
+                            status.to_string()
+                        };
+                        self.write(channel, session, message)?;
+                    }
+                    None => {}
                 }
             }
             Err(err) => {
                 trace!("Sending help message and disconnecting");
 
-                self.renderer.help(err.render().ansi().to_string());
+                let success = !err.use_stderr();
+                self.reply(channel, session, err.render().ansi().to_string(), success)?;
             }
         }
 
-        Ok(session.channel_success(channel)?)
+        Ok(())
     }
 
     async fn channel_close(
@@ -362,14 +1721,130 @@ impl russh::server::Handler for Handler {
             return Err(russh::Error::Inconsistent.into());
         };
 
-        let tunnel = Tunnel::create(
-            &mut self.registry,
+        if !self.allowed_ports.is_allowed(*port) {
+            warn!(user, port, "Forwarding port is not allowed");
+
+            self.error(format!("Forwarding port {port} is not allowed"));
+
+            return Ok(false);
+        }
+
+        // The name is picked by the registry if the client did not ask for one
+        let name = if self.registry.generates_name(address) {
+            None
+        } else {
+            // Users can only claim the subdomains below their own name, this covers both
+            // wildcards and exact names with multiple labels. When namespacing by user every
+            // tunnel is already below their own name.
+            let owner = (!self.registry.is_namespaced()).then_some(user.as_str());
+            match self.name_policy.apply(address, owner) {
+                Ok(name) => Some(name),
+                Err(err) => {
+                    warn!(
+                        user,
+                        address, "Forwarded address is not a valid name: {err}"
+                    );
+                    self.error(format!("Can not forward {address}: {err}"));
+
+                    return Ok(false);
+                }
+            }
+        };
+
+        if self.public_revoked.has_changed().unwrap_or(false) {
+            self.public_revoked.borrow_and_update();
+            debug!(user, "Public access was revoked");
+            if matches!(self.default_access, Some(TunnelAccess::Public)) {
+                self.default_access = None;
+            }
+            self.public_until = None;
+        }
+
+        let access = self
+            .default_access
+            .clone()
+            .unwrap_or_else(|| TunnelAccess::Private(user.clone()));
+
+        // Listed once the user is logged in, which always happens before forwarding
+        let Some(owner) = &self.session else {
+            return Err(russh::Error::Inconsistent.into());
+        };
+
+        let mut tunnel = Tunnel::new(
+            &self.registry,
             session.handle(),
             address,
             *port,
-            TunnelAccess::Private(user),
-        )
-        .await;
+            owner,
+            access,
+            self.retry_policy,
+        );
+        tunnel.set_pool(self.pool);
+        tunnel.set_label(self.label.clone()).await;
+        // The unix socket depends on the name, so it has to be picked first
+        tunnel.pick_name(name).await;
+        tunnel.set_header_rules(self.header_rules.clone()).await;
+        tunnel.set_basic_auth(self.basic_auth.clone()).await;
+        tunnel.set_cors(self.cors.clone()).await;
+        tunnel.set_routes(self.routes.clone()).await;
+        tunnel.set_upstream(self.upstream.clone()).await;
+        tunnel
+            .set_unix_socket(self.unix_socket_for(tunnel.get_name()))
+            .await;
+        tunnel.set_viewers(self.viewers.clone()).await;
+        tunnel.set_inspect(self.inspect).await;
+        tunnel.set_http2(self.http2).await;
+        tunnel.set_upstream_timeout(self.upstream_timeout).await;
+        tunnel.set_compress(self.compress).await;
+        tunnel.set_upstream_tls(self.upstream_tls.clone()).await;
+        tunnel.set_ip_filter(self.ip_filter.clone()).await;
+        tunnel
+            .set_websocket_policy(self.websocket_policy.clone())
+            .await;
+        tunnel.set_auth_provider(self.auth_provider.clone()).await;
+        // Requests can arrive as soon as the tunnel is registered, so the settings have to be
+        // in place already
+        tunnel.register().await;
+        if tunnel.get_address().is_none() {
+            if self.auto_retry {
+                tunnel.retry_in_background();
+            }
+
+            let name = tunnel.get_name();
+            warn!(
+                user,
+                name, "Failed to register tunnel, address is already in use"
+            );
+            // The TUI already shows the tunnel as failed
+            if self.pty_channel.is_none() && self.auto_retry {
+                self.pending_errors.push(format!(
+                    "Failed to register tunnel {name}, the address is already in use. Retrying in \
+                    the background"
+                ));
+            } else if self.pty_channel.is_none() {
+                self.pending_errors.push(format!(
+                    "Failed to register tunnel {name}, the address is already in use. Pick a \
+                    different name using `-R <name>:{port}:<host>:<hostport>`"
+                ));
+            }
+        }
+        if let Some(expires) = self.public_until
+            && expires > Instant::now()
+        {
+            tunnel.set_access_until(TunnelAccess::Public, expires).await;
+        }
+
+        if let Some(channel) = self.headless_channel {
+            for message in std::mem::take(&mut self.pending_errors) {
+                session.extended_data(channel, 1, CryptoVec::from(format!("{message}\n")))?;
+            }
+            let info = tunnel.info().await;
+            session.data(channel, CryptoVec::from(format!("{info}\n")))?;
+        }
+
+        if self.pty_channel.is_some() || self.headless_channel.is_some() {
+            self.watch_reaped(&tunnel, session);
+        }
 
         self.tunnels.push(tunnel);
 
@@ -394,10 +1869,42 @@ impl russh::server::Handler for Handler {
         Ok(())
     }
 
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        trace!(?channel, "shell_request");
+
+        session.channel_success(channel)?;
+
+        // The TUI was already started by the pty request
+        if self.pty_channel.is_some() {
+            return Ok(());
+        }
+
+        debug!("No pty requested, printing tunnels as plain text");
+        for message in std::mem::take(&mut self.pending_errors) {
+            session.extended_data(channel, 1, CryptoVec::from(format!("{message}\n")))?;
+        }
+        let mut message = String::new();
+        for tunnel in &self.tunnels {
+            message.push_str(&format!("{}\n", tunnel.info().await));
+        }
+        session.data(channel, CryptoVec::from(message))?;
+
+        self.headless_channel = Some(channel);
+        for tunnel in &self.tunnels {
+            self.watch_reaped(tunnel, session);
+        }
+
+        Ok(())
+    }
+
     async fn pty_request(
         &mut self,
         channel: ChannelId,
-        _term: &str,
+        term: &str,
         col_width: u32,
         row_height: u32,
         _pix_width: u32,
@@ -405,7 +1912,12 @@ impl russh::server::Handler for Handler {
         _modes: &[(russh::Pty, u32)],
         session: &mut Session,
     ) -> Result<(), Self::Error> {
-        trace!(col_width, row_height, ?channel, "pty_request");
+        trace!(term, col_width, row_height, ?channel, "pty_request");
+
+        let dumb = is_dumb_terminal(term);
+        if dumb {
+            debug!(term, "Rendering without alternate screen and styling");
+        }
 
         let rect = Rect {
             x: 0,
@@ -413,15 +1925,38 @@ impl russh::server::Handler for Handler {
             width: col_width as u16,
             height: row_height as u16,
         };
-        let terminal_handle = TerminalHandle::start(session.handle(), channel).await?;
-        let backend = CrosstermBackend::new(terminal_handle);
-        let options = TerminalOptions {
-            viewport: Viewport::Fixed(rect),
+        let terminal = match start_terminal(session.handle(), channel, rect, !dumb).await {
+            Ok(terminal) => terminal,
+            Err(err) => {
+                // Forwarding does not depend on the TUI, so the session can continue without it
+                warn!("Failed to set up terminal: {err}");
+                session.channel_failure(channel)?;
+                session.extended_data(
+                    channel,
+                    1,
+                    CryptoVec::from(
+                        "Failed to set up the terminal, tunnels keep working without the interface\n"
+                            .to_owned(),
+                    ),
+                )?;
+
+                return Ok(());
+            }
         };
-        let terminal = Terminal::with_options(backend, options)?;
-        self.renderer.start(terminal);
+        self.renderer.start(terminal, dumb);
+        for tunnel in &self.tunnels {
+            self.watch_reaped(tunnel, session);
+        }
 
         self.renderer.rows(&self.tunnels).await;
+        if let Some(banner) = &self.banner {
+            self.showing_popup = true;
+            self.renderer
+                .popup(Some((std::env!("CARGO_PKG_NAME").into(), banner.clone())));
+        }
+        for message in std::mem::take(&mut self.pending_errors) {
+            self.renderer.error(message);
+        }
 
         self.pty_channel = Some(channel);
 
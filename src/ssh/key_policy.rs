@@ -0,0 +1,73 @@
+use russh::keys::{Algorithm, EcdsaCurve, PublicKey};
+
+/// Key algorithms that users are allowed to authenticate with, keys that do not match are rejected
+/// even if they are listed in LDAP
+#[derive(Debug, Clone)]
+pub struct KeyPolicy {
+    algorithms: Vec<Algorithm>,
+    min_rsa_bits: usize,
+}
+
+impl KeyPolicy {
+    pub const DEFAULT_MIN_RSA_BITS: usize = 3072;
+
+    pub fn new(algorithms: Vec<Algorithm>, min_rsa_bits: usize) -> Self {
+        Self {
+            algorithms,
+            min_rsa_bits,
+        }
+    }
+
+    /// Returns the reason the key is not allowed
+    pub fn check(&self, key: &PublicKey) -> Result<(), String> {
+        let algorithm = key.algorithm();
+        if !self.algorithms.contains(&algorithm) {
+            return Err(format!("algorithm {algorithm} is not allowed"));
+        }
+
+        if let Some(rsa) = key.key_data().rsa() {
+            let bits = rsa
+                .n
+                .as_positive_bytes()
+                .and_then(|n| {
+                    let first = n.first()?;
+                    Some(n.len() * 8 - first.leading_zeros() as usize)
+                })
+                .unwrap_or(0);
+
+            if bits < self.min_rsa_bits {
+                return Err(format!(
+                    "RSA key of {bits} bits is smaller than the minimum of {} bits",
+                    self.min_rsa_bits
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Only modern algorithms, RSA is still allowed but limited by the minimum key size
+    pub fn default_algorithms() -> Vec<Algorithm> {
+        vec![
+            Algorithm::Ed25519,
+            Algorithm::Ecdsa {
+                curve: EcdsaCurve::NistP256,
+            },
+            Algorithm::Ecdsa {
+                curve: EcdsaCurve::NistP384,
+            },
+            Algorithm::Ecdsa {
+                curve: EcdsaCurve::NistP521,
+            },
+            Algorithm::SkEd25519,
+            Algorithm::SkEcdsaSha2NistP256,
+            Algorithm::Rsa { hash: None },
+        ]
+    }
+}
+
+impl Default for KeyPolicy {
+    fn default() -> Self {
+        Self::new(Self::default_algorithms(), Self::DEFAULT_MIN_RSA_BITS)
+    }
+}
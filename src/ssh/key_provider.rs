@@ -0,0 +1,24 @@
+use std::fmt::Debug;
+use std::pin::Pin;
+
+use russh::keys::PublicKey;
+
+/// Looking up the keys failed, the user is not able to log in until it succeeds
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct KeyProviderError(Box<dyn std::error::Error + Send + Sync>);
+
+impl KeyProviderError {
+    pub fn new(err: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Self(Box::new(err))
+    }
+}
+
+/// Source of the public keys that users are allowed to log in with, for example LDAP
+pub trait KeyProvider: Debug + Send + Sync {
+    /// Users without keys are not able to log in
+    fn get_ssh_keys<'a>(
+        &'a self,
+        user: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<PublicKey>, KeyProviderError>> + Send + 'a>>;
+}
@@ -1,26 +1,84 @@
 mod handler;
+mod key_policy;
+mod key_provider;
+mod ports;
 mod renderer;
 
-use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use handler::Handler;
+pub use key_policy::KeyPolicy;
+pub use key_provider::{KeyProvider, KeyProviderError};
+pub use ports::PortPolicy;
 use renderer::Renderer;
+pub use renderer::TableStyle;
 use russh::MethodKind;
-use russh::keys::PrivateKey;
-use russh::server::Server as _;
-use tokio::net::ToSocketAddrs;
+use russh::keys::{PrivateKey, PublicKey};
+use tokio::net::{TcpListener, ToSocketAddrs};
 use tokio::select;
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, trace, warn};
+use uuid::Uuid;
 
-use crate::ldap::Ldap;
-use crate::tunnel::Registry;
+use crate::tunnel::{NameCharset, NamePolicy, Registry, RetryPolicy};
+
+/// Timeouts and keepalive settings for ssh sessions.
+///
+/// Clients answer every keepalive, which counts as activity. While keepalives are enabled a
+/// session is therefore only closed by the inactivity timeout if the keepalive interval is longer
+/// than the timeout. Connections that silently died are instead closed after `keepalive_max`
+/// unanswered keepalives.
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    pub inactivity_timeout: Option<Duration>,
+    pub auth_rejection_time: Duration,
+    pub keepalive_interval: Option<Duration>,
+    pub keepalive_max: usize,
+    /// How opening a tunnel is retried when the client fails to open a channel
+    pub open_retry: RetryPolicy,
+    /// Ports that users are allowed to forward
+    pub allowed_ports: PortPolicy,
+    /// Public keys that are accepted
+    pub key_policy: KeyPolicy,
+    /// Trusted to sign user certificates
+    pub ca_key: Option<PublicKey>,
+    /// Characters that can be used when renaming a tunnel
+    pub name_charset: NameCharset,
+    /// What happens to forwarded addresses that are not valid names
+    pub name_policy: NamePolicy,
+    /// Shown to users when the TUI is opened
+    pub banner: Option<String>,
+    /// Spacing of the table in the TUI
+    pub table_style: TableStyle,
+    /// Names of the auth providers that tunnels can pick instead of the default provider
+    pub auth_providers: Vec<String>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            inactivity_timeout: Some(Duration::from_secs(3600)),
+            auth_rejection_time: Duration::from_secs(1),
+            keepalive_interval: Some(Duration::from_secs(60)),
+            keepalive_max: 3,
+            open_retry: Default::default(),
+            allowed_ports: Default::default(),
+            key_policy: Default::default(),
+            ca_key: None,
+            name_charset: Default::default(),
+            name_policy: Default::default(),
+            banner: None,
+            table_style: Default::default(),
+            auth_providers: Vec::new(),
+        }
+    }
+}
 
 pub struct Server {
-    ldap: Ldap,
+    key_provider: Arc<dyn KeyProvider>,
     registry: Registry,
+    session_config: SessionConfig,
     token: CancellationToken,
 }
 
@@ -36,20 +94,42 @@ async fn graceful_shutdown(token: CancellationToken) {
 }
 
 impl Server {
-    pub fn new(ldap: Ldap, registry: Registry, token: CancellationToken) -> Self {
+    pub fn new(
+        key_provider: impl KeyProvider + 'static,
+        registry: Registry,
+        session_config: SessionConfig,
+        token: CancellationToken,
+    ) -> Self {
         Server {
-            ldap,
+            key_provider: Arc::new(key_provider),
             registry,
+            session_config,
             token,
         }
     }
 
-    pub async fn run(mut self, key: PrivateKey, addr: impl ToSocketAddrs + Send + std::fmt::Debug) {
+    pub async fn run(
+        self,
+        keys: Vec<PrivateKey>,
+        addr: impl ToSocketAddrs + Send + std::fmt::Debug,
+    ) {
+        debug!(?addr, "Running ssh");
+
+        match TcpListener::bind(addr).await {
+            Ok(listener) => self.serve(keys, listener).await,
+            Err(err) => error!("SSH Server error: {err}"),
+        }
+    }
+
+    /// Same as [`Self::run`], but on a listener that is already bound
+    pub async fn serve(self, keys: Vec<PrivateKey>, listener: TcpListener) {
         let config = russh::server::Config {
-            inactivity_timeout: Some(Duration::from_secs(3600)),
-            auth_rejection_time: Duration::from_secs(1),
+            inactivity_timeout: self.session_config.inactivity_timeout,
+            auth_rejection_time: self.session_config.auth_rejection_time,
             auth_rejection_time_initial: Some(Duration::from_secs(0)),
-            keys: vec![key],
+            keepalive_interval: self.session_config.keepalive_interval,
+            keepalive_max: self.session_config.keepalive_max,
+            keys,
             preferred: russh::Preferred {
                 ..Default::default()
             },
@@ -59,11 +139,9 @@ impl Server {
         };
         let config = Arc::new(config);
 
-        debug!(?addr, "Running ssh");
-
         let token = self.token.clone();
         select! {
-            res = self.run_on_address(config, addr) => {
+            res = self.accept(config, &listener) => {
                 if let Err(err) = res {
                     error!("SSH Server error: {err}");
                 }
@@ -73,16 +151,46 @@ impl Server {
             }
         }
     }
-}
 
-impl russh::server::Server for Server {
-    type Handler = Handler;
+    /// Accept connections and run a session for each of them. The tunnels of a session are
+    /// removed as soon as the session ends, instead of relying on the handler being dropped.
+    async fn accept(
+        &self,
+        config: Arc<russh::server::Config>,
+        listener: &TcpListener,
+    ) -> std::io::Result<()> {
+        loop {
+            let (stream, peer_addr) = listener.accept().await?;
+            if config.nodelay
+                && let Err(err) = stream.set_nodelay(true)
+            {
+                warn!("Failed to set nodelay: {err}");
+            }
 
-    fn new_client(&mut self, _peer_addr: Option<SocketAddr>) -> Self::Handler {
-        Handler::new(self.ldap.clone(), self.registry.clone(), self.token.clone())
-    }
+            let session_id = Uuid::new_v4();
+            let handler = Handler::new(
+                self.key_provider.clone(),
+                self.registry.clone(),
+                &self.session_config,
+                Some(peer_addr),
+                session_id,
+                self.token.clone(),
+            );
+            let config = config.clone();
+            let registry = self.registry.clone();
+            tokio::spawn(async move {
+                match russh::server::run_stream(config, stream, handler).await {
+                    Ok(session) => {
+                        if let Err(err) = session.await {
+                            warn!("Session error: {err:#?}");
+                        }
+                    }
+                    Err(err) => warn!("Session error: {err:#?}"),
+                }
 
-    fn handle_session_error(&mut self, error: <Self::Handler as russh::server::Handler>::Error) {
-        warn!("Session error: {error:#?}");
+                trace!(%session_id, "Session closed, removing its tunnels");
+                registry.remove_owned_by(session_id).await;
+            });
+        }
     }
 }
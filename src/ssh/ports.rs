@@ -0,0 +1,50 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Ports that users are allowed to forward, an empty policy allows every port
+#[derive(Debug, Clone, Default)]
+pub struct PortPolicy {
+    ranges: Vec<RangeInclusive<u32>>,
+}
+
+impl PortPolicy {
+    pub fn is_allowed(&self, port: u32) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&port))
+    }
+}
+
+impl FromStr for PortPolicy {
+    type Err = String;
+
+    /// Parse a comma separated list of ports and port ranges, e.g. `80,1024-65535`
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        let parse_port = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .map(u32::from)
+                .map_err(|err| format!("invalid port '{port}': {err}"))
+        };
+
+        let ranges = policy
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (start, end) = match entry.split_once('-') {
+                    Some((start, end)) => (parse_port(start)?, parse_port(end)?),
+                    None => {
+                        let port = parse_port(entry)?;
+                        (port, port)
+                    }
+                };
+
+                if start > end {
+                    return Err(format!("invalid port range '{entry}'"));
+                }
+
+                Ok(start..=end)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { ranges })
+    }
+}
@@ -1,8 +1,12 @@
 use std::cmp::{self, max};
 use std::io::Write as _;
 use std::iter::once;
-use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
+use base64::Engine as _;
+use base64::prelude::BASE64_STANDARD;
 use futures::StreamExt;
 use ratatui::layout::{Constraint, Flex, Layout, Position, Rect};
 use ratatui::prelude::CrosstermBackend;
@@ -20,31 +24,74 @@ use unicode_width::UnicodeWidthStr;
 
 use crate::VERSION;
 use crate::io::TerminalHandle;
-use crate::tunnel::{Tunnel, TunnelRow};
+use crate::tunnel::{TableView, Tunnel, TunnelRow};
+
+/// Redraw requests that arrive within this window are combined into a single draw, this keeps
+/// held keys and fast resizes from redrawing the whole screen for every event
+const REDRAW_DEBOUNCE: Duration = Duration::from_millis(16);
+
+/// Columns that are kept when the table does not fit, the columns after them are dropped first
+const ESSENTIAL_COLUMNS: usize = 4;
+
+const SHUTDOWN_NOTICE: &str = "The server is shutting down, your tunnels will be closed";
+/// How long the notice is shown before the interface closes, the server stops shortly after
+const SHUTDOWN_NOTICE_DURATION: Duration = Duration::from_millis(500);
 
 enum Message {
     Resize { width: u16, height: u16 },
     Redraw,
     Rows(Vec<TunnelRow>),
     Select(Option<usize>),
+    View(TableView),
     Rename(Option<String>),
+    Label(Option<String>),
+    Filter(Option<String>),
+    ConfirmDelete(Option<String>),
+    Popup(Option<(String, String)>),
+    Details(bool),
+    Copy(String),
+    Notify { message: String, error: bool },
     Help(String),
     Close,
 }
 
+struct Notification {
+    message: String,
+    error: bool,
+    expires: Instant,
+}
+
 struct RendererInner {
     state: TableState,
     rows: Vec<TunnelRow>,
+    selected: Option<usize>,
+    view: TableView,
     input: Option<String>,
+    label_input: Option<String>,
+    filter_input: Option<String>,
+    confirm_delete: Option<String>,
+    // Title and text of a popup that is closed by pressing any key
+    popup: Option<(String, String)>,
+    // Show the selected tunnel on its own instead of the table
+    details: bool,
+    notification: Option<Notification>,
+    // Banner that is shown on top of everything else
+    notice: Option<String>,
+    table_style: TableStyle,
+    // Number of rows that fit in the table, shared with the handler for paging
+    page_size: Arc<AtomicUsize>,
     rx: UnboundedReceiver<Message>,
+    // Render without colors or other styling
+    plain: bool,
 
     token: CancellationToken,
 }
 
-fn compute_widths(rows: &Vec<Vec<Span<'static>>>) -> Vec<u16> {
+fn compute_widths(rows: &[Vec<Span<'static>>], totals: &[Span<'static>]) -> Vec<u16> {
     let table_header = Tunnel::header();
-    std::iter::once(&table_header)
-        .chain(rows)
+    std::iter::once(table_header.as_slice())
+        .chain(rows.iter().map(Vec::as_slice))
+        .chain(once(totals))
         .map(|row| row.iter().map(|cell| cell.width() as u16))
         .fold(vec![0; table_header.len()], |acc, row| {
             acc.into_iter()
@@ -54,13 +101,48 @@ fn compute_widths(rows: &Vec<Vec<Span<'static>>>) -> Vec<u16> {
         })
 }
 
+/// Spacing of the table, narrow terminals fit more columns with less spacing
+#[derive(Debug, Clone)]
+pub struct TableStyle {
+    pub column_spacing: u16,
+    // Shown in front of the selected row
+    pub highlight_symbol: String,
+}
+
+impl Default for TableStyle {
+    fn default() -> Self {
+        Self {
+            column_spacing: 3,
+            highlight_symbol: "> ".into(),
+        }
+    }
+}
+
+/// The columns to show as `(take, skip)`, from the most to the least columns. Columns are dropped
+/// from the end one at a time until only the essential columns are left, after which the essential
+/// columns are dropped from the start.
+fn column_patterns(columns: usize) -> impl Iterator<Item = (usize, usize)> {
+    let essential = columns.min(ESSENTIAL_COLUMNS);
+
+    (essential..=columns)
+        .rev()
+        .map(|take| (take, 0))
+        .chain((1..essential).map(move |skip| (essential, skip)))
+}
+
 fn compute_column_skip(
     widths: &[u16],
     column_spacing: u16,
     highlight_symbol: usize,
     max_width: u16,
 ) -> (usize, usize) {
-    for pattern in [(7, 0), (4, 0), (4, 1), (4, 2)] {
+    let mut patterns = column_patterns(widths.len()).peekable();
+    while let Some(pattern) = patterns.next() {
+        // The last pattern is used even if it does not fit
+        if patterns.peek().is_none() {
+            return pattern;
+        }
+
         let width: u16 = widths
             .iter()
             .take(pattern.0)
@@ -74,16 +156,134 @@ fn compute_column_skip(
         }
     }
 
-    (4, 3)
+    (widths.len(), 0)
+}
+
+fn render_input(frame: &mut Frame, area: Rect, title: &str, input: &str) {
+    let vertical = Layout::vertical([Constraint::Length(3)]).flex(Flex::Center);
+    let horizontal =
+        Layout::horizontal([Constraint::Max(max(20, input.width() as u16 + 4))]).flex(Flex::Center);
+    let [area] = vertical.areas(area);
+    let [area] = horizontal.areas(area);
+
+    let title = Line::from(title).centered();
+    let block = Block::bordered().title(title);
+    let text = Paragraph::new(format!(" {input}")).block(block);
+
+    frame.render_widget(Clear, area);
+
+    frame.render_widget(text, area);
+
+    frame.set_cursor_position(Position::new(area.x + input.width() as u16 + 2, area.y + 1));
+}
+
+fn render_confirm(frame: &mut Frame, area: Rect, name: &str) {
+    let question = format!("Delete {name}?");
+    let hint = Line::from(vec![
+        "y".bold().light_cyan(),
+        " confirm | ".dim(),
+        "n".bold().light_cyan(),
+        " cancel".dim(),
+    ]);
+
+    let vertical = Layout::vertical([Constraint::Length(4)]).flex(Flex::Center);
+    let horizontal = Layout::horizontal([Constraint::Max(
+        max(hint.width(), question.width()) as u16 + 4,
+    )])
+    .flex(Flex::Center);
+    let [area] = vertical.areas(area);
+    let [area] = horizontal.areas(area);
+
+    let title = Line::from("Confirm").centered();
+    let block = Block::bordered().title(title);
+    let text = Paragraph::new(vec![Line::from(question), hint])
+        .centered()
+        .block(block);
+
+    frame.render_widget(Clear, area);
+
+    frame.render_widget(text, area);
+}
+
+fn render_popup(frame: &mut Frame, area: Rect, title: &str, text: &str) {
+    let lines: Vec<_> = text.lines().map(Line::from).collect();
+    let width = lines.iter().map(Line::width).max().unwrap_or_default();
+
+    let vertical =
+        Layout::vertical([Constraint::Length(lines.len() as u16 + 2)]).flex(Flex::Center);
+    let horizontal = Layout::horizontal([Constraint::Length(max(width, title.width()) as u16 + 4)])
+        .flex(Flex::Center);
+    let [area] = vertical.areas(area);
+    let [area] = horizontal.areas(area);
+
+    let title = Line::from(title).centered();
+    let block = Block::bordered().title(title);
+    let text = Paragraph::new(lines).centered().block(block);
+
+    frame.render_widget(Clear, area);
+
+    frame.render_widget(text, area);
+}
+
+fn render_notice(frame: &mut Frame, area: Rect, text: &str) {
+    let [area] = Layout::vertical([Constraint::Length(3)])
+        .flex(Flex::Start)
+        .areas(area);
+
+    let block = Block::bordered().border_type(BorderType::Thick);
+    let text = Paragraph::new(text).centered().bold().yellow().block(block);
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(text, area);
+}
+
+fn render_details(frame: &mut Frame, area: Rect, row: &TunnelRow) {
+    let details = row.details();
+    let width = details
+        .iter()
+        .map(|(label, _)| label.width())
+        .max()
+        .unwrap_or_default();
+
+    let lines: Vec<_> = details
+        .into_iter()
+        .map(|(label, value)| {
+            Line::from(vec![format!("{:width$}   ", label.content).bold(), value])
+        })
+        .collect();
+
+    let title = Line::from(row.name()).bold().centered();
+    let block = Block::bordered().title(title);
+    let text = Paragraph::new(lines).block(block);
+
+    frame.render_widget(text, area);
 }
 
 impl RendererInner {
-    fn new(rx: UnboundedReceiver<Message>, token: CancellationToken) -> Self {
+    fn new(
+        rx: UnboundedReceiver<Message>,
+        page_size: Arc<AtomicUsize>,
+        plain: bool,
+        table_style: TableStyle,
+        token: CancellationToken,
+    ) -> Self {
         Self {
             state: Default::default(),
             rows: Default::default(),
+            selected: None,
+            view: Default::default(),
             input: None,
+            label_input: None,
+            filter_input: None,
+            confirm_delete: None,
+            popup: None,
+            details: false,
+            notification: None,
+            notice: None,
+            table_style,
+            page_size,
             rx,
+            plain,
             token,
         }
     }
@@ -91,35 +291,105 @@ impl RendererInner {
     fn compute_footer_text<'a>(&self, rect: Rect) -> (u16, Paragraph<'a>) {
         let width = rect.width as usize - 2;
 
-        fn command<'c>(key: &'c str, text: &'c str) -> Vec<Span<'c>> {
+        fn command<'c>(key: &'c str, text: impl Into<Span<'c>>) -> Vec<Span<'c>> {
+            let text: Span = text.into();
             vec![key.bold().light_cyan(), " ".into(), text.dim()]
         }
 
-        let commands = if self.state.selected().is_some() {
+        let sort = format!("sort ({})", self.view.sort());
+        let filter = if self.view.filter().is_empty() {
+            "filter".to_owned()
+        } else {
+            format!("filter ({})", self.view.filter())
+        };
+
+        // Actions that need a selection are still shown, but greyed out, to make them discoverable
+        fn disabled<'c>(key: &'c str, text: &'c str) -> Vec<Span<'c>> {
+            vec![key.bold().dark_gray(), " ".into(), text.dark_gray()]
+        }
+
+        let selected = self.selected.and_then(|selected| self.rows.get(selected));
+        let any_failed = self.rows.iter().any(|row| !row.has_address());
+
+        let commands = if self.popup.is_some() {
+            vec![command("any key", "close")]
+        } else if self.details && selected.is_some() {
             vec![
+                command("q", "quit"),
+                command("esc/i", "back to table"),
+                command("↓/j", "next tunnel"),
+                command("↑/k", "previous tunnel"),
+            ]
+        } else if self.confirm_delete.is_some() {
+            vec![command("y/enter", "confirm"), command("n/esc", "cancel")]
+        } else if self.input.is_some() {
+            vec![command("enter", "rename"), command("esc", "cancel")]
+        } else if self.label_input.is_some() {
+            vec![command("enter", "set label"), command("esc", "cancel")]
+        } else if self.filter_input.is_some() {
+            vec![
+                command("enter", "apply filter"),
+                command("esc", "clear filter"),
+            ]
+        } else if let Some(row) = selected {
+            let mut commands = vec![
                 command("q", "quit"),
                 command("esc", "deselect"),
                 command("↓/j", "move down"),
                 command("↑/k", "move up"),
+                command("pgdn/pgup", "move page"),
+                command("home/end", "first/last"),
+                command("i/enter", "details"),
                 vec![],
                 command("del", "remove"),
                 command("r", "rename"),
-                command("shift-r", "retry"),
+                command("l", "label"),
+            ];
+            if row.has_address() {
+                commands.push(command("y", "copy url"));
+                commands.push(command("c", "show qr code"));
+            } else {
+                commands.push(command("shift-r", "retry"));
+            }
+            if any_failed {
+                commands.push(command("ctrl-r", "retry all failed"));
+            }
+            commands.extend([
                 vec![],
                 command("p", "make private"),
                 command("ctrl-p", "make protected"),
                 command("shift-p", "make public"),
-            ]
+                command("u", "undo access change"),
+                vec![],
+                command("s", sort),
+                command("/", filter),
+            ]);
+            commands
         } else {
-            vec![
+            let mut commands = vec![
                 command("q", "quit"),
                 command("↓/j", "select first"),
                 command("↑/k", "select last"),
+                vec![],
+                disabled("del", "remove"),
+                disabled("r", "rename"),
+                disabled("l", "label"),
+                disabled("y", "copy url"),
+            ];
+            if any_failed {
+                commands.push(command("ctrl-r", "retry all failed"));
+            }
+            commands.extend([
                 vec![],
                 command("p", "make all private"),
                 command("ctrl-p", "make all protected"),
                 command("shift-p", "make all public"),
-            ]
+                command("u", "undo access change"),
+                vec![],
+                command("s", sort),
+                command("/", filter),
+            ]);
+            commands
         };
 
         let mut text = Text::default();
@@ -163,31 +433,87 @@ impl RendererInner {
         let layout = Layout::vertical([Constraint::Min(5), Constraint::Length(footer_height)]);
         let chunks = layout.split(area);
 
-        self.render_table(frame, chunks[0]);
+        let selected = self.selected.and_then(|selected| self.rows.get(selected));
+        match selected {
+            Some(row) if self.details => render_details(frame, chunks[0], row),
+            _ => self.render_table(frame, chunks[0]),
+        }
         frame.render_widget(footer, chunks[1]);
-        self.render_rename(frame, area);
+        if let Some(input) = &self.input {
+            render_input(frame, area, "New name", input);
+        }
+        if let Some(input) = &self.label_input {
+            render_input(frame, area, "Label", input);
+        }
+        if let Some(input) = &self.filter_input {
+            render_input(frame, area, "Filter", input);
+        }
+        if let Some(name) = &self.confirm_delete {
+            render_confirm(frame, area, name);
+        }
+        if let Some((title, text)) = &self.popup {
+            render_popup(frame, area, title, text);
+        }
+        if let Some(notice) = &self.notice {
+            render_notice(frame, area, notice);
+        }
+
+        if self.plain {
+            let area = frame.area();
+            frame.buffer_mut().set_style(area, Style::reset());
+        }
     }
 
     fn render_title(&self, frame: &mut Frame, rect: Rect) {
-        let title = format!("{} ({})", std::env!("CARGO_PKG_NAME"), VERSION).bold();
+        let title = if let Some(notification) = &self.notification
+            && notification.expires > Instant::now()
+        {
+            let message = notification.message.clone().bold();
+            if notification.error {
+                message.red()
+            } else {
+                message.green()
+            }
+        } else {
+            format!("{} ({})", std::env!("CARGO_PKG_NAME"), VERSION).bold()
+        };
         let title = Line::from(title).centered();
         frame.render_widget(title, rect);
     }
 
+    fn notify(&mut self, message: impl Into<String>, error: bool) {
+        self.notification = Some(Notification {
+            message: message.into(),
+            error,
+            expires: Instant::now() + Duration::from_secs(3),
+        });
+    }
+
     fn render_table(&mut self, frame: &mut Frame<'_>, rect: Rect) {
         let highlight_style = Style::default().bold();
         let header_style = Style::default().bold().reversed();
+        let footer_style = Style::default().bold();
         let row_style = Style::default();
-        let highlight_symbol = Line::from("> ");
-        let column_spacing = 3;
+        let highlight_symbol = Line::from(self.table_style.highlight_symbol.clone());
+        let column_spacing = self.table_style.column_spacing;
 
-        let rows = self
-            .rows
-            .iter()
+        let view = self.view.rows(&self.rows);
+        self.state.select(
+            self.selected
+                .and_then(|selected| view.iter().position(|&index| index == selected)),
+        );
+
+        let displayed: Vec<_> = view
+            .into_iter()
+            .filter_map(|index| self.rows.get(index))
+            .collect();
+        let totals = TunnelRow::totals(displayed.iter().copied());
+        let rows = displayed
+            .into_iter()
             .map(From::from)
             .collect::<Vec<Vec<Span<'static>>>>();
 
-        let widths = compute_widths(&rows);
+        let widths = compute_widths(&rows, &totals);
         let (take, skip) = compute_column_skip(
             &widths,
             column_spacing,
@@ -230,9 +556,19 @@ impl RendererInner {
             .style(header_style)
             .height(1);
 
+        let footer = totals
+            .into_iter()
+            .take(take)
+            .skip(skip)
+            .map(Cell::from)
+            .collect::<Row>()
+            .style(footer_style)
+            .height(1);
+
         let t = Table::default()
             .header(header)
             .rows(rows)
+            .footer(footer)
             .flex(Flex::Start)
             .column_spacing(column_spacing)
             .widths(&constraints)
@@ -241,34 +577,22 @@ impl RendererInner {
             .highlight_spacing(HighlightSpacing::Always);
 
         frame.render_stateful_widget(t, rect, &mut self.state);
-    }
 
-    fn render_rename(&self, frame: &mut Frame, area: Rect) {
-        if let Some(input) = &self.input {
-            let vertical = Layout::vertical([Constraint::Length(3)]).flex(Flex::Center);
-            let horizontal =
-                Layout::horizontal([Constraint::Max(max(20, input.width() as u16 + 4))])
-                    .flex(Flex::Center);
-            let [area] = vertical.areas(area);
-            let [area] = horizontal.areas(area);
-
-            let title = Line::from("New name").centered();
-            let block = Block::bordered().title(title);
-            let text = Paragraph::new(format!(" {input}")).block(block);
-
-            frame.render_widget(Clear, area);
-
-            frame.render_widget(text, area);
-
-            frame.set_cursor_position(Position::new(area.x + input.width() as u16 + 2, area.y + 1));
-        }
+        // The header and footer take up one row each
+        let page_size = usize::from(rect.height.saturating_sub(2)).max(1);
+        self.page_size.store(page_size, Ordering::Relaxed);
     }
 
     pub async fn start(
         &mut self,
         mut terminal: Terminal<CrosstermBackend<TerminalHandle>>,
     ) -> std::io::Result<()> {
+        // Set while a redraw has been requested but not yet drawn
+        let mut redraw_at: Option<tokio::time::Instant> = None;
+
         loop {
+            let debounce = redraw_at.unwrap_or_else(tokio::time::Instant::now);
+
             select! {
                 message = self.rx.recv() => {
                     let Some(message) = message else {
@@ -281,13 +605,29 @@ impl RendererInner {
 
                             terminal.resize(rect)?;
                         }
-                        Message::Select(selected) => self.state.select(selected),
+                        Message::Select(selected) => self.selected = selected,
+                        Message::View(view) => self.view = view,
                         Message::Rename(input) => self.input = input,
+                        Message::Label(input) => self.label_input = input,
+                        Message::Filter(input) => self.filter_input = input,
+                        Message::ConfirmDelete(name) => self.confirm_delete = name,
+                        Message::Popup(popup) => self.popup = popup,
+                        Message::Details(details) => self.details = details,
+                        Message::Copy(text) => {
+                            // Copy the text to the clipboard of the client using OSC 52
+                            let encoded = BASE64_STANDARD.encode(&text);
+                            let writer = terminal.backend_mut().writer_mut();
+                            write!(writer, "\x1b]52;c;{encoded}\x07")?;
+                            writer.flush()?;
+
+                            self.notify(format!("Copied {text}"), false);
+                        }
+                        Message::Notify { message, error } => self.notify(message, error),
                         Message::Rows(rows) => self.rows = rows,
                         Message::Redraw => {
-                            terminal.draw(|frame| {
-                                self.render(frame);
-                            })?;
+                            redraw_at.get_or_insert_with(|| {
+                                tokio::time::Instant::now() + REDRAW_DEBOUNCE
+                            });
                         }
                         Message::Help(message) => {
                             let writer = terminal.backend_mut().writer_mut();
@@ -302,13 +642,33 @@ impl RendererInner {
                         }
                     }
                 }
+                _ = tokio::time::sleep_until(debounce), if redraw_at.is_some() => {
+                    redraw_at = None;
+                    terminal.draw(|frame| {
+                        self.render(frame);
+                    })?;
+                }
                 _ = tokio::time::sleep(Duration::from_secs(1)) => {
+                    redraw_at = None;
                     terminal.draw(|frame| {
                         self.render(frame);
                     })?;
                 }
                 _ = self.token.cancelled() => {
                     debug!("Graceful shutdown");
+
+                    self.notice = Some(SHUTDOWN_NOTICE.into());
+                    terminal.draw(|frame| {
+                        self.render(frame);
+                    })?;
+                    tokio::time::sleep(SHUTDOWN_NOTICE_DURATION).await;
+
+                    // Keep the notice around after the interface is gone
+                    let writer = terminal.backend_mut().writer_mut();
+                    writer.leave_alternate_screen()?;
+                    write!(writer, "{SHUTDOWN_NOTICE}\r\n")?;
+                    writer.flush()?;
+
                     break;
                 }
             }
@@ -321,21 +681,31 @@ impl RendererInner {
 #[derive(Debug, Clone)]
 pub struct Renderer {
     tx: Option<UnboundedSender<Message>>,
+    page_size: Arc<AtomicUsize>,
+    table_style: TableStyle,
     token: CancellationToken,
 }
 
 impl Renderer {
-    pub fn new(token: CancellationToken) -> Self {
+    pub fn new(table_style: TableStyle, token: CancellationToken) -> Self {
         Self {
             tx: Default::default(),
+            page_size: Arc::new(AtomicUsize::new(1)),
+            table_style,
             token,
         }
     }
 
-    pub fn start(&mut self, terminal: Terminal<CrosstermBackend<TerminalHandle>>) {
+    pub fn start(&mut self, terminal: Terminal<CrosstermBackend<TerminalHandle>>, plain: bool) {
         let (tx, rx) = unbounded_channel();
 
-        let mut inner = RendererInner::new(rx, self.token.clone());
+        let mut inner = RendererInner::new(
+            rx,
+            self.page_size.clone(),
+            plain,
+            self.table_style.clone(),
+            self.token.clone(),
+        );
 
         tokio::spawn(async move {
             if let Err(err) = inner.start(terminal).await {
@@ -346,6 +716,11 @@ impl Renderer {
         self.tx = Some(tx)
     }
 
+    /// Number of rows that fit in the table the last time it was rendered
+    pub fn page_size(&self) -> usize {
+        self.page_size.load(Ordering::Relaxed)
+    }
+
     pub fn select(&self, selected: Option<usize>) {
         if let Some(tx) = &self.tx {
             tx.send(Message::Select(selected)).ok();
@@ -360,6 +735,77 @@ impl Renderer {
         }
     }
 
+    pub fn label(&self, input: &Option<String>) {
+        if let Some(tx) = &self.tx {
+            tx.send(Message::Label(input.clone())).ok();
+            self.redraw();
+        }
+    }
+
+    pub fn filter(&self, input: &Option<String>) {
+        if let Some(tx) = &self.tx {
+            tx.send(Message::Filter(input.clone())).ok();
+            self.redraw();
+        }
+    }
+
+    pub fn confirm_delete(&self, name: Option<String>) {
+        if let Some(tx) = &self.tx {
+            tx.send(Message::ConfirmDelete(name)).ok();
+            self.redraw();
+        }
+    }
+
+    pub fn popup(&self, popup: Option<(String, String)>) {
+        if let Some(tx) = &self.tx {
+            tx.send(Message::Popup(popup)).ok();
+            self.redraw();
+        }
+    }
+
+    pub fn details(&self, details: bool) {
+        if let Some(tx) = &self.tx {
+            tx.send(Message::Details(details)).ok();
+            self.redraw();
+        }
+    }
+
+    pub fn view(&self, view: &TableView) {
+        if let Some(tx) = &self.tx {
+            tx.send(Message::View(view.clone())).ok();
+            self.redraw();
+        }
+    }
+
+    pub fn copy(&self, text: impl Into<String>) {
+        if let Some(tx) = &self.tx {
+            tx.send(Message::Copy(text.into())).ok();
+            self.redraw();
+        }
+    }
+
+    pub fn notify(&self, message: impl Into<String>) {
+        if let Some(tx) = &self.tx {
+            tx.send(Message::Notify {
+                message: message.into(),
+                error: false,
+            })
+            .ok();
+            self.redraw();
+        }
+    }
+
+    pub fn error(&self, message: impl Into<String>) {
+        if let Some(tx) = &self.tx {
+            tx.send(Message::Notify {
+                message: message.into(),
+                error: true,
+            })
+            .ok();
+            self.redraw();
+        }
+    }
+
     pub fn help(&self, message: String) {
         if let Some(tx) = &self.tx {
             tx.send(Message::Help(message.replace("\n", "\n\r"))).ok();
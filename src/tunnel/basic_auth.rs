@@ -0,0 +1,81 @@
+use std::str::FromStr;
+
+use base64::Engine as _;
+use base64::prelude::BASE64_STANDARD;
+use hyper::header::HeaderValue;
+use rand::RngCore as _;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+
+/// Credentials for protecting a tunnel with HTTP basic auth, the password is only stored as a
+/// salted hash.
+#[derive(Debug, Clone)]
+pub struct BasicAuth {
+    username: String,
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+fn hash(salt: &[u8], password: &str) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(salt)
+        .chain_update(password.as_bytes())
+        .finalize()
+        .into()
+}
+
+impl BasicAuth {
+    pub fn new(username: impl Into<String>, password: impl AsRef<str>) -> Self {
+        let mut salt = [0; 16];
+        OsRng.fill_bytes(&mut salt);
+
+        Self {
+            username: username.into(),
+            salt,
+            hash: hash(&salt, password.as_ref()),
+        }
+    }
+
+    /// Check if the value of an `Authorization` header matches the credentials
+    pub fn verify(&self, authorization: &HeaderValue) -> bool {
+        let Some(credentials) = authorization
+            .to_str()
+            .ok()
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("basic"))
+            .and_then(|(_, credentials)| BASE64_STANDARD.decode(credentials.trim()).ok())
+            .and_then(|credentials| String::from_utf8(credentials).ok())
+        else {
+            return false;
+        };
+
+        let Some((username, password)) = credentials.split_once(':') else {
+            return false;
+        };
+
+        // Compare in constant time to avoid leaking how much of the hash matched
+        let matches = hash(&self.salt, password)
+            .iter()
+            .zip(self.hash)
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0;
+
+        matches && username == self.username
+    }
+}
+
+impl FromStr for BasicAuth {
+    type Err = String;
+
+    fn from_str(credentials: &str) -> Result<Self, Self::Err> {
+        let (username, password) = credentials
+            .split_once(':')
+            .ok_or_else(|| "invalid USER:PASSWORD: no ':' found".to_owned())?;
+
+        if username.is_empty() || password.is_empty() {
+            return Err("username and password can not be empty".into());
+        }
+
+        Ok(Self::new(username, password))
+    }
+}
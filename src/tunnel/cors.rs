@@ -0,0 +1,53 @@
+use hyper::header::{
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+    HeaderValue, ORIGIN,
+};
+use hyper::{HeaderMap, Method};
+
+const DEFAULT_METHODS: &str = "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS";
+
+/// Allows browsers to call the tunnel from any origin, preflight requests are answered without
+/// reaching the forwarded port
+#[derive(Debug, Clone)]
+pub struct Cors {
+    allow_methods: HeaderValue,
+    // Echo the headers requested by the browser if not set
+    allow_headers: Option<HeaderValue>,
+}
+
+impl Cors {
+    pub fn new(allow_methods: Option<HeaderValue>, allow_headers: Option<HeaderValue>) -> Self {
+        Self {
+            allow_methods: allow_methods.unwrap_or(HeaderValue::from_static(DEFAULT_METHODS)),
+            allow_headers,
+        }
+    }
+
+    pub(crate) fn is_preflight(method: &Method, headers: &HeaderMap) -> bool {
+        method == Method::OPTIONS
+            && headers.contains_key(ORIGIN)
+            && headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// Add the headers that answer a preflight request
+    pub(crate) fn apply_preflight(&self, request: &HeaderMap, response: &mut HeaderMap) {
+        self.apply(response);
+
+        response.insert(ACCESS_CONTROL_ALLOW_METHODS, self.allow_methods.clone());
+        let allow_headers = self
+            .allow_headers
+            .as_ref()
+            .or_else(|| request.get(ACCESS_CONTROL_REQUEST_HEADERS));
+        if let Some(allow_headers) = allow_headers {
+            response.insert(ACCESS_CONTROL_ALLOW_HEADERS, allow_headers.clone());
+        }
+        response.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static("86400"));
+    }
+
+    /// Add the headers that allow any origin to read the response. Credentials are never
+    /// allowed, otherwise any website could act on behalf of a visitor that is logged in.
+    pub(crate) fn apply(&self, response: &mut HeaderMap) {
+        response.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    }
+}
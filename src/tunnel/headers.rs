@@ -0,0 +1,71 @@
+use hyper::HeaderMap;
+use hyper::header::{CONNECTION, HeaderName, HeaderValue, UPGRADE};
+
+#[derive(Debug, Clone)]
+pub enum HeaderRule {
+    Add(HeaderName, HeaderValue),
+    Set(HeaderName, HeaderValue),
+    Remove(HeaderName),
+}
+
+impl HeaderRule {
+    fn apply(&self, headers: &mut HeaderMap) {
+        match self {
+            HeaderRule::Add(name, value) => {
+                headers.append(name, value.clone());
+            }
+            HeaderRule::Set(name, value) => {
+                headers.insert(name, value.clone());
+            }
+            HeaderRule::Remove(name) => {
+                headers.remove(name);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HeaderRules {
+    request: Vec<HeaderRule>,
+    response: Vec<HeaderRule>,
+}
+
+impl HeaderRules {
+    pub fn new(request: Vec<HeaderRule>, response: Vec<HeaderRule>) -> Self {
+        Self { request, response }
+    }
+
+    pub fn apply_request(&self, headers: &mut HeaderMap) {
+        for rule in &self.request {
+            rule.apply(headers);
+        }
+    }
+
+    pub fn apply_response(&self, headers: &mut HeaderMap) {
+        for rule in &self.response {
+            rule.apply(headers);
+        }
+    }
+}
+
+pub fn parse_header_name(name: &str) -> Result<HeaderName, String> {
+    let name = HeaderName::try_from(name).map_err(|err| err.to_string())?;
+
+    // Rewriting these would break upgrading connections to websockets
+    if name == CONNECTION || name == UPGRADE {
+        return Err(format!("header '{name}' can not be rewritten"));
+    }
+
+    Ok(name)
+}
+
+pub fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = header
+        .split_once('=')
+        .ok_or_else(|| format!("invalid NAME=VALUE: no '=' found in '{header}'"))?;
+
+    let name = parse_header_name(name)?;
+    let value = HeaderValue::try_from(value).map_err(|err| err.to_string())?;
+
+    Ok((name, value))
+}
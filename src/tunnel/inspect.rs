@@ -0,0 +1,192 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+
+use bytes::Bytes;
+use hyper::Request;
+use hyper::body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use serde::Serialize;
+
+/// Amount of requests that are kept per tunnel
+const MAX_REQUESTS: usize = 20;
+/// Only the start of the body is kept, so large uploads do not end up in memory
+const MAX_BODY_SIZE: usize = 4 * 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedRequest {
+    method: String,
+    uri: String,
+    headers: Vec<(String, String)>,
+    body: String,
+    body_size: u64,
+    // Amount of bytes of the body that were captured
+    #[serde(skip)]
+    captured: usize,
+}
+
+impl CapturedRequest {
+    fn append_body(&mut self, data: &[u8]) {
+        self.body_size += data.len() as u64;
+
+        let remaining = MAX_BODY_SIZE.saturating_sub(self.captured);
+        if remaining > 0 {
+            let data = &data[..data.len().min(remaining)];
+            self.body.push_str(&String::from_utf8_lossy(data));
+            self.captured += data.len();
+        }
+    }
+
+    fn is_truncated(&self) -> bool {
+        self.body_size > self.captured as u64
+    }
+}
+
+impl fmt::Display for CapturedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}", self.method, self.uri)?;
+        for (name, value) in &self.headers {
+            writeln!(f, "{name}: {value}")?;
+        }
+
+        if !self.body.is_empty() {
+            // Replace control characters so binary bodies can not mess up the terminal
+            let body: String = self
+                .body
+                .chars()
+                .map(|c| {
+                    if c.is_control() && c != '\n' && c != '\t' {
+                        '.'
+                    } else {
+                        c
+                    }
+                })
+                .collect();
+
+            writeln!(f)?;
+            writeln!(f, "{}", body.trim_end())?;
+        }
+
+        if self.is_truncated() {
+            writeln!(f, "... ({} bytes in total)", self.body_size)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Keeps the last requests that were send through a tunnel
+#[derive(Debug, Clone, Default)]
+pub struct Inspector {
+    // The body is captured while it is being forwarded, so every request is shared with its body
+    requests: Arc<Mutex<VecDeque<Arc<Mutex<CapturedRequest>>>>>,
+}
+
+impl Inspector {
+    /// Record the request, the body is recorded as it streams through
+    pub(crate) fn capture<B>(&self, req: Request<B>) -> Request<InspectedBody<B>> {
+        let captured = CapturedRequest {
+            method: req.method().to_string(),
+            uri: req.uri().to_string(),
+            headers: req
+                .headers()
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                    )
+                })
+                .collect(),
+            body: String::new(),
+            body_size: 0,
+            captured: 0,
+        };
+        let captured = Arc::new(Mutex::new(captured));
+
+        {
+            let mut requests = self.requests.lock().expect("lock should not be poisoned");
+            if requests.len() >= MAX_REQUESTS {
+                requests.pop_front();
+            }
+            requests.push_back(captured.clone());
+        }
+
+        req.map(|inner| InspectedBody {
+            inner,
+            captured: Some(captured),
+        })
+    }
+
+    /// Copy of the captured requests, oldest first
+    pub(crate) fn requests(&self) -> Vec<CapturedRequest> {
+        self.requests
+            .lock()
+            .expect("lock should not be poisoned")
+            .iter()
+            .map(|captured| {
+                captured
+                    .lock()
+                    .expect("lock should not be poisoned")
+                    .clone()
+            })
+            .collect()
+    }
+}
+
+pin_project! {
+    /// Copies the start of the body into the captured request while it is being forwarded
+    pub struct InspectedBody<B> {
+        #[pin]
+        inner: B,
+        captured: Option<Arc<Mutex<CapturedRequest>>>,
+    }
+}
+
+impl<B> InspectedBody<B> {
+    /// Forward the body without capturing it
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            captured: None,
+        }
+    }
+}
+
+impl<B> Body for InspectedBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let project = self.project();
+
+        let frame = ready!(project.inner.poll_frame(cx));
+        if let Some(Ok(frame)) = &frame
+            && let Some(data) = frame.data_ref()
+            && let Some(captured) = project.captured
+        {
+            captured
+                .lock()
+                .expect("lock should not be poisoned")
+                .append_body(data);
+        }
+
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,35 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+/// Restricts which addresses can reach a tunnel, denied ranges take precedence over allowed
+/// ranges and an empty allow list allows everyone.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpFilter {
+    pub fn new(allow: Vec<IpNet>, deny: Vec<IpNet>) -> Self {
+        Self { allow, deny }
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        // IPv4 clients connecting to a dual stack socket show up as IPv4-mapped IPv6 addresses
+        let ip = ip.to_canonical();
+
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+pub fn parse_ip_net(net: &str) -> Result<IpNet, String> {
+    // Also accept single addresses
+    net.parse::<IpNet>()
+        .or_else(|_| net.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|err| format!("invalid address range '{net}': {err}"))
+}
@@ -1,45 +1,196 @@
+mod basic_auth;
+mod cors;
+mod headers;
+mod inspect;
+mod ip_filter;
+mod names;
 mod registry;
+mod retry;
+mod routes;
+mod sessions;
 mod tui;
+mod unix_socket;
+mod upstream_tls;
+mod webhook;
+mod websocket;
 
-use std::sync::Arc;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+pub use basic_auth::BasicAuth;
+pub use cors::Cors;
+pub use headers::{HeaderRule, HeaderRules, parse_header, parse_header_name};
+pub use inspect::{CapturedRequest, InspectedBody, Inspector};
+pub use ip_filter::{IpFilter, parse_ip_net};
+pub use names::{NameCharset, NameGenerator, NamePolicy, WordList, validate_tunnel_name};
 pub use registry::Registry;
-use registry::RegistryEntry;
-use russh::server::Handle;
-use tokio::sync::{RwLock, RwLockReadGuard};
-use tracing::trace;
-pub use tui::TunnelRow;
+pub(crate) use registry::{ReapReason, Reaped};
+use registry::{RegistryEntry, RegistryError};
+pub use retry::RetryPolicy;
+use routes::select_route;
+pub use routes::{Route, Upstream};
+use russh::Channel;
+use russh::server::{Handle, Msg};
+use serde::Serialize;
+pub(crate) use sessions::{SessionGuard, Sessions};
+use tokio::sync::{RwLock, RwLockReadGuard, watch};
+use tokio::time::Instant;
+use tracing::{debug, trace};
+pub use tui::{TableView, TunnelRow};
+pub use unix_socket::UnixSocket;
+pub use upstream_tls::{UpstreamTls, parse_server_name};
+use uuid::Uuid;
+pub(crate) use webhook::TunnelEvent;
+pub use webhook::Webhook;
+pub use websocket::WebSocketPolicy;
 
+use crate::helper::{Unit, format_duration};
 use crate::io::{Stats, TrackStats};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, thiserror::Error)]
+pub enum OpenError {
+    #[error(transparent)]
+    Channel(#[from] russh::Error),
+    #[error("TLS handshake with the forwarded port failed: {0}")]
+    Tls(std::io::Error),
+}
+
+/// Connection to the forwarded port, either plain or wrapped in TLS
+pub(crate) trait UpstreamIo: hyper::rt::Read + hyper::rt::Write + Unpin + Send {}
+
+impl<T> UpstreamIo for T where T: hyper::rt::Read + hyper::rt::Write + Unpin + Send {}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "owner", rename_all = "lowercase")]
 pub(crate) enum TunnelAccess {
     Private(String),
     Protected,
     Public,
 }
 
+/// Temporary access that reverts back to the previous access once it expires
+#[derive(Debug, Clone)]
+pub(crate) struct AccessExpiry {
+    expires: Instant,
+    previous: TunnelAccess,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct TunnelInner {
     handle: Handle,
     internal_address: String,
     port: u32,
+    owner: String,
+    // Session that forwarded the port, all of its tunnels are removed once it ends
+    session_id: Uuid,
     access: Arc<RwLock<TunnelAccess>>,
+    access_expiry: Arc<RwLock<Option<AccessExpiry>>>,
+    header_rules: Arc<RwLock<HeaderRules>>,
+    basic_auth: Arc<RwLock<Option<BasicAuth>>>,
+    cors: Arc<RwLock<Option<Cors>>>,
+    routes: Arc<RwLock<Vec<Route>>>,
+    upstream: Arc<RwLock<Option<Upstream>>>,
+    ip_filter: Arc<RwLock<IpFilter>>,
+    websocket: Arc<RwLock<WebSocketPolicy>>,
+    // Users that can see the stats of the tunnel, this does not give them access
+    viewers: Arc<RwLock<Vec<String>>>,
+    // Only set when the owner wants to inspect the requests
+    inspector: Arc<RwLock<Option<Inspector>>>,
+    http2: Arc<RwLock<bool>>,
+    // Overrides how long the server waits for the forwarded port to respond, `Some(None)` waits
+    // indefinitely
+    upstream_timeout: Arc<RwLock<Option<Option<Duration>>>>,
+    // Named auth provider that protects the tunnel, the default provider if not set
+    auth_provider: Arc<RwLock<Option<String>>>,
+    compress: Arc<RwLock<bool>>,
+    upstream_tls: Arc<RwLock<Option<UpstreamTls>>>,
+    // Socket the client forwards the port to, only used to show it to the owner
+    unix_socket: Arc<RwLock<Option<String>>>,
+    // Free text set by the owner to tell tunnels apart, does not affect anything else
+    label: Arc<RwLock<Option<String>>>,
+    retry_policy: RetryPolicy,
     stats: Arc<Stats>,
+    // Kept when the tunnel is renamed or registered again
+    created: Instant,
+    // Shared with the registry entry, so an idle tunnel can be removed by the registry
+    address: Arc<Mutex<Option<String>>>,
+    // A tunnel that is registered again should not immediately be closed for being idle
+    registered: Arc<Mutex<Instant>>,
+    // Set when the server closes the tunnel, cleared once it is registered again
+    reaped: Arc<watch::Sender<Option<Reaped>>>,
+    // Join tunnels of the same owner that use the same address instead of failing to register
+    pool: Arc<AtomicBool>,
+    // Other tunnels that joined the pool, only filled in on the tunnel that is in the registry
+    backends: Arc<Mutex<Vec<TunnelInner>>>,
+    next_backend: Arc<AtomicUsize>,
 }
 
 impl TunnelInner {
-    pub(crate) async fn open(&self) -> Result<TrackStats, russh::Error> {
-        trace!("Opening tunnel");
+    /// Open a connection to the forwarded port, for a pool the tunnels take turns and tunnels
+    /// that fail are skipped
+    pub(crate) async fn open(&self, path: &str) -> Result<Box<dyn UpstreamIo>, OpenError> {
+        let upstream = {
+            let routes = self.routes.read().await;
+            let upstream = self.upstream.read().await;
+            select_route(&routes, path).or(upstream.as_ref()).cloned()
+        };
+
+        let mut error = None;
+        let mut opened = None;
+        for backend in self.backends() {
+            let (address, port) = match &upstream {
+                Some(upstream) => {
+                    let (address, port) = upstream.target(&backend.internal_address);
+                    (address.to_owned(), port)
+                }
+                None => (backend.internal_address.clone(), backend.port),
+            };
+
+            match backend.open_channel(&address, port).await {
+                Ok(channel) => {
+                    opened = Some((channel, address, backend.stats));
+                    break;
+                }
+                Err(err) => {
+                    debug!(
+                        address,
+                        port, "Failed to open tunnel, trying the next one: {err}"
+                    );
+                    error = Some(err);
+                }
+            }
+        }
+        let Some((channel, address, stats)) = opened else {
+            return Err(error
+                .expect("pool should contain at least one tunnel")
+                .into());
+        };
+
+        let io = TrackStats::new(channel.into_stream(), stats);
+        let upstream_tls = self.upstream_tls.read().await.clone();
+        match upstream_tls {
+            Some(upstream_tls) => {
+                let io = upstream_tls
+                    .connect(&address, io)
+                    .await
+                    .map_err(OpenError::Tls)?;
+                Ok(Box::new(io))
+            }
+            None => Ok(Box::new(io)),
+        }
+    }
+
+    async fn open_channel(&self, address: &str, port: u32) -> Result<Channel<Msg>, russh::Error> {
+        trace!(address, port, "Opening tunnel");
         self.stats.add_connection();
         let channel = self
-            .handle
-            .channel_open_forwarded_tcpip(
-                &self.internal_address,
-                self.port,
-                &self.internal_address,
-                self.port,
-            )
+            .retry_policy
+            .run(|| {
+                self.handle
+                    .channel_open_forwarded_tcpip(address, port, address, port)
+            })
             .await
             .inspect_err(|_| {
                 self.stats.set_failed(true);
@@ -47,7 +198,45 @@ impl TunnelInner {
 
         self.stats.set_failed(false);
 
-        Ok(TrackStats::new(channel.into_stream(), self.stats.clone()))
+        Ok(channel)
+    }
+
+    /// The tunnel itself followed by the other tunnels in its pool
+    fn members(&self) -> Vec<TunnelInner> {
+        let mut members = vec![self.clone()];
+        members.extend(
+            self.backends
+                .lock()
+                .expect("lock should not be poisoned")
+                .iter()
+                .cloned(),
+        );
+        members
+    }
+
+    /// Members of the pool in the order they should be tried, every call starts at the next
+    /// member. Members that failed the last time they were used are tried last.
+    fn backends(&self) -> Vec<TunnelInner> {
+        let mut backends = self.members();
+        let next = self.next_backend.fetch_add(1, Ordering::Relaxed) % backends.len();
+        backends.rotate_left(next);
+        // The sort is stable, so the order of the healthy members is kept
+        backends.sort_by_key(|backend| backend.stats.failed());
+        backends
+    }
+
+    fn is_pooled(&self) -> bool {
+        self.pool.load(Ordering::Relaxed)
+    }
+
+    /// Whether the other tunnel is allowed to join the pool of this tunnel
+    fn can_pool_with(&self, other: &TunnelInner) -> bool {
+        self.is_pooled() && other.is_pooled() && self.owner == other.owner
+    }
+
+    /// Check if the other tunnel is this tunnel or one of the other tunnels in its pool
+    fn contains(&self, other: &TunnelInner) -> bool {
+        self.members().iter().any(|member| member.is_same(other))
     }
 
     pub(crate) async fn is_public(&self) -> bool {
@@ -57,6 +246,261 @@ impl TunnelInner {
     pub(crate) async fn get_access(&self) -> RwLockReadGuard<'_, TunnelAccess> {
         self.access.read().await
     }
+
+    /// Revert to the previous access, unless the access was changed again since the expiry was
+    /// set
+    async fn expire_access(&self, expires: Instant) -> Option<TunnelAccess> {
+        let mut expiry = self.access_expiry.write().await;
+        if expiry.as_ref()?.expires != expires {
+            return None;
+        }
+
+        let previous = expiry.take()?.previous;
+        *self.access.write().await = previous.clone();
+
+        Some(previous)
+    }
+
+    /// Make a public tunnel protected, including tunnels that are temporarily public. Returns
+    /// false if the tunnel was not public.
+    async fn revoke_public(&self) -> bool {
+        let mut expiry = self.access_expiry.write().await;
+        let mut access = self.access.write().await;
+        if !matches!(*access, TunnelAccess::Public) {
+            return false;
+        }
+
+        *expiry = None;
+        *access = TunnelAccess::Protected;
+        true
+    }
+
+    /// Give a private tunnel to another user, only the user that currently owns it can do this.
+    /// This is not necessarily the user that created the tunnel, as it might have been transferred
+    /// before.
+    async fn transfer(&self, name: &str, user: &str, new_owner: &str) -> Result<(), RegistryError> {
+        let mut access = self.access.write().await;
+        match &mut *access {
+            TunnelAccess::Private(owner) if owner == user => {
+                *owner = new_owner.into();
+                Ok(())
+            }
+            TunnelAccess::Private(_) => Err(RegistryError::NotOwner(name.into())),
+            _ if !self.is_owned_by(user) => Err(RegistryError::NotOwner(name.into())),
+            _ => Err(RegistryError::NotPrivate(name.into())),
+        }
+    }
+
+    pub(crate) async fn get_header_rules(&self) -> RwLockReadGuard<'_, HeaderRules> {
+        self.header_rules.read().await
+    }
+
+    pub(crate) async fn get_basic_auth(&self) -> RwLockReadGuard<'_, Option<BasicAuth>> {
+        self.basic_auth.read().await
+    }
+
+    pub(crate) async fn get_cors(&self) -> RwLockReadGuard<'_, Option<Cors>> {
+        self.cors.read().await
+    }
+
+    pub(crate) async fn get_ip_filter(&self) -> RwLockReadGuard<'_, IpFilter> {
+        self.ip_filter.read().await
+    }
+
+    pub(crate) async fn get_websocket_policy(&self) -> RwLockReadGuard<'_, WebSocketPolicy> {
+        self.websocket.read().await
+    }
+
+    pub(crate) async fn get_inspector(&self) -> Option<Inspector> {
+        self.inspector.read().await.clone()
+    }
+
+    /// Whether the forwarded port expects HTTP/2 instead of HTTP/1.1
+    pub(crate) async fn is_http2(&self) -> bool {
+        *self.http2.read().await
+    }
+
+    pub(crate) async fn get_upstream_timeout(&self) -> Option<Option<Duration>> {
+        *self.upstream_timeout.read().await
+    }
+
+    pub(crate) async fn get_auth_provider(&self) -> Option<String> {
+        self.auth_provider.read().await.clone()
+    }
+
+    pub(crate) async fn has_upstream_tls(&self) -> bool {
+        self.upstream_tls.read().await.is_some()
+    }
+
+    /// Time since the tunnel was created
+    pub(crate) fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    /// Time since the last request, or since the tunnel was registered if that is more recent
+    pub(crate) fn idle(&self) -> Duration {
+        let registered = self
+            .registered
+            .lock()
+            .expect("lock should not be poisoned")
+            .elapsed();
+
+        self.stats
+            .idle()
+            .map_or(registered, |idle| idle.min(registered))
+    }
+
+    fn mark_registered(&self) {
+        *self.registered.lock().expect("lock should not be poisoned") = Instant::now();
+        self.reaped
+            .send_if_modified(|reaped| reaped.take().is_some());
+    }
+
+    /// Whether responses should be compressed for clients that support it
+    pub(crate) async fn is_compressed(&self) -> bool {
+        *self.compress.read().await
+    }
+
+    pub(crate) fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Check if both refer to the same tunnel
+    pub(crate) fn is_same(&self, other: &TunnelInner) -> bool {
+        Arc::ptr_eq(&self.stats, &other.stats)
+    }
+
+    /// Public and protected tunnels are visible to all users, private tunnels only to their owner
+    /// and viewers
+    pub(crate) async fn is_visible_to(&self, user: impl AsRef<str>) -> bool {
+        if self.is_owned_by(&user) {
+            return true;
+        }
+
+        match &*self.access.read().await {
+            TunnelAccess::Private(owner) if owner == user.as_ref() => true,
+            TunnelAccess::Private(_) => self.is_viewable_by(&user).await,
+            TunnelAccess::Protected | TunnelAccess::Public => true,
+        }
+    }
+
+    pub(crate) async fn is_viewable_by(&self, user: impl AsRef<str>) -> bool {
+        self.viewers
+            .read()
+            .await
+            .iter()
+            .any(|viewer| viewer == user.as_ref())
+    }
+
+    pub(crate) fn is_owned_by(&self, user: impl AsRef<str>) -> bool {
+        self.owner == user.as_ref()
+    }
+}
+
+/// Machine readable description of a tunnel
+#[derive(Debug, Serialize)]
+pub(crate) struct TunnelInfo {
+    name: String,
+    address: Option<String>,
+    port: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unix_socket: Option<String>,
+    access: TunnelAccess,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+impl fmt::Display for TunnelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let address = self.address.as_deref().unwrap_or("-");
+        match &self.unix_socket {
+            Some(path) => write!(f, "{} {address} (unix socket {path}, ", self.name)?,
+            None => write!(f, "{} {address} (port {}, ", self.name, self.port)?,
+        }
+        match &self.access {
+            TunnelAccess::Private(owner) => write!(f, "private to {owner})")?,
+            TunnelAccess::Protected => write!(f, "protected)")?,
+            TunnelAccess::Public => write!(f, "public)")?,
+        }
+        if let Some(label) = &self.label {
+            write!(f, " [{label}]")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Stats of a tunnel that is shared with a viewer
+#[derive(Debug, Serialize)]
+pub(crate) struct ViewedTunnel {
+    name: String,
+    owner: String,
+    connections: usize,
+    rx_bytes: usize,
+    tx_bytes: usize,
+    requests: usize,
+}
+
+impl fmt::Display for ViewedTunnel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (owned by {}, {} connections, rx {}, tx {}, {} requests)",
+            self.name,
+            self.owner,
+            self.connections,
+            Unit::binary(self.rx_bytes, "B"),
+            Unit::binary(self.tx_bytes, "B"),
+            self.requests
+        )
+    }
+}
+
+/// Tunnel as listed by the list command, includes tunnels of other users
+#[derive(Debug, Serialize)]
+pub(crate) struct ListedTunnel {
+    name: String,
+    address: String,
+    owner: String,
+    access: TunnelAccess,
+    connections: usize,
+    rx_bytes: usize,
+    tx_bytes: usize,
+    requests: usize,
+    age_secs: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+impl ListedTunnel {
+    pub(crate) fn header() -> [&'static str; 10] {
+        [
+            "NAME", "ADDRESS", "OWNER", "ACCESS", "CONN", "RX", "TX", "REQS", "AGE", "LABEL",
+        ]
+    }
+
+    /// Columns of the row, none of them contain whitespace so the output is easy to parse. The
+    /// label is last, since it is the only column that can contain whitespace.
+    pub(crate) fn columns(&self) -> [String; 10] {
+        let access = match &self.access {
+            TunnelAccess::Private(owner) => format!("private:{owner}"),
+            TunnelAccess::Protected => "protected".into(),
+            TunnelAccess::Public => "public".into(),
+        };
+
+        [
+            self.name.clone(),
+            self.address.clone(),
+            self.owner.clone(),
+            access,
+            self.connections.to_string(),
+            self.rx_bytes.to_string(),
+            self.tx_bytes.to_string(),
+            self.requests.to_string(),
+            format_duration(Duration::from_secs(self.age_secs)),
+            self.label.clone().unwrap_or_else(|| "-".into()),
+        ]
+    }
 }
 
 #[derive(Debug)]
@@ -68,45 +512,262 @@ pub(crate) struct Tunnel {
 }
 
 impl Tunnel {
-    pub async fn create(
-        registry: &mut Registry,
+    /// Create a tunnel for the forwarded address, it only gets an address once it is registered.
+    /// The tunnel is owned by the user of the session and removed once the session ends.
+    pub(crate) fn new(
+        registry: &Registry,
         handle: Handle,
         internal_address: impl Into<String>,
         port: u32,
+        owner: &SessionGuard,
         access: TunnelAccess,
+        retry_policy: RetryPolicy,
     ) -> Self {
-        let mut tunnel = Self {
+        let address: Arc<Mutex<Option<String>>> = Default::default();
+        Self {
             inner: TunnelInner {
                 handle,
                 internal_address: internal_address.into(),
                 port,
+                owner: owner.user().into(),
+                session_id: owner.id(),
                 access: Arc::new(RwLock::new(access)),
+                access_expiry: Default::default(),
+                header_rules: Default::default(),
+                basic_auth: Default::default(),
+                cors: Default::default(),
+                routes: Default::default(),
+                upstream: Default::default(),
+                ip_filter: Default::default(),
+                websocket: Default::default(),
+                viewers: Default::default(),
+                inspector: Default::default(),
+                http2: Default::default(),
+                upstream_timeout: Default::default(),
+                auth_provider: Default::default(),
+                compress: Default::default(),
+                upstream_tls: Default::default(),
+                unix_socket: Default::default(),
+                label: Default::default(),
+                retry_policy,
                 stats: Default::default(),
+                created: Instant::now(),
+                address: address.clone(),
+                registered: Arc::new(Mutex::new(Instant::now())),
+                reaped: Arc::new(watch::Sender::new(None)),
+                pool: Default::default(),
+                backends: Default::default(),
+                next_backend: Default::default(),
             },
             registry: registry.clone(),
-            registry_entry: RegistryEntry::new(registry.clone()),
-        };
+            registry_entry: RegistryEntry::new(address),
+        }
+    }
 
-        registry.register(&mut tunnel).await;
+    /// Name the tunnel before registering it, without a name the name is picked based on the
+    /// forwarded address
+    pub async fn pick_name(&mut self, name: Option<String>) {
+        if let Some(name) = name {
+            self.registry_entry.name = name;
+        }
+
+        let registry = self.registry.clone();
+        registry.pick_name(self).await;
+    }
 
-        tunnel
+    /// Register the tunnel for the first time. Requests are forwarded as soon as the tunnel is
+    /// registered, so all settings have to be applied before calling this.
+    pub async fn register(&mut self) {
+        let mut registry = self.registry.clone();
+        registry.register(self).await;
     }
 
     pub async fn set_access(&self, access: TunnelAccess) {
-        *self.inner.access.write().await = access;
+        let mut expiry = self.inner.access_expiry.write().await;
+        *expiry = None;
+        *self.inner.access.write().await = access.clone();
+        drop(expiry);
+
+        self.registry.notify(TunnelEvent::AccessChanged {
+            name: self.get_name().into(),
+            access,
+        });
+    }
+
+    pub async fn get_access(&self) -> TunnelAccess {
+        self.inner.access.read().await.clone()
+    }
+
+    /// Temporarily change the access, once it expires the tunnel goes back to the access it had
+    /// before
+    pub async fn set_access_until(&self, access: TunnelAccess, expires: Instant) {
+        let mut expiry = self.inner.access_expiry.write().await;
+        let mut current = self.inner.access.write().await;
+        // When extending temporary access we still want to end up at the original access
+        let previous = match expiry.take() {
+            Some(expiry) => expiry.previous,
+            None => current.clone(),
+        };
+        *expiry = Some(AccessExpiry { expires, previous });
+        *current = access.clone();
+        drop(current);
+        drop(expiry);
+
+        self.registry.notify(TunnelEvent::AccessChanged {
+            name: self.get_name().into(),
+            access,
+        });
+
+        let inner = self.inner.clone();
+        let registry = self.registry.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(expires).await;
+
+            if let Some(access) = inner.expire_access(expires).await {
+                trace!(?access, "Temporary access expired");
+                // The tunnel might have been renamed or removed in the meantime
+                if let Some(name) = registry.name_of(&inner).await {
+                    registry.notify(TunnelEvent::AccessChanged { name, access });
+                }
+            }
+        });
+    }
+
+    pub async fn set_header_rules(&self, rules: HeaderRules) {
+        *self.inner.header_rules.write().await = rules;
+    }
+
+    pub async fn set_basic_auth(&self, basic_auth: Option<BasicAuth>) {
+        *self.inner.basic_auth.write().await = basic_auth;
+    }
+
+    pub async fn set_cors(&self, cors: Option<Cors>) {
+        *self.inner.cors.write().await = cors;
+    }
+
+    pub async fn set_routes(&self, routes: Vec<Route>) {
+        *self.inner.routes.write().await = routes;
+    }
+
+    pub async fn set_upstream(&self, upstream: Option<Upstream>) {
+        *self.inner.upstream.write().await = upstream;
+    }
+
+    pub async fn set_ip_filter(&self, ip_filter: IpFilter) {
+        *self.inner.ip_filter.write().await = ip_filter;
+    }
+
+    pub async fn set_websocket_policy(&self, policy: WebSocketPolicy) {
+        *self.inner.websocket.write().await = policy;
+    }
+
+    pub async fn set_viewers(&self, viewers: Vec<String>) {
+        *self.inner.viewers.write().await = viewers;
+    }
+
+    /// Start or stop capturing requests, requests that were already captured are kept as long as
+    /// inspecting stays enabled
+    pub async fn set_inspect(&self, inspect: bool) {
+        let mut inspector = self.inner.inspector.write().await;
+        match (inspect, inspector.is_some()) {
+            (true, false) => *inspector = Some(Inspector::default()),
+            (false, true) => *inspector = None,
+            _ => {}
+        }
+    }
+
+    pub async fn set_http2(&self, http2: bool) {
+        *self.inner.http2.write().await = http2;
+    }
+
+    pub async fn set_upstream_timeout(&self, timeout: Option<Option<Duration>>) {
+        *self.inner.upstream_timeout.write().await = timeout;
+    }
+
+    pub async fn set_auth_provider(&self, provider: Option<String>) {
+        *self.inner.auth_provider.write().await = provider;
+    }
+
+    pub async fn set_compress(&self, compress: bool) {
+        *self.inner.compress.write().await = compress;
+    }
+
+    pub async fn set_upstream_tls(&self, upstream_tls: Option<UpstreamTls>) {
+        *self.inner.upstream_tls.write().await = upstream_tls;
+    }
+
+    /// Only affects registering the tunnel, the tunnel stays in the pool it already joined
+    pub fn set_pool(&self, pool: bool) {
+        self.inner.pool.store(pool, Ordering::Relaxed);
+    }
+
+    pub async fn set_label(&self, label: Option<String>) {
+        *self.inner.label.write().await = label;
+    }
+
+    pub async fn get_label(&self) -> Option<String> {
+        self.inner.label.read().await.clone()
     }
 
-    pub fn get_address(&self) -> Option<&String> {
+    pub async fn set_unix_socket(&self, path: Option<String>) {
+        *self.inner.unix_socket.write().await = path;
+    }
+
+    pub fn get_address(&self) -> Option<String> {
         self.registry_entry.get_address()
     }
 
+    /// Url that visitors use to reach the tunnel, if it has an address
+    pub fn get_url(&self) -> Option<String> {
+        self.get_address()
+            .map(|address| self.registry.url(&address))
+    }
+
+    pub fn get_name(&self) -> &str {
+        self.registry_entry.get_name()
+    }
+
     pub async fn set_name(&mut self, name: impl Into<String>) {
         let mut registry = self.registry.clone();
         registry.rename(self, name).await;
     }
 
+    pub async fn info(&self) -> TunnelInfo {
+        TunnelInfo {
+            name: self.registry_entry.get_name().into(),
+            address: self.get_url(),
+            port: self.inner.port,
+            unix_socket: self.inner.unix_socket.read().await.clone(),
+            access: self.inner.access.read().await.clone(),
+            label: self.inner.label.read().await.clone(),
+        }
+    }
+
+    /// Receives the name of the tunnel whenever it is closed by the server
+    pub fn watch_reaped(&self) -> watch::Receiver<Option<Reaped>> {
+        self.inner.reaped.subscribe()
+    }
+
+    /// Remove the tunnel on request of the owner, unlike when the session ends it is not
+    /// reported as offline afterwards
+    pub async fn close(mut self) {
+        let registry = self.registry.clone();
+        registry.remove_closed(&mut self).await;
+    }
+
     pub async fn retry(&mut self) {
         let mut registry = self.registry.clone();
         registry.register(self).await;
     }
+
+    /// Keep retrying in the background until the tunnel is registered, does nothing if the
+    /// tunnel already has an address
+    pub fn retry_in_background(&mut self) {
+        if self.get_address().is_some() {
+            return;
+        }
+
+        let registry = self.registry.clone();
+        registry.retry_in_background(self);
+    }
 }
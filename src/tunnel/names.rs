@@ -0,0 +1,206 @@
+use std::fmt::Debug;
+use std::path::Path;
+use std::str::FromStr;
+
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+
+use crate::helper::ANIMALS;
+
+/// Source of names for tunnels that did not request a specific name
+pub trait NameGenerator: Debug + Send + Sync {
+    fn generate(&self) -> String;
+}
+
+/// Picks a random word from a list
+#[derive(Debug, Clone)]
+pub struct WordList {
+    words: Vec<String>,
+}
+
+impl WordList {
+    /// Builtin list of animal names
+    pub fn animals() -> Self {
+        Self {
+            words: ANIMALS.lines().map(Into::into).collect(),
+        }
+    }
+
+    /// Read a list of words from a file, containing one word per line
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let words: Vec<String> = std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|word| !word.is_empty())
+            .map(Into::into)
+            .collect();
+
+        if words.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "word list is empty",
+            ));
+        }
+
+        Ok(Self { words })
+    }
+}
+
+impl NameGenerator for WordList {
+    fn generate(&self) -> String {
+        self.words
+            .choose(&mut OsRng)
+            .expect("List should not be empty")
+            .clone()
+    }
+}
+
+/// Maximum length of a DNS label
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// Check that the name is a valid DNS label (RFC 1123), this is required as the name becomes a
+/// subdomain
+pub fn validate_tunnel_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Name can not be empty".into());
+    }
+    if name.len() > MAX_LABEL_LENGTH {
+        return Err(format!(
+            "Name is too long: {name}, at most {MAX_LABEL_LENGTH} characters are allowed"
+        ));
+    }
+    if name.starts_with('-') || name.ends_with('-') {
+        return Err("Name can not start or end with a dash".into());
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(format!(
+            "Invalid name: {name}, only lowercase letters, digits, and dashes are allowed"
+        ));
+    }
+
+    Ok(())
+}
+
+/// What to do with forwarded addresses that are not valid as the name of a tunnel
+#[derive(Debug, Clone, Copy, Default)]
+pub enum NamePolicy {
+    /// Refuse to forward the address
+    #[default]
+    Reject,
+    /// Lowercase the address and replace the characters that are not allowed with dashes
+    Sanitize,
+}
+
+impl NamePolicy {
+    /// Name to use for the forwarded address, every part of the address has to be a valid name.
+    /// Only the first part can be `*`, to request a wildcard tunnel. Names with multiple parts
+    /// have to end in the name of the `owner`, unless no owner is given because every tunnel is
+    /// already below the name of the user.
+    pub fn apply(&self, address: &str, owner: Option<&str>) -> Result<String, String> {
+        let (wildcard, address) = match address.strip_prefix("*.") {
+            Some(address) => (true, address),
+            None => (false, address),
+        };
+
+        let labels = address
+            .split('.')
+            .map(|label| match self {
+                Self::Reject => validate_tunnel_name(label).map(|_| label.to_owned()),
+                Self::Sanitize => {
+                    let label = sanitize(label);
+                    validate_tunnel_name(&label).map(|_| label)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(owner) = owner
+            && (wildcard || labels.len() > 1)
+            && labels.last().is_some_and(|parent| parent != owner)
+        {
+            return Err(format!(
+                "Names with multiple labels are only allowed below your own name, e.g. \
+                <name>.{owner} or *.{owner}"
+            ));
+        }
+
+        let name = labels.join(".");
+        Ok(if wildcard { format!("*.{name}") } else { name })
+    }
+}
+
+fn sanitize(label: &str) -> String {
+    let label: String = label
+        .chars()
+        .map(|c| c.to_ascii_lowercase())
+        .map(|c| {
+            if c.is_ascii_lowercase() || c.is_ascii_digit() {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+
+    let label = label.trim_matches('-');
+    label[..label.len().min(MAX_LABEL_LENGTH)]
+        .trim_end_matches('-')
+        .to_owned()
+}
+
+impl FromStr for NamePolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "reject" => Ok(Self::Reject),
+            "sanitize" => Ok(Self::Sanitize),
+            other => Err(format!(
+                "unknown policy '{other}', expected reject or sanitize"
+            )),
+        }
+    }
+}
+
+/// Characters that users can type when renaming a tunnel
+#[derive(Debug, Clone, Copy, Default)]
+pub enum NameCharset {
+    /// Lowercase letters, digits, and dashes
+    #[default]
+    Ascii,
+    /// Letters and digits of any script, the name is converted to punycode when accepted
+    Unicode,
+}
+
+impl NameCharset {
+    pub fn allows(&self, c: char) -> bool {
+        match self {
+            Self::Ascii => c.is_ascii_alphanumeric() || c == '-',
+            Self::Unicode => c.is_alphanumeric() || c == '-',
+        }
+    }
+
+    /// Turn the name into a valid DNS label, returns the reason if that is not possible
+    pub fn to_label(&self, name: &str) -> Result<String, String> {
+        let label = idna::domain_to_ascii(name).map_err(|_| format!("Invalid name: {name}"))?;
+        validate_tunnel_name(&label)?;
+
+        Ok(label)
+    }
+}
+
+impl FromStr for NameCharset {
+    type Err = String;
+
+    fn from_str(charset: &str) -> Result<Self, Self::Err> {
+        match charset {
+            "ascii" => Ok(Self::Ascii),
+            "unicode" => Ok(Self::Unicode),
+            other => Err(format!(
+                "unknown charset '{other}', expected ascii or unicode"
+            )),
+        }
+    }
+}
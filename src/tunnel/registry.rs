@@ -1,129 +1,1008 @@
-use std::collections::HashMap;
 use std::collections::hash_map::Entry;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use tokio::sync::RwLock;
-use tracing::trace;
+use tokio::select;
+use tokio::sync::{RwLock, broadcast, watch};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, trace};
+use uuid::Uuid;
 
-use super::TunnelInner;
-use crate::helper::get_animal_name;
+use super::webhook::TunnelEvent;
+use super::{
+    CapturedRequest, ListedTunnel, NameGenerator, Sessions, TunnelAccess, TunnelInner,
+    ViewedTunnel, Webhook,
+};
 use crate::tunnel::Tunnel;
 
-#[derive(Debug)]
+/// Amount of random names that are tried before falling back to adding a numbered suffix
+const MAX_NAME_ATTEMPTS: usize = 16;
+
+/// Events that are kept for subscribers that are lagging behind, after that they miss events
+const EVENTS_CAPACITY: usize = 256;
+
+/// Delay before the first background retry, doubles after every failed attempt
+const RETRY_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How often tunnels are checked for reaching their maximum lifetime
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, thiserror::Error)]
+pub enum RegistryError {
+    #[error("Unknown tunnel: {0}")]
+    UnknownTunnel(String),
+    #[error("Tunnel '{0}' is not owned by you")]
+    NotOwner(String),
+    #[error("Tunnel '{0}' is not private")]
+    NotPrivate(String),
+    #[error("Tunnel '{0}' is not being inspected, enable it using --inspect")]
+    NotInspected(String),
+}
+
+/// Why the server closed a tunnel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReapReason {
+    // Can be registered again by the owner
+    Idle,
+    // Reached the maximum lifetime, can never be registered again
+    Expired,
+}
+
+/// Tunnel that was closed by the server instead of by its owner
+#[derive(Debug, Clone)]
+pub(crate) struct Reaped {
+    pub(crate) name: String,
+    pub(crate) reason: ReapReason,
+}
+
+/// Progress of a tunnel that is being registered in the background
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RetryStatus {
+    // Cancelled once the retry stops, either because it succeeded or because it was stopped
+    token: CancellationToken,
+    attempts: Arc<AtomicUsize>,
+}
+
+impl RetryStatus {
+    pub(crate) fn is_active(&self) -> bool {
+        !self.token.is_cancelled()
+    }
+
+    pub(crate) fn attempts(&self) -> usize {
+        self.attempts.load(Ordering::Relaxed)
+    }
+
+    fn stop(&self) {
+        self.token.cancel();
+    }
+}
+
+#[derive(Debug, Default)]
 pub(crate) struct RegistryEntry {
-    registry: Registry,
     name: String,
-    address: Option<String>,
+    // Shared with the background retry, which fills it in once the tunnel is registered. The
+    // lock is never held across an await.
+    address: Arc<Mutex<Option<String>>>,
+    retry: Option<RetryStatus>,
 }
 
 impl RegistryEntry {
-    pub(crate) fn new(registry: Registry) -> Self {
+    /// The address is shared with the tunnel, so the registry can also clear it
+    pub(super) fn new(address: Arc<Mutex<Option<String>>>) -> Self {
         Self {
-            registry,
-            name: Default::default(),
-            address: Default::default(),
+            address,
+            ..Default::default()
         }
     }
 
-    pub(crate) fn get_address(&self) -> Option<&String> {
-        self.address.as_ref()
+    pub(crate) fn get_address(&self) -> Option<String> {
+        self.lock_address().clone()
     }
 
     pub(crate) fn get_name(&self) -> &str {
         &self.name
     }
+
+    pub(crate) fn shared_address(&self) -> Arc<Mutex<Option<String>>> {
+        self.address.clone()
+    }
+
+    pub(crate) fn retry_status(&self) -> Option<RetryStatus> {
+        self.retry.clone()
+    }
+
+    pub(crate) fn is_retrying(&self) -> bool {
+        self.retry.as_ref().is_some_and(RetryStatus::is_active)
+    }
+
+    fn lock_address(&self) -> std::sync::MutexGuard<'_, Option<String>> {
+        self.address.lock().expect("lock should not be poisoned")
+    }
+
+    fn stop_retry(&mut self) {
+        if let Some(retry) = self.retry.take() {
+            retry.stop();
+        }
+    }
 }
 
-impl Drop for RegistryEntry {
+impl Drop for Tunnel {
     fn drop(&mut self) {
-        trace!(
-            name = self.name,
-            address = self.address,
-            "Dropping registry entry"
-        );
+        // Otherwise the background retry could still register the tunnel after it is gone. The
+        // tunnel itself is removed from the registry once the session has ended.
+        self.registry_entry.stop_retry();
+        trace!(name = self.registry_entry.name, "Dropping tunnel");
+    }
+}
 
-        if let Some(address) = self.address.take() {
-            let registry = self.registry.clone();
-            tokio::spawn(async move {
-                registry.tunnels.write().await.remove(&address);
-            });
+/// Outcome of trying to claim an address for a tunnel
+enum Claim {
+    Registered,
+    // The address was already used by a pool that the tunnel joined
+    Joined,
+    InUse,
+}
+
+/// Claim the address for the tunnel, if the address is used by a pool the tunnel is allowed to
+/// join the tunnel is added to the pool instead
+fn claim(tunnels: &mut HashMap<String, TunnelInner>, address: &str, tunnel: &TunnelInner) -> Claim {
+    match tunnels.entry(address.to_owned()) {
+        Entry::Vacant(e) => {
+            e.insert(tunnel.clone());
+            Claim::Registered
+        }
+        Entry::Occupied(e) if e.get().can_pool_with(tunnel) => {
+            e.get()
+                .backends
+                .lock()
+                .expect("lock should not be poisoned")
+                .push(tunnel.clone());
+            Claim::Joined
+        }
+        Entry::Occupied(_) => Claim::InUse,
+    }
+}
+
+/// Take the tunnel off the address, when the tunnel is part of a pool the rest of the pool keeps
+/// the address. Returns true if the address is no longer in use.
+fn detach(tunnels: &mut HashMap<String, TunnelInner>, address: &str, tunnel: &TunnelInner) -> bool {
+    let Some(registered) = tunnels.get(address) else {
+        trace!(address, "Address is not used by this tunnel");
+        return false;
+    };
+
+    if !registered.is_same(tunnel) {
+        let mut backends = registered
+            .backends
+            .lock()
+            .expect("lock should not be poisoned");
+        if backends.iter().any(|backend| backend.is_same(tunnel)) {
+            trace!(address, "Leaving pool");
+            backends.retain(|backend| !backend.is_same(tunnel));
+        } else {
+            trace!(address, "Address is not used by this tunnel");
+        }
+
+        return false;
+    }
+
+    let mut backends = std::mem::take(
+        &mut *registered
+            .backends
+            .lock()
+            .expect("lock should not be poisoned"),
+    )
+    .into_iter();
+    match backends.next() {
+        // The settings of the tunnel that takes over are used from now on
+        Some(next) => {
+            trace!(address, "Handing pool over to the next tunnel");
+            *next.backends.lock().expect("lock should not be poisoned") = backends.collect();
+            tunnels.insert(address.to_owned(), next);
+            false
+        }
+        None => {
+            tunnels.remove(address);
+            true
         }
     }
 }
 
+/// Addresses of tunnels that went away because the session of their owner ended
+#[derive(Debug)]
+struct OfflineTunnels {
+    // Remembered until the address is closed by its owner if not set
+    grace: Option<Duration>,
+    since: RwLock<HashMap<String, Instant>>,
+}
+
+impl OfflineTunnels {
+    fn is_expired(&self, since: Instant) -> bool {
+        self.grace.is_some_and(|grace| since.elapsed() >= grace)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Registry {
     tunnels: Arc<RwLock<HashMap<String, TunnelInner>>>,
     domain: String,
+    names: Arc<dyn NameGenerator>,
+    webhook: Option<Webhook>,
+    events: broadcast::Sender<TunnelEvent>,
+    // Put the tunnels of every user below their own subdomain
+    namespace_by_user: bool,
+    offline: Option<Arc<OfflineTunnels>>,
+    // Tunnels are closed once they exist for this long, no matter if they are used
+    max_lifetime: Option<Duration>,
+    // Forwarded addresses that do not name the tunnel, stored normalized
+    generated_name_addresses: Arc<HashSet<String>>,
+    sessions: Sessions,
+    // Notifies sessions that public access was revoked, so new tunnels are not public either
+    public_revoked: Arc<watch::Sender<()>>,
+    // Scheme of the urls handed to users, https when the web service terminates TLS
+    scheme: &'static str,
+}
+
+/// Addresses are compared case insensitive and IPv6 addresses can be written with brackets
+fn normalize_address(address: &str) -> String {
+    let address = address.trim();
+    address
+        .strip_prefix('[')
+        .and_then(|address| address.strip_suffix(']'))
+        .unwrap_or(address)
+        .to_ascii_lowercase()
 }
 
 impl Registry {
-    pub fn new(domain: impl Into<String>) -> Self {
+    /// Loopback aliases and the addresses meaning "all interfaces"
+    pub const DEFAULT_GENERATED_NAME_ADDRESSES: &[&str] = &[
+        "*",
+        "0.0.0.0",
+        "::",
+        "localhost",
+        "ip6-localhost",
+        "ip6-loopback",
+        "127.0.0.1",
+        "::1",
+    ];
+
+    pub fn new(
+        domain: impl Into<String>,
+        names: impl NameGenerator + 'static,
+        webhook: Option<Webhook>,
+        namespace_by_user: bool,
+    ) -> Self {
         Self {
             tunnels: Arc::new(RwLock::new(HashMap::new())),
             domain: domain.into(),
+            names: Arc::new(names),
+            webhook,
+            events: broadcast::Sender::new(EVENTS_CAPACITY),
+            namespace_by_user,
+            offline: None,
+            max_lifetime: None,
+            generated_name_addresses: Arc::new(
+                Self::DEFAULT_GENERATED_NAME_ADDRESSES
+                    .iter()
+                    .map(|address| normalize_address(address))
+                    .collect(),
+            ),
+            sessions: Default::default(),
+            public_revoked: Arc::new(watch::Sender::new(())),
+            scheme: "http",
         }
     }
 
-    fn address(&self, name: impl AsRef<str>) -> String {
-        format!("{}.{}", name.as_ref(), self.domain)
+    /// Replace the forwarded addresses for which a name is generated, instead of using the
+    /// address as the name
+    pub fn generate_names_for<S: AsRef<str>>(
+        mut self,
+        addresses: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.generated_name_addresses = Arc::new(
+            addresses
+                .into_iter()
+                .map(|address| normalize_address(address.as_ref()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Whether forwarding the address should give the tunnel a generated name. An empty address,
+    /// as forwarded by `ssh -R 80:localhost:3000`, can never be a name so it always gets one.
+    pub fn generates_name(&self, address: &str) -> bool {
+        let address = normalize_address(address);
+        address.is_empty() || self.generated_name_addresses.contains(&address)
+    }
+
+    /// Remember the tunnels of owners that disconnected, so requests can be told that the tunnel
+    /// is offline instead of unknown. Without a grace period they are remembered until the owner
+    /// closes the tunnel.
+    pub fn remember_offline(mut self, grace: Option<Duration>) -> Self {
+        self.offline = Some(Arc::new(OfflineTunnels {
+            grace,
+            since: Default::default(),
+        }));
+        self
+    }
+
+    /// Hand out https urls, needed when the web service terminates TLS itself
+    pub fn use_https(mut self) -> Self {
+        self.scheme = "https";
+        self
+    }
+
+    /// Close tunnels once they exist for longer than `lifetime`, call [`Self::expire_tunnels`] to
+    /// start closing them
+    pub fn limit_lifetime(mut self, lifetime: Duration) -> Self {
+        self.max_lifetime = Some(lifetime);
+        self
+    }
+
+    pub(crate) fn max_lifetime(&self) -> Option<Duration> {
+        self.max_lifetime
+    }
+
+    fn is_expired(&self, tunnel: &TunnelInner) -> bool {
+        self.max_lifetime
+            .is_some_and(|lifetime| tunnel.age() >= lifetime)
+    }
+
+    pub(crate) fn notify(&self, event: TunnelEvent) {
+        // Fails if nobody is subscribed, which is fine
+        let _ = self.events.send(event.clone());
+
+        if let Some(webhook) = &self.webhook {
+            webhook.send(event);
+        }
+    }
+
+    /// Receive every event from now on, subscribers that fall behind skip the oldest events
+    /// instead of blocking the registry
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<TunnelEvent> {
+        self.events.subscribe()
+    }
+
+    /// Changes every time public access is revoked
+    pub(crate) fn watch_public_revoked(&self) -> watch::Receiver<()> {
+        self.public_revoked.subscribe()
+    }
+
+    /// Sessions of users that are logged in, shared with the admin endpoints
+    pub(crate) fn sessions(&self) -> &Sessions {
+        &self.sessions
+    }
+
+    /// Domain below which all tunnels are registered
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Scheme of the urls handed to users
+    pub(crate) fn scheme(&self) -> &'static str {
+        self.scheme
+    }
+
+    /// Url that visitors use to reach the address
+    pub(crate) fn url(&self, address: &str) -> String {
+        format!("{}://{address}", self.scheme)
+    }
+
+    /// Whether the tunnels of every user are placed below `<user>.<domain>`
+    pub fn is_namespaced(&self) -> bool {
+        self.namespace_by_user
+    }
+
+    fn address(&self, name: impl AsRef<str>, owner: impl AsRef<str>) -> String {
+        if self.namespace_by_user {
+            format!("{}.{}.{}", name.as_ref(), owner.as_ref(), self.domain)
+        } else {
+            format!("{}.{}", name.as_ref(), self.domain)
+        }
     }
 
-    async fn generate_tunnel_name(&self) -> String {
-        // NOTE: It is technically possible to become stuck in this loop.
-        // However, that really only becomes a concern if a (very) high
-        // number of tunnels is open at the same time.
-        loop {
-            let name = get_animal_name();
-            if !self.tunnels.read().await.contains_key(&self.address(name)) {
-                break name.into();
+    /// Inverse of [`Self::address`]
+    fn name_from_address<'a>(&self, address: &'a str, owner: &str) -> &'a str {
+        let suffix = if self.namespace_by_user {
+            format!(".{owner}.{}", self.domain)
+        } else {
+            format!(".{}", self.domain)
+        };
+
+        address.strip_suffix(&suffix).unwrap_or(address)
+    }
+
+    /// Generate a name that is not in use yet, when namespacing by user it only has to be unique
+    /// for the owner
+    async fn generate_tunnel_name(&self, owner: &str) -> String {
+        let tunnels = self.tunnels.read().await;
+
+        for _ in 0..MAX_NAME_ATTEMPTS {
+            let name = self.names.generate();
+            if !tunnels.contains_key(&self.address(&name, owner)) {
+                return name;
             }
             trace!(name, "Already in use, picking new name");
         }
+
+        // Only a finite amount of tunnels can exist, so this is guaranteed to find a free name
+        let name = self.names.generate();
+        trace!(name, "Falling back to numbered suffix");
+        (2..)
+            .map(|i| format!("{name}-{i}"))
+            .find(|name| !tunnels.contains_key(&self.address(name, owner)))
+            .expect("range should be infinite")
     }
 
     pub(super) async fn register(&mut self, tunnel: &mut Tunnel) {
+        if self.insert(tunnel).await
+            && let Some(address) = tunnel.registry_entry.get_address()
+        {
+            self.notify(TunnelEvent::Created {
+                name: tunnel.registry_entry.name.clone(),
+                address,
+                owner: tunnel.inner.owner.clone(),
+            });
+        }
+    }
+
+    /// Give the tunnel a name if it does not have one yet, based on the forwarded address
+    pub(super) async fn pick_name(&self, tunnel: &mut Tunnel) {
         if tunnel.registry_entry.name.is_empty() {
-            if tunnel.inner.internal_address == "localhost" {
-                tunnel.registry_entry.name = self.generate_tunnel_name().await;
+            if self.generates_name(&tunnel.inner.internal_address) {
+                tunnel.registry_entry.name = self.generate_tunnel_name(&tunnel.inner.owner).await;
             } else {
                 tunnel.registry_entry.name = tunnel.inner.internal_address.clone();
             }
         }
+    }
+
+    /// Add the tunnel to the registry, returns true if the address was not in use before. Joining
+    /// a pool does not count, the address was already announced by the pool.
+    async fn insert(&mut self, tunnel: &mut Tunnel) -> bool {
+        self.pick_name(tunnel).await;
 
         trace!(
             name = tunnel.registry_entry.name,
             "Attempting to register tunnel"
         );
 
-        if tunnel.registry_entry.address.is_some() {
-            trace!(name = tunnel.registry_entry.name, "Already registered");
-            return;
+        // The tunnel was already closed for reaching the maximum lifetime
+        if self.is_expired(&tunnel.inner) {
+            debug!(
+                name = tunnel.registry_entry.name,
+                "Tunnel reached its maximum lifetime"
+            );
+            return false;
         }
 
-        let address = self.address(&tunnel.registry_entry.name);
+        let address = self.address(&tunnel.registry_entry.name, &tunnel.inner.owner);
 
-        if let Entry::Vacant(e) = self.tunnels.write().await.entry(address.clone()) {
-            tunnel.registry_entry.address = Some(address);
-            e.insert(tunnel.inner.clone());
-        } else {
-            trace!(name = tunnel.registry_entry.name, "Address already in use");
-            tunnel.registry_entry.address = None;
+        let mut tunnels = self.tunnels.write().await;
+        let mut registered = tunnel.registry_entry.lock_address();
+        if registered.is_some() {
+            trace!(name = tunnel.registry_entry.name, "Already registered");
+            return false;
+        }
+
+        match claim(&mut tunnels, &address, &tunnel.inner) {
+            Claim::Registered => {
+                *registered = Some(address);
+                tunnel.inner.mark_registered();
+                true
+            }
+            Claim::Joined => {
+                debug!(name = tunnel.registry_entry.name, "Joined pool");
+                *registered = Some(address);
+                tunnel.inner.mark_registered();
+                false
+            }
+            Claim::InUse => {
+                trace!(name = tunnel.registry_entry.name, "Address already in use");
+                false
+            }
         }
     }
 
     pub(super) async fn rename(&mut self, tunnel: &mut Tunnel, name: impl Into<String>) {
         trace!(name = tunnel.registry_entry.name, "Renaming tunnel");
 
-        if let Some(address) = tunnel.registry_entry.address.take() {
-            self.tunnels.write().await.remove(&address);
+        // The background retry would otherwise still register the old name
+        tunnel.registry_entry.stop_retry();
+        {
+            let mut tunnels = self.tunnels.write().await;
+            if let Some(address) = tunnel.registry_entry.lock_address().take() {
+                detach(&mut tunnels, &address, &tunnel.inner);
+            }
         }
 
-        tunnel.registry_entry.name = name.into();
-        self.register(tunnel).await;
+        let old_name = std::mem::replace(&mut tunnel.registry_entry.name, name.into());
+        self.insert(tunnel).await;
+
+        self.notify(TunnelEvent::Renamed {
+            old_name,
+            new_name: tunnel.registry_entry.name.clone(),
+            address: tunnel.registry_entry.get_address(),
+        });
     }
 
-    pub(crate) async fn get(&self, address: &str) -> Option<TunnelInner> {
+    /// Keep trying to register the tunnel in the background with an increasing delay between the
+    /// attempts. Stops once it succeeds, or when the tunnel is renamed or closed.
+    pub(super) fn retry_in_background(&self, tunnel: &mut Tunnel) {
+        if tunnel.registry_entry.is_retrying() {
+            return;
+        }
+
+        let status = RetryStatus::default();
+        tunnel.registry_entry.retry = Some(status.clone());
+
+        let registry = self.clone();
+        let name = tunnel.registry_entry.name.clone();
+        let shared_address = tunnel.registry_entry.shared_address();
+        let inner = tunnel.inner.clone();
+        let address = self.address(&name, &inner.owner);
+        tokio::spawn(async move {
+            let mut delay = RETRY_INITIAL_DELAY;
+            loop {
+                select! {
+                    _ = status.token.cancelled() => return,
+                    _ = tokio::time::sleep(delay) => {}
+                }
+
+                let attempt = status.attempts.fetch_add(1, Ordering::Relaxed) + 1;
+                trace!(name, attempt, "Retrying registration in the background");
+
+                {
+                    let mut tunnels = registry.tunnels.write().await;
+                    let mut registered =
+                        shared_address.lock().expect("lock should not be poisoned");
+                    // Checked while holding the locks, this way a tunnel that is being dropped or
+                    // renamed is never registered
+                    if status.token.is_cancelled() {
+                        return;
+                    }
+
+                    // Registered in the meantime by a manual retry
+                    if registered.is_some() || registry.is_expired(&inner) {
+                        status.stop();
+                        return;
+                    }
+
+                    let claim = claim(&mut tunnels, &address, &inner);
+                    if matches!(claim, Claim::InUse) {
+                        delay = (delay * 2).min(RETRY_MAX_DELAY);
+                        continue;
+                    }
+                    *registered = Some(address.clone());
+                    inner.mark_registered();
+
+                    if matches!(claim, Claim::Joined) {
+                        debug!(name, attempt, "Joined pool in the background");
+                        status.stop();
+                        return;
+                    }
+                }
+
+                debug!(name, attempt, "Registered tunnel in the background");
+                status.stop();
+                registry.notify(TunnelEvent::Created {
+                    name,
+                    address,
+                    owner: inner.owner.clone(),
+                });
+                return;
+            }
+        });
+    }
+
+    /// Periodically close tunnels that did not receive any requests for longer than `threshold`,
+    /// public tunnels are left alone. The owner can register the tunnel again.
+    pub fn reap_idle(&self, threshold: Duration, interval: Duration, token: CancellationToken) {
+        let registry = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                select! {
+                    _ = token.cancelled() => return,
+                    _ = interval.tick() => {}
+                }
+
+                registry
+                    .reap(ReapReason::Idle, |tunnel| tunnel.idle() >= threshold)
+                    .await;
+            }
+        });
+    }
+
+    /// Close tunnels that reached the maximum lifetime set by [`Self::limit_lifetime`], does
+    /// nothing if there is no maximum lifetime
+    pub fn expire_tunnels(&self, token: CancellationToken) {
+        if self.max_lifetime.is_none() {
+            return;
+        }
+
+        let registry = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EXPIRE_INTERVAL);
+            loop {
+                select! {
+                    _ = token.cancelled() => return,
+                    _ = interval.tick() => {}
+                }
+
+                registry
+                    .reap(ReapReason::Expired, |tunnel| registry.is_expired(tunnel))
+                    .await;
+            }
+        });
+    }
+
+    async fn reap(&self, reason: ReapReason, should_reap: impl Fn(&TunnelInner) -> bool) {
+        // Public tunnels are meant to wait for visitors, but they still expire
+        let spare_public = reason == ReapReason::Idle;
+
+        // Only look for tunnels to close while holding the read lock, requests keep being handled
+        let mut candidates = Vec::new();
+        {
+            let tunnels = self.tunnels.read().await;
+            for (address, tunnel) in tunnels.iter() {
+                if should_reap(tunnel) && !(spare_public && tunnel.is_public().await) {
+                    candidates.push((address.clone(), tunnel.clone()));
+                }
+            }
+        }
+
+        for (address, tunnel) in candidates {
+            {
+                let mut tunnels = self.tunnels.write().await;
+                // The tunnel might have been used, renamed, closed, or made public in the meantime
+                if !tunnels
+                    .get(&address)
+                    .is_some_and(|registered| registered.is_same(&tunnel))
+                    || !should_reap(&tunnel)
+                    || (spare_public && tunnel.is_public().await)
+                {
+                    continue;
+                }
+
+                tunnels.remove(&address);
+                // The whole pool is closed
+                for member in tunnel.members() {
+                    member
+                        .address
+                        .lock()
+                        .expect("lock should not be poisoned")
+                        .take();
+                }
+            }
+
+            let name = self.name_from_address(&address, &tunnel.owner).to_owned();
+            match reason {
+                ReapReason::Idle => info!(name, owner = tunnel.owner, "Closed idle tunnel"),
+                ReapReason::Expired => info!(
+                    name,
+                    owner = tunnel.owner,
+                    "Closed tunnel that reached its maximum lifetime"
+                ),
+            }
+            for member in tunnel.members() {
+                member.reaped.send_replace(Some(Reaped {
+                    name: name.clone(),
+                    reason,
+                }));
+            }
+            self.notify(TunnelEvent::Removed { name });
+        }
+    }
+
+    /// Look up the tunnel registered for exactly this address, for commands that act on a tunnel
+    /// by name
+    async fn get_exact(&self, address: &str) -> Option<TunnelInner> {
         self.tunnels.read().await.get(address).cloned()
     }
+
+    /// Look up the tunnel for the address, falling back to a wildcard tunnel (`*.name`) that
+    /// covers the address if there is no exact match
+    pub(crate) async fn get(&self, address: &str) -> Option<TunnelInner> {
+        let tunnels = self.tunnels.read().await;
+        if let Some(tunnel) = tunnels.get(address) {
+            return Some(tunnel.clone());
+        }
+
+        let (_, parent) = address.split_once('.')?;
+        tunnels.get(&format!("*.{parent}")).cloned()
+    }
+
+    async fn mark_offline(&self, address: String) {
+        let Some(offline) = &self.offline else {
+            return;
+        };
+
+        let mut since = offline.since.write().await;
+        // Clean up while we are here, so the map does not keep growing
+        since.retain(|_, since| !offline.is_expired(*since));
+        since.insert(address, Instant::now());
+    }
+
+    async fn forget_offline(&self, address: &str) {
+        if let Some(offline) = &self.offline {
+            offline.since.write().await.remove(address);
+        }
+    }
+
+    /// Check if the address belonged to a tunnel whose owner disconnected recently, only valid if
+    /// there is no tunnel for the address
+    pub(crate) async fn is_offline(&self, address: &str) -> bool {
+        let Some(offline) = &self.offline else {
+            return false;
+        };
+
+        offline
+            .since
+            .read()
+            .await
+            .get(address)
+            .is_some_and(|since| !offline.is_expired(*since))
+    }
+
+    /// Remove the tunnel, returns false if the address is no longer used by this tunnel. This
+    /// happens when the tunnel was already closed, in which case the address might even have
+    /// been claimed by a new tunnel in the meantime. It also returns false if the rest of the
+    /// pool of the tunnel keeps using the address.
+    async fn remove(&self, address: &str, tunnel: &TunnelInner) -> bool {
+        let mut tunnels = self.tunnels.write().await;
+        detach(&mut tunnels, address, tunnel)
+    }
+
+    /// Remove a tunnel that was closed by its owner, unlike the tunnels of a session that ended
+    /// it is not remembered as offline
+    pub(super) async fn remove_closed(&self, tunnel: &mut Tunnel) {
+        tunnel.registry_entry.stop_retry();
+        let Some(address) = tunnel.registry_entry.lock_address().take() else {
+            return;
+        };
+
+        if self.remove(&address, &tunnel.inner).await {
+            self.forget_offline(&address).await;
+            self.notify(TunnelEvent::Removed {
+                name: tunnel.registry_entry.name.clone(),
+            });
+        }
+    }
+
+    /// Remove every tunnel of the session, called once the session has ended. Tunnels that were
+    /// part of a pool with tunnels of other sessions leave the pool.
+    pub(crate) async fn remove_owned_by(&self, session_id: Uuid) {
+        let mut removed = Vec::new();
+        {
+            let mut tunnels = self.tunnels.write().await;
+            let owned: Vec<_> = tunnels
+                .iter()
+                .flat_map(|(address, tunnel)| {
+                    tunnel
+                        .members()
+                        .into_iter()
+                        .filter(|member| member.session_id == session_id)
+                        .map(|member| (address.clone(), member))
+                })
+                .collect();
+
+            for (address, tunnel) in owned {
+                tunnel
+                    .address
+                    .lock()
+                    .expect("lock should not be poisoned")
+                    .take();
+                if detach(&mut tunnels, &address, &tunnel) {
+                    removed.push((address, tunnel.owner));
+                }
+            }
+        }
+
+        for (address, owner) in removed {
+            let name = self.name_from_address(&address, &owner).to_owned();
+            debug!(name, %session_id, "Removed tunnel of closed session");
+            self.mark_offline(address).await;
+            self.notify(TunnelEvent::Removed { name });
+        }
+    }
+
+    /// Current name of a registered tunnel
+    pub(crate) async fn name_of(&self, tunnel: &TunnelInner) -> Option<String> {
+        self.tunnels
+            .read()
+            .await
+            .iter()
+            .find(|(_, inner)| inner.contains(tunnel))
+            .map(|(address, inner)| self.name_from_address(address, &inner.owner).into())
+    }
+
+    /// Transfer a private tunnel to another user, only the current owner of the tunnel is allowed
+    /// to do this. Every member of a pool is transferred, so the tunnel keeps its new owner when
+    /// another member takes over.
+    pub(crate) async fn transfer(
+        &self,
+        name: impl AsRef<str>,
+        user: impl AsRef<str>,
+        new_owner: impl Into<String>,
+    ) -> Result<(), RegistryError> {
+        let name = name.as_ref();
+        let user = user.as_ref();
+        let address = self.address(name, user);
+
+        let Some(tunnel) = self.get_exact(&address).await else {
+            return Err(RegistryError::UnknownTunnel(name.into()));
+        };
+
+        let new_owner = new_owner.into();
+        if let Err(err) = tunnel.transfer(name, user, &new_owner).await {
+            trace!(name, user, "Failed to transfer tunnel: {err}");
+            return Err(err);
+        }
+
+        for member in tunnel.members().iter().skip(1) {
+            if let Err(err) = member.transfer(name, user, &new_owner).await {
+                debug!(name, user, "Member of pool was not transferred: {err}");
+            }
+        }
+
+        trace!(name, new_owner, "Transferred tunnel");
+        self.notify(TunnelEvent::AccessChanged {
+            name: name.into(),
+            access: TunnelAccess::Private(new_owner),
+        });
+
+        Ok(())
+    }
+
+    /// Tunnels of other users that the user is allowed to see the stats of
+    pub(crate) async fn viewable_by(&self, user: impl AsRef<str>) -> Vec<ViewedTunnel> {
+        let tunnels = self.tunnels.read().await;
+
+        let mut viewable = Vec::new();
+        for (address, tunnel) in tunnels.iter() {
+            if !tunnel.is_viewable_by(&user).await {
+                continue;
+            }
+
+            let stats = tunnel.stats();
+            viewable.push(ViewedTunnel {
+                name: self.name_from_address(address, &tunnel.owner).into(),
+                owner: tunnel.owner.clone(),
+                connections: stats.connections(),
+                rx_bytes: stats.rx_bytes(),
+                tx_bytes: stats.tx_bytes(),
+                requests: stats.requests(),
+            });
+        }
+
+        viewable.sort_by(|a, b| a.name.cmp(&b.name));
+        viewable
+    }
+
+    /// All tunnels the user is allowed to see, sorted by name
+    pub(crate) async fn visible_to(&self, user: impl AsRef<str>) -> Vec<ListedTunnel> {
+        let tunnels = self.tunnels.read().await;
+
+        let mut visible = Vec::new();
+        for (address, tunnel) in tunnels.iter() {
+            if !tunnel.is_visible_to(&user).await {
+                continue;
+            }
+
+            let stats = tunnel.stats();
+            visible.push(ListedTunnel {
+                name: self.name_from_address(address, &tunnel.owner).into(),
+                address: self.url(address),
+                owner: tunnel.owner.clone(),
+                access: tunnel.get_access().await.clone(),
+                connections: stats.connections(),
+                rx_bytes: stats.rx_bytes(),
+                tx_bytes: stats.tx_bytes(),
+                requests: stats.requests(),
+                age_secs: tunnel.age().as_secs(),
+                label: tunnel.label.read().await.clone(),
+            });
+        }
+
+        visible.sort_by(|a, b| a.name.cmp(&b.name));
+        visible
+    }
+
+    /// Make every public tunnel of every user protected, returns the amount of tunnels that were
+    /// changed. Every member of a pool is changed and counted, otherwise the next member would
+    /// make the tunnel public again once it takes over.
+    pub async fn revoke_public_access(&self) -> usize {
+        self.public_revoked.send_replace(());
+        let tunnels = self.tunnels.read().await;
+
+        let mut revoked = 0;
+        for (address, tunnel) in tunnels.iter() {
+            let name = self.name_from_address(address, &tunnel.owner);
+
+            let mut changed = false;
+            for member in tunnel.members() {
+                if !member.revoke_public().await {
+                    continue;
+                }
+
+                info!(name, owner = member.owner, "Revoked public access");
+                changed = true;
+                revoked += 1;
+            }
+
+            if changed {
+                self.notify(TunnelEvent::AccessChanged {
+                    name: name.into(),
+                    access: TunnelAccess::Protected,
+                });
+            }
+        }
+
+        revoked
+    }
+
+    /// Requests captured by one of the tunnels of the user
+    pub(crate) async fn inspect(
+        &self,
+        name: impl AsRef<str>,
+        user: impl AsRef<str>,
+    ) -> Result<Vec<CapturedRequest>, RegistryError> {
+        let name = name.as_ref();
+        let address = self.address(name, &user);
+
+        let Some(tunnel) = self.get_exact(&address).await else {
+            return Err(RegistryError::UnknownTunnel(name.into()));
+        };
+
+        if !tunnel.is_owned_by(&user) {
+            trace!(name, user = user.as_ref(), "Tunnel is not owned by user");
+            return Err(RegistryError::NotOwner(name.into()));
+        }
+
+        match tunnel.get_inspector().await {
+            Some(inspector) => Ok(inspector.requests()),
+            None => Err(RegistryError::NotInspected(name.into())),
+        }
+    }
+
+    pub(crate) async fn close(
+        &self,
+        name: impl AsRef<str>,
+        user: impl AsRef<str>,
+    ) -> Result<(), RegistryError> {
+        let name = name.as_ref();
+        let address = self.address(name, &user);
+
+        let mut tunnels = self.tunnels.write().await;
+        let Some(tunnel) = tunnels.get(&address) else {
+            return Err(RegistryError::UnknownTunnel(name.into()));
+        };
+
+        if !tunnel.is_owned_by(&user) {
+            trace!(name, user = user.as_ref(), "Tunnel is not owned by user");
+            return Err(RegistryError::NotOwner(name.into()));
+        }
+
+        trace!(name, "Closing tunnel");
+        if let Some(tunnel) = tunnels.remove(&address) {
+            // The whole pool is closed, the tunnels no longer hold the address
+            for member in tunnel.members() {
+                member
+                    .address
+                    .lock()
+                    .expect("lock should not be poisoned")
+                    .take();
+            }
+        }
+        drop(tunnels);
+        self.forget_offline(&address).await;
+        self.notify(TunnelEvent::Removed { name: name.into() });
+
+        Ok(())
+    }
 }
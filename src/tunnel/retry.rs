@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use russh::ChannelOpenFailure;
+use tracing::debug;
+
+/// How often and how fast opening a channel to the client is retried after a transient failure
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    retries: u32,
+    base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32, base_delay: Duration) -> Self {
+        Self {
+            retries,
+            base_delay,
+        }
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    pub fn base_delay(&self) -> Duration {
+        self.base_delay
+    }
+
+    /// Run the operation until it succeeds, fails with an error that is not transient, or runs
+    /// out of retries. The delay doubles after every attempt.
+    pub async fn run<T, F, Fut>(&self, mut operation: F) -> Result<T, russh::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, russh::Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match operation().await {
+                Err(err) if attempt < self.retries && is_transient(&err) => {
+                    let delay = self.base_delay.saturating_mul(2u32.saturating_pow(attempt));
+                    debug!(attempt, ?delay, "Retrying after transient error: {err}");
+
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(2, Duration::from_millis(50))
+    }
+}
+
+/// The client can temporarily run out of resources to open a channel, any other error means the
+/// session is gone or the forwarded port can not be reached, retrying will not help
+fn is_transient(err: &russh::Error) -> bool {
+    matches!(
+        err,
+        russh::Error::ChannelOpenFailure(ChannelOpenFailure::ResourceShortage)
+    )
+}
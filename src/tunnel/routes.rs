@@ -0,0 +1,85 @@
+use std::str::FromStr;
+
+/// Forwarded address and port that requests are sent to instead of the port of the tunnel.
+///
+/// The client still needs to forward the port, as the channel is opened on the client side. This
+/// also means that the address is resolved by the client, not by the server.
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    address: Option<String>,
+    port: u32,
+}
+
+impl Upstream {
+    /// Address and port to open the channel to, the address falls back to the address of the
+    /// tunnel
+    pub fn target<'a>(&'a self, default_address: &'a str) -> (&'a str, u32) {
+        (
+            self.address.as_deref().unwrap_or(default_address),
+            self.port,
+        )
+    }
+}
+
+impl FromStr for Upstream {
+    type Err = String;
+
+    fn from_str(upstream: &str) -> Result<Self, Self::Err> {
+        let (address, port) = match upstream.rsplit_once(':') {
+            Some((address, port)) => (Some(address.to_owned()), port),
+            None => (None, upstream),
+        };
+
+        let port = port
+            .parse()
+            .map_err(|err| format!("invalid port '{port}': {err}"))?;
+
+        Ok(Self { address, port })
+    }
+}
+
+/// Sends requests matching a path prefix to a different forwarded port.
+#[derive(Debug, Clone)]
+pub struct Route {
+    prefix: String,
+    upstream: Upstream,
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> bool {
+        let Some(rest) = path.strip_prefix(&self.prefix) else {
+            return false;
+        };
+
+        // Only match on segment boundaries, '/api' should not match '/apis'
+        rest.is_empty() || rest.starts_with('/') || self.prefix.ends_with('/')
+    }
+}
+
+/// Find the upstream of the route with the longest prefix that matches the path
+pub fn select_route<'a>(routes: &'a [Route], path: &str) -> Option<&'a Upstream> {
+    routes
+        .iter()
+        .filter(|route| route.matches(path))
+        .max_by_key(|route| route.prefix.len())
+        .map(|route| &route.upstream)
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    fn from_str(route: &str) -> Result<Self, Self::Err> {
+        let (prefix, target) = route
+            .split_once('=')
+            .ok_or_else(|| format!("invalid PREFIX=[ADDRESS:]PORT: no '=' found in '{route}'"))?;
+
+        if !prefix.starts_with('/') {
+            return Err(format!("prefix '{prefix}' should start with '/'"));
+        }
+
+        Ok(Self {
+            prefix: prefix.into(),
+            upstream: target.parse()?,
+        })
+    }
+}
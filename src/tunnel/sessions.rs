@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use russh::server::Handle;
+use serde::Serialize;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Shown to the client when an admin closes their session
+const CLOSED_BY_ADMIN: &str = "Your session was closed by an administrator";
+
+#[derive(Debug)]
+struct ActiveSession {
+    id: Uuid,
+    peer_addr: Option<SocketAddr>,
+    connected: Instant,
+    handle: Handle,
+}
+
+/// Active session as shown to admins
+#[derive(Debug, Serialize)]
+pub(crate) struct SessionInfo {
+    id: String,
+    user: String,
+    peer_addr: Option<SocketAddr>,
+    connected_secs: u64,
+}
+
+/// Sessions of users that are logged in, keyed by username, so admins can see who is connected
+/// and close their sessions. The lock is never held across an await.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Sessions {
+    active: Arc<Mutex<HashMap<String, Vec<ActiveSession>>>>,
+}
+
+/// Keeps the session listed until it is dropped
+#[derive(Debug)]
+pub(crate) struct SessionGuard {
+    id: Uuid,
+    user: String,
+    sessions: Sessions,
+}
+
+impl Sessions {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<ActiveSession>>> {
+        self.active.lock().expect("lock should not be poisoned")
+    }
+
+    pub(crate) fn add(
+        &self,
+        id: Uuid,
+        user: impl Into<String>,
+        peer_addr: Option<SocketAddr>,
+        handle: Handle,
+    ) -> SessionGuard {
+        let user = user.into();
+        debug!(user, %id, ?peer_addr, "Session started");
+
+        self.lock()
+            .entry(user.clone())
+            .or_default()
+            .push(ActiveSession {
+                id,
+                peer_addr,
+                connected: Instant::now(),
+                handle,
+            });
+
+        SessionGuard {
+            id,
+            user,
+            sessions: self.clone(),
+        }
+    }
+
+    /// Sessions of the user, or of everyone if no user is given
+    pub(crate) fn list(&self, user: Option<&str>) -> Vec<SessionInfo> {
+        let active = self.lock();
+        let mut sessions: Vec<_> = active
+            .iter()
+            .filter(|(name, _)| user.is_none_or(|user| user == name.as_str()))
+            .flat_map(|(name, sessions)| {
+                sessions.iter().map(|session| SessionInfo {
+                    id: session.id.to_string(),
+                    user: name.clone(),
+                    peer_addr: session.peer_addr,
+                    connected_secs: session.connected.elapsed().as_secs(),
+                })
+            })
+            .collect();
+        sessions.sort_by(|a, b| (&a.user, b.connected_secs).cmp(&(&b.user, a.connected_secs)));
+
+        sessions
+    }
+
+    /// Disconnect every session of the user, their tunnels are removed once the sessions are
+    /// gone. Returns the amount of sessions that were closed.
+    pub(crate) async fn close_user(&self, user: &str) -> usize {
+        let handles: Vec<_> = self
+            .lock()
+            .get(user)
+            .map(|sessions| {
+                sessions
+                    .iter()
+                    .map(|session| (session.id, session.handle.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut closed = 0;
+        for (id, handle) in handles {
+            let result = handle
+                .disconnect(
+                    russh::Disconnect::ByApplication,
+                    CLOSED_BY_ADMIN.into(),
+                    "en".into(),
+                )
+                .await;
+
+            if result.is_err() {
+                // The session already ended, it is removed once its handler is dropped
+                warn!(user, %id, "Failed to close session");
+            } else {
+                debug!(user, %id, "Closed session");
+                closed += 1;
+            }
+        }
+
+        closed
+    }
+}
+
+impl SessionGuard {
+    pub(crate) fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub(crate) fn user(&self) -> &str {
+        &self.user
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        debug!(user = self.user, id = %self.id, "Session ended");
+
+        let mut active = self.sessions.lock();
+        if let Some(sessions) = active.get_mut(&self.user) {
+            sessions.retain(|session| session.id != self.id);
+            if sessions.is_empty() {
+                active.remove(&self.user);
+            }
+        }
+    }
+}
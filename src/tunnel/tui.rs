@@ -1,18 +1,138 @@
+use std::cmp::Reverse;
+use std::fmt;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use ratatui::style::Stylize;
 use ratatui::text::Span;
+use tokio::sync::{RwLock, watch};
+use tokio::time::Instant;
 
-use super::{Tunnel, TunnelAccess};
+use super::registry::RetryStatus;
+use super::{AccessExpiry, ReapReason, Reaped, Tunnel, TunnelAccess};
+use crate::helper::{Unit, format_duration};
 use crate::io::Stats;
 
 pub struct TunnelRow {
     name: Span<'static>,
+    owner: String,
+    label: Span<'static>,
     port: Span<'static>,
-    access: Span<'static>,
-    address: Span<'static>,
+    // Shared with the tunnel, so changes made from other sessions show up immediately
+    access: Arc<RwLock<TunnelAccess>>,
+    access_expiry: Arc<RwLock<Option<AccessExpiry>>>,
+    // Shared with the tunnel, so a tunnel that gets registered in the background shows up
+    address: Arc<Mutex<Option<String>>>,
+    scheme: &'static str,
+    retry: Option<RetryStatus>,
+    reaped: watch::Receiver<Option<Reaped>>,
     stats: Arc<Stats>,
+    created: Instant,
+    // Only set if tunnels have a maximum lifetime
+    expires: Option<Instant>,
+}
+
+impl TunnelRow {
+    pub fn name(&self) -> Span<'static> {
+        self.name.clone()
+    }
+
+    pub fn has_address(&self) -> bool {
+        self.address
+            .lock()
+            .expect("lock should not be poisoned")
+            .is_some()
+    }
+
+    fn address_span(&self) -> Span<'static> {
+        if let Some(address) = self
+            .address
+            .lock()
+            .expect("lock should not be poisoned")
+            .as_ref()
+        {
+            return format!("{}://{address}", self.scheme).into();
+        }
+
+        match self.reaped.borrow().as_ref().map(|reaped| reaped.reason) {
+            Some(ReapReason::Idle) => return "IDLE".yellow(),
+            Some(ReapReason::Expired) => return "EXPIRED".red(),
+            None => {}
+        }
+
+        match &self.retry {
+            Some(retry) if retry.is_active() => format!("RETRYING ({})", retry.attempts()).yellow(),
+            _ => "FAILED".red(),
+        }
+    }
+
+    /// Age of the tunnel, with a countdown if the tunnel has a maximum lifetime
+    fn age_span(&self) -> Span<'static> {
+        let age = format_duration(self.created.elapsed());
+        match self.expires {
+            Some(expires) if self.has_address() => {
+                let remaining = expires.saturating_duration_since(Instant::now());
+                format!("{age} ({} left)", format_duration(remaining)).into()
+            }
+            _ => age.into(),
+        }
+    }
+
+    /// Every column of the row together with its name, followed by the details that do not fit
+    /// in the table
+    pub fn details(&self) -> Vec<(Span<'static>, Span<'static>)> {
+        let mut details: Vec<_> = Tunnel::header()
+            .into_iter()
+            .zip(Vec::<Span<'static>>::from(self))
+            .collect();
+
+        details.insert(1, ("Owner".into(), self.owner.clone().into()));
+        if self.stats.failed() {
+            details.push(("Status".into(), "FAILING".red()));
+        }
+
+        details
+    }
+
+    /// Summary row with the combined stats of the given rows
+    pub fn totals<'a>(rows: impl IntoIterator<Item = &'a TunnelRow>) -> Vec<Span<'static>> {
+        let (count, connections, rx, tx, requests) = rows.into_iter().fold(
+            (0, 0, 0, 0, 0),
+            |(count, connections, rx, tx, requests), row| {
+                (
+                    count + 1,
+                    connections + row.stats.connections(),
+                    rx + row.stats.rx_bytes(),
+                    tx + row.stats.tx_bytes(),
+                    requests + row.stats.requests(),
+                )
+            },
+        );
+
+        let tunnels = if count == 1 { "tunnel" } else { "tunnels" };
+
+        vec![
+            "Total".into(),
+            "".into(),
+            "".into(),
+            format!("{count} {tunnels}").into(),
+            connections.to_string().into(),
+            Unit::binary(rx, "B").to_string().into(),
+            Unit::binary(tx, "B").to_string().into(),
+            requests.to_string().into(),
+            "".into(),
+            "".into(),
+            "".into(),
+        ]
+    }
+}
+
+fn access_span(access: &TunnelAccess) -> Span<'static> {
+    match access {
+        TunnelAccess::Private(owner) => owner.clone().yellow(),
+        TunnelAccess::Protected => "PROTECTED".blue(),
+        TunnelAccess::Public => "PUBLIC".green(),
+    }
 }
 
 impl From<&TunnelRow> for Vec<Span<'static>> {
@@ -23,14 +143,43 @@ impl From<&TunnelRow> for Vec<Span<'static>> {
             row.port.clone()
         };
 
+        // The locks are only held very briefly while changing the access, if we happen to hit
+        // that moment the next redraw will show the new access
+        let access = row
+            .access
+            .try_read()
+            .map_or_else(|_| "-".into(), |access| access_span(&access));
+        let access = match row.access_expiry.try_read().as_deref() {
+            Ok(Some(expiry)) => {
+                let remaining = expiry.expires.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    access_span(&expiry.previous)
+                } else {
+                    Span::styled(
+                        format!("{} ({})", access.content, format_duration(remaining)),
+                        access.style,
+                    )
+                }
+            }
+            _ => access,
+        };
+
         vec![
             row.name.clone(),
             port,
-            row.access.clone(),
-            row.address.clone(),
+            access,
+            row.address_span(),
             row.stats.connections().to_string().into(),
-            row.stats.rx().to_string().into(),
-            row.stats.tx().to_string().into(),
+            row.stats.rx_binary().to_string().into(),
+            row.stats.tx_binary().to_string().into(),
+            row.stats.requests().to_string().into(),
+            row.stats
+                .idle()
+                .map(format_duration)
+                .unwrap_or_else(|| "-".into())
+                .into(),
+            row.age_span(),
+            row.label.clone(),
         ]
     }
 }
@@ -45,27 +194,141 @@ impl Tunnel {
             "Conn".into(),
             "Rx".into(),
             "Tx".into(),
+            "Reqs".into(),
+            "Idle".into(),
+            "Age".into(),
+            "Label".into(),
         ]
     }
 
     pub async fn to_row(tunnel: &Tunnel) -> TunnelRow {
-        let access = match tunnel.inner.access.read().await.deref() {
-            TunnelAccess::Private(owner) => owner.clone().yellow(),
-            TunnelAccess::Protected => "PROTECTED".blue(),
-            TunnelAccess::Public => "PUBLIC".green(),
+        let port = match tunnel.inner.unix_socket.read().await.as_deref() {
+            Some(path) => format!("unix:{path}"),
+            None => tunnel.inner.port.to_string(),
         };
 
-        let address = tunnel
-            .get_address()
-            .map(|address| format!("http://{address}").into())
-            .unwrap_or("FAILED".red());
-
         TunnelRow {
             name: tunnel.registry_entry.get_name().to_string().into(),
-            port: tunnel.inner.port.to_string().into(),
-            access,
-            address,
+            owner: tunnel.inner.owner.clone(),
+            label: tunnel
+                .inner
+                .label
+                .read()
+                .await
+                .clone()
+                .map_or_else(|| "-".dim(), |label| label.italic()),
+            port: port.into(),
+            access: tunnel.inner.access.clone(),
+            access_expiry: tunnel.inner.access_expiry.clone(),
+            address: tunnel.registry_entry.shared_address(),
+            scheme: tunnel.registry.scheme(),
+            retry: tunnel.registry_entry.retry_status(),
+            reaped: tunnel.watch_reaped(),
             stats: tunnel.inner.stats.clone(),
+            created: tunnel.inner.created,
+            expires: tunnel
+                .registry
+                .max_lifetime()
+                .map(|lifetime| tunnel.inner.created + lifetime),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    #[default]
+    None,
+    Name,
+    Connections,
+    Rx,
+    Tx,
+}
+
+impl SortBy {
+    pub fn next(self) -> Self {
+        match self {
+            SortBy::None => SortBy::Name,
+            SortBy::Name => SortBy::Connections,
+            SortBy::Connections => SortBy::Rx,
+            SortBy::Rx => SortBy::Tx,
+            SortBy::Tx => SortBy::None,
+        }
+    }
+}
+
+impl fmt::Display for SortBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SortBy::None => "none",
+            SortBy::Name => "name",
+            SortBy::Connections => "conn",
+            SortBy::Rx => "rx",
+            SortBy::Tx => "tx",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Determines which tunnels are shown in the table and in what order.
+///
+/// Both the handler and the renderer use this to map between the index of a tunnel and its
+/// position in the table, so they always agree on what is selected.
+#[derive(Debug, Default, Clone)]
+pub struct TableView {
+    sort: SortBy,
+    filter: String,
+}
+
+impl TableView {
+    pub fn sort(&self) -> SortBy {
+        self.sort
+    }
+
+    pub fn cycle_sort(&mut self) {
+        self.sort = self.sort.next();
+    }
+
+    pub fn filter(&self) -> &str {
+        &self.filter
+    }
+
+    pub fn set_filter(&mut self, filter: impl Into<String>) {
+        self.filter = filter.into();
+    }
+
+    /// Returns the indices of the tunnels that should be displayed, in display order
+    pub fn tunnels(&self, tunnels: &[Tunnel]) -> Vec<usize> {
+        self.apply(
+            tunnels
+                .iter()
+                .map(|tunnel| (tunnel.registry_entry.get_name(), tunnel.inner.stats.deref())),
+        )
+    }
+
+    /// Returns the indices of the rows that should be displayed, in display order
+    pub fn rows(&self, rows: &[TunnelRow]) -> Vec<usize> {
+        self.apply(
+            rows.iter()
+                .map(|row| (row.name.content.as_ref(), row.stats.deref())),
+        )
+    }
+
+    fn apply<'a>(&self, entries: impl Iterator<Item = (&'a str, &'a Stats)>) -> Vec<usize> {
+        let mut entries: Vec<_> = entries
+            .enumerate()
+            .filter(|(_, (name, _))| name.contains(&self.filter))
+            .collect();
+
+        match self.sort {
+            SortBy::None => {}
+            SortBy::Name => entries.sort_by_key(|(_, (name, _))| *name),
+            SortBy::Connections => {
+                entries.sort_by_key(|(_, (_, stats))| Reverse(stats.connections()))
+            }
+            SortBy::Rx => entries.sort_by_key(|(_, (_, stats))| Reverse(stats.rx_bytes())),
+            SortBy::Tx => entries.sort_by_key(|(_, (_, stats))| Reverse(stats.tx_bytes())),
         }
+
+        entries.into_iter().map(|(index, _)| index).collect()
     }
 }
@@ -0,0 +1,44 @@
+use std::str::FromStr;
+
+/// Unix socket that the client forwards a tunnel to.
+///
+/// OpenSSH can forward a remote port to a local socket (`-R <name>:<port>:/path/to.sock`). This
+/// happens entirely on the client, the server only sees a normal forwarded port, so the socket is
+/// only used to show where the requests end up.
+#[derive(Debug, Clone)]
+pub struct UnixSocket {
+    // Applies to all tunnels if not set
+    name: Option<String>,
+    path: String,
+}
+
+impl UnixSocket {
+    pub fn applies_to(&self, name: &str) -> bool {
+        self.name.as_deref().is_none_or(|own| own == name)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl FromStr for UnixSocket {
+    type Err = String;
+
+    fn from_str(socket: &str) -> Result<Self, Self::Err> {
+        let (name, path) = match socket.split_once('=') {
+            // Paths are allowed to contain '='
+            Some((name, path)) if !name.contains('/') => (Some(name.to_owned()), path),
+            _ => (None, socket),
+        };
+
+        if path.is_empty() {
+            return Err("path of the unix socket is empty".into());
+        }
+
+        Ok(Self {
+            name,
+            path: path.into(),
+        })
+    }
+}
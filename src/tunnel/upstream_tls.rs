@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use hyper_util::rt::TokioIo;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{
+    CryptoProvider, verify_tls12_signature, verify_tls13_signature,
+};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{self, ClientConfig, DigitallySignedStruct, RootCertStore};
+
+use crate::io::TrackStats;
+
+pub fn parse_server_name(name: &str) -> Result<ServerName<'static>, String> {
+    ServerName::try_from(name.to_owned()).map_err(|err| format!("invalid server name: {err}"))
+}
+
+/// Accepts any certificate, only the signatures of the handshake are checked
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Talk HTTPS instead of plain HTTP to the forwarded port, for development servers that only
+/// support HTTPS
+#[derive(Debug, Clone)]
+pub struct UpstreamTls {
+    config: Arc<ClientConfig>,
+    // Falls back to the address the port was forwarded on, which is the name of the tunnel
+    server_name: Option<ServerName<'static>>,
+    insecure: bool,
+}
+
+impl UpstreamTls {
+    /// Without `insecure` the certificate has to be signed by one of the well known CAs
+    pub fn new(server_name: Option<ServerName<'static>>, insecure: bool, http2: bool) -> Self {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .expect("ring should support the default protocol versions");
+
+        let mut config = if insecure {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+                .with_no_client_auth()
+        } else {
+            let roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+            builder.with_root_certificates(roots).with_no_client_auth()
+        };
+        // Only offer the protocol that is going to be spoken over the connection
+        config.alpn_protocols = if http2 {
+            vec![b"h2".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        };
+
+        Self {
+            config: Arc::new(config),
+            server_name,
+            insecure,
+        }
+    }
+
+    pub fn is_insecure(&self) -> bool {
+        self.insecure
+    }
+
+    pub(crate) async fn connect(
+        &self,
+        address: &str,
+        io: TrackStats,
+    ) -> std::io::Result<TokioIo<TlsStream<TokioIo<TrackStats>>>> {
+        let server_name = match &self.server_name {
+            Some(server_name) => server_name.clone(),
+            None => parse_server_name(address).map_err(std::io::Error::other)?,
+        };
+
+        let stream = TlsConnector::from(self.config.clone())
+            .connect(server_name, TokioIo::new(io))
+            .await?;
+
+        Ok(TokioIo::new(stream))
+    }
+}
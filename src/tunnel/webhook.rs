@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use hyper::header::{CONTENT_TYPE, HeaderValue};
+use serde::Serialize;
+use tracing::{trace, warn};
+
+use super::TunnelAccess;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum TunnelEvent {
+    Created {
+        name: String,
+        address: String,
+        owner: String,
+    },
+    Renamed {
+        old_name: String,
+        new_name: String,
+        address: Option<String>,
+    },
+    AccessChanged {
+        name: String,
+        access: TunnelAccess,
+    },
+    Removed {
+        name: String,
+    },
+}
+
+impl TunnelEvent {
+    /// Same as the `event` field of the serialized event
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Self::Created { .. } => "created",
+            Self::Renamed { .. } => "renamed",
+            Self::AccessChanged { .. } => "access_changed",
+            Self::Removed { .. } => "removed",
+        }
+    }
+}
+
+/// Sends tunnel lifecycle events to an external endpoint
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            client: Default::default(),
+        }
+    }
+
+    /// Deliver the event in the background, failures are only logged
+    pub(crate) fn send(&self, event: TunnelEvent) {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("Failed to serialize webhook event: {err}");
+                return;
+            }
+        };
+
+        let request = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+            .body(body)
+            .timeout(WEBHOOK_TIMEOUT);
+
+        tokio::spawn(async move {
+            match request
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+            {
+                Ok(_) => trace!(?event, "Delivered webhook"),
+                Err(err) => warn!(?event, "Failed to deliver webhook: {err}"),
+            }
+        });
+    }
+}
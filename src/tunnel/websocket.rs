@@ -0,0 +1,70 @@
+use hyper::HeaderMap;
+use hyper::header::{ORIGIN, SEC_WEBSOCKET_PROTOCOL, UPGRADE};
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebSocketError {
+    #[error("Origin '{0}' is not allowed to open a WebSocket")]
+    Origin(String),
+    #[error("None of the requested WebSocket subprotocols are allowed")]
+    Protocol,
+}
+
+/// Restricts which origins and subprotocols can open a WebSocket through a tunnel, an empty list
+/// allows everything.
+#[derive(Debug, Clone, Default)]
+pub struct WebSocketPolicy {
+    origins: Vec<String>,
+    protocols: Vec<String>,
+}
+
+impl WebSocketPolicy {
+    pub fn new(origins: Vec<String>, protocols: Vec<String>) -> Self {
+        Self { origins, protocols }
+    }
+
+    pub(crate) fn is_websocket(headers: &HeaderMap) -> bool {
+        headers
+            .get(UPGRADE)
+            .and_then(|upgrade| upgrade.to_str().ok())
+            .is_some_and(|upgrade| {
+                upgrade
+                    .split(',')
+                    .any(|protocol| protocol.trim().eq_ignore_ascii_case("websocket"))
+            })
+    }
+
+    pub(crate) fn check(&self, headers: &HeaderMap) -> Result<(), WebSocketError> {
+        // Only browsers send an origin and other clients can set it to anything, so there is
+        // nothing to check if it is missing
+        if !self.origins.is_empty()
+            && let Some(origin) = headers.get(ORIGIN)
+        {
+            let origin = String::from_utf8_lossy(origin.as_bytes());
+            if !self
+                .origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(&origin))
+            {
+                return Err(WebSocketError::Origin(origin.into_owned()));
+            }
+        }
+
+        if !self.protocols.is_empty() {
+            let allowed = headers
+                .get_all(SEC_WEBSOCKET_PROTOCOL)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .any(|protocol| {
+                    self.protocols
+                        .iter()
+                        .any(|allowed| allowed == protocol.trim())
+                });
+            if !allowed {
+                return Err(WebSocketError::Protocol);
+            }
+        }
+
+        Ok(())
+    }
+}
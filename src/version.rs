@@ -9,3 +9,28 @@ const fn get_version() -> &'static str {
         git_version::git_version!(fallback = "unknown")
     }
 }
+
+/// Commit the binary was built from, `GIT_HASH` can be set when the repository is not available
+/// during the build
+pub const GIT_HASH: &str = get_git_hash();
+
+/// Time the binary was built, only known when `BUILD_TIME` is set during the build
+pub const BUILD_TIME: Option<&str> = std::option_env!("BUILD_TIME");
+
+const fn get_git_hash() -> &'static str {
+    if let Some(hash) = std::option_env!("GIT_HASH")
+        && !hash.is_empty()
+    {
+        hash
+    } else {
+        git_version::git_version!(
+            args = [
+                "--always",
+                "--abbrev=40",
+                "--dirty=-modified",
+                "--exclude=*"
+            ],
+            fallback = "unknown"
+        )
+    }
+}
@@ -1,11 +1,22 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
 use hyper::header::{self, HeaderName, HeaderValue, ToStrError};
-use hyper::{HeaderMap, Method, StatusCode};
+use hyper::{HeaderMap, Method, StatusCode, Uri};
 use reqwest::redirect::Policy;
+use tokio::sync::RwLock;
 use tracing::{debug, error};
 
 #[derive(Debug, Clone)]
 pub struct ForwardAuth {
-    address: String,
+    address: Arc<RwLock<String>>,
+    // Shared between requests so connections to the auth endpoint are reused
+    client: reqwest::Client,
+    // Query parameter of the login page that holds the url to return to after logging in
+    redirect_param: Option<String>,
+    // Response header of the auth endpoint that contains the username
+    user_header: HeaderName,
 }
 
 #[derive(Debug)]
@@ -17,6 +28,10 @@ impl User {
     pub fn is(&self, username: impl AsRef<str>) -> bool {
         self.username.eq(username.as_ref())
     }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
 }
 
 #[derive(Debug)]
@@ -27,8 +42,9 @@ pub enum AuthStatus {
     Unauthorized,
 }
 
-const REMOTE_USER: HeaderName = HeaderName::from_static("remote-user");
 const X_FORWARDED_METHOD: HeaderName = HeaderName::from_static("x-forwarded-method");
+const X_FORWARDED_URI: HeaderName = HeaderName::from_static("x-forwarded-uri");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
 
 #[derive(Debug, thiserror::Error)]
 pub enum AuthError {
@@ -40,47 +56,125 @@ pub enum AuthError {
     MissingHeader(HeaderName),
     #[error("Header '{0}' received from auth endpoint is invalid: {1}")]
     InvalidHeader(HeaderName, ToStrError),
+    #[error("Invalid auth endpoint '{0}': {1}")]
+    InvalidAddress(String, String),
     #[error("Unexpected response from auth endpoint: {0:?}")]
     UnexpectedResponse(reqwest::Response),
 }
 
 impl ForwardAuth {
-    pub fn new(endpoint: impl Into<String>) -> Self {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub const DEFAULT_REDIRECT_PARAM: &str = "rd";
+
+    pub const DEFAULT_USER_HEADER: HeaderName = HeaderName::from_static("remote-user");
+
+    pub fn new(
+        endpoint: impl Into<String>,
+        timeout: Duration,
+        redirect_param: Option<String>,
+        user_header: HeaderName,
+    ) -> Self {
+        let client = reqwest::ClientBuilder::new()
+            .redirect(Policy::none())
+            .timeout(timeout)
+            .build()
+            .expect("client configuration should be valid");
+
         Self {
-            address: endpoint.into(),
+            address: Arc::new(RwLock::new(endpoint.into())),
+            client,
+            redirect_param,
+            user_header,
+        }
+    }
+
+    /// Add the original url to the location of the login page, so the user returns to it after
+    /// logging in. The location is left alone if it already contains a url to return to.
+    pub fn preserve_redirect(&self, location: HeaderValue, original_url: &str) -> HeaderValue {
+        let Some(param) = &self.redirect_param else {
+            return location;
+        };
+
+        let Some(mut url) = location
+            .to_str()
+            .ok()
+            .and_then(|location| reqwest::Url::parse(location).ok())
+        else {
+            debug!(?location, "Login location is not an absolute url");
+            return location;
+        };
+
+        if url.query_pairs().any(|(key, _)| key == param.as_str()) {
+            return location;
+        }
+
+        url.query_pairs_mut().append_pair(param, original_url);
+        HeaderValue::from_str(url.as_str()).unwrap_or(location)
+    }
+
+    /// Replace the address of the auth endpoint, returns true if the address changed
+    pub async fn set_address(&self, address: String) -> Result<bool, AuthError> {
+        if let Err(err) = reqwest::Url::parse(&address) {
+            return Err(AuthError::InvalidAddress(address, err.to_string()));
         }
+
+        let mut current = self.address.write().await;
+        if *current == address {
+            return Ok(false);
+        }
+
+        *current = address;
+        Ok(true)
+    }
+
+    /// Check if the auth endpoint can be reached, any response is considered a success
+    pub async fn probe(&self) -> Result<(), AuthError> {
+        let address = self.address.read().await.clone();
+        let resp = self.client.get(address).send().await?;
+        debug!(status = %resp.status(), "Auth endpoint responded");
+
+        Ok(())
     }
 
+    /// Ask the auth endpoint if the request is allowed, the `X-Forwarded-*` headers describe the
+    /// original request to the endpoint
     pub async fn check(
         &self,
-        methods: &Method,
+        method: &Method,
+        uri: &Uri,
+        host: &str,
         headers: &HeaderMap<HeaderValue>,
     ) -> Result<AuthStatus, AuthError> {
-        let client = reqwest::ClientBuilder::new()
-            .redirect(Policy::none())
-            .build()?;
-
+        // The request to the auth endpoint has no body, so headers describing the body of the
+        // original request should not be send along
         let mut headers: HeaderMap = headers
-            .clone()
-            .into_iter()
-            .filter_map(|(key, value)| {
-                if let Some(key) = key
-                    && key != header::CONTENT_LENGTH
-                    && key != header::HOST
-                {
-                    Some((key, value))
-                } else {
-                    None
-                }
+            .iter()
+            .filter(|(key, _)| {
+                *key != header::CONTENT_LENGTH
+                    && *key != header::TRANSFER_ENCODING
+                    && *key != header::HOST
             })
+            .map(|(key, value)| (key.clone(), value.clone()))
             .collect();
 
         headers.insert(
             X_FORWARDED_METHOD,
-            HeaderValue::from_str(methods.as_str()).expect("method should convert to valid ascii"),
+            HeaderValue::from_str(method.as_str()).expect("method should convert to valid ascii"),
         );
+        let path_and_query = uri
+            .path_and_query()
+            .map_or("/", |path_and_query| path_and_query.as_str());
+        headers.insert(
+            X_FORWARDED_URI,
+            HeaderValue::from_str(path_and_query).expect("uri should convert to valid ascii"),
+        );
+        if let Ok(host) = HeaderValue::from_str(host) {
+            headers.insert(X_FORWARDED_HOST, host);
+        }
 
-        let resp = client.get(&self.address).headers(headers).send().await?;
+        let address = self.address.read().await.clone();
+        let resp = self.client.get(address).headers(headers).send().await?;
 
         let status_code = resp.status();
         if status_code == StatusCode::FOUND {
@@ -99,10 +193,10 @@ impl ForwardAuth {
 
         let username = resp
             .headers()
-            .get(REMOTE_USER)
-            .ok_or(AuthError::MissingHeader(REMOTE_USER))?
+            .get(&self.user_header)
+            .ok_or_else(|| AuthError::MissingHeader(self.user_header.clone()))?
             .to_str()
-            .map_err(|err| AuthError::InvalidHeader(REMOTE_USER, err))?
+            .map_err(|err| AuthError::InvalidHeader(self.user_header.clone(), err))?
             .to_owned();
 
         debug!("Connected user is: {username}");
@@ -110,3 +204,45 @@ impl ForwardAuth {
         Ok(AuthStatus::Authenticated(User { username }))
     }
 }
+
+/// The default auth endpoint together with named endpoints that tunnels can use instead, e.g. to
+/// put one tunnel behind the company SSO and another behind the identity provider of a partner
+#[derive(Debug, Clone)]
+pub struct AuthProviders {
+    default: ForwardAuth,
+    named: Arc<HashMap<String, ForwardAuth>>,
+}
+
+impl AuthProviders {
+    pub fn new(
+        default: ForwardAuth,
+        named: impl IntoIterator<Item = (String, ForwardAuth)>,
+    ) -> Self {
+        Self {
+            default,
+            named: Arc::new(named.into_iter().collect()),
+        }
+    }
+
+    /// Provider picked by a tunnel, the default provider if the tunnel did not pick one
+    pub fn get(&self, name: Option<&str>) -> Option<&ForwardAuth> {
+        match name {
+            Some(name) => self.named.get(name),
+            None => Some(&self.default),
+        }
+    }
+
+    pub fn default_provider(&self) -> &ForwardAuth {
+        &self.default
+    }
+
+    pub fn named(&self) -> impl Iterator<Item = (&str, &ForwardAuth)> {
+        self.named.iter().map(|(name, auth)| (name.as_str(), auth))
+    }
+}
+
+impl From<ForwardAuth> for AuthProviders {
+    fn from(default: ForwardAuth) -> Self {
+        Self::new(default, [])
+    }
+}
@@ -0,0 +1,269 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use hyper::body::{Body, Frame, Incoming, SizeHint};
+use pin_project_lite::pin_project;
+use tokio::time::{Instant, Sleep};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use tracing::warn;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BodyError {
+    #[error("Request body exceeds the maximum size of {0} bytes")]
+    TooLarge(u64),
+    #[error("Timed out while reading the request body")]
+    Timeout,
+    #[error("Response body ended after {received} of the announced {expected} bytes")]
+    Incomplete { expected: u64, received: u64 },
+    #[error("Response body is longer than the announced {0} bytes")]
+    TooLong(u64),
+    #[error("Forwarded port stopped sending the response body")]
+    Stalled,
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+}
+
+/// Limits that are enforced on request bodies while they are being forwarded
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimits {
+    max_size: u64,
+    timeout: Duration,
+}
+
+impl BodyLimits {
+    pub fn new(max_size: u64, timeout: Duration) -> Self {
+        Self { max_size, timeout }
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self::new(100 * 1024 * 1024, Duration::from_secs(30))
+    }
+}
+
+pin_project! {
+    /// Streams the request body while keeping track of the size, the timeout restarts every time
+    /// data is received.
+    pub struct LimitedBody {
+        #[pin]
+        inner: Incoming,
+        // Boxed to keep the body Unpin
+        timeout: Pin<Box<Sleep>>,
+        limits: BodyLimits,
+        read: u64,
+        stop: CancellationToken,
+        stop_requested: Pin<Box<WaitForCancellationFutureOwned>>,
+        stopped: bool,
+        // Not read until the forwarded port asks for the body, or the delay passed
+        held: Option<(Pin<Box<WaitForCancellationFutureOwned>>, Pin<Box<Sleep>>)>,
+    }
+}
+
+impl LimitedBody {
+    pub fn new(inner: Incoming, limits: BodyLimits) -> Self {
+        let stop = CancellationToken::new();
+
+        Self {
+            inner,
+            timeout: Box::pin(tokio::time::sleep(limits.timeout)),
+            limits,
+            read: 0,
+            stop_requested: Box::pin(stop.clone().cancelled_owned()),
+            stop,
+            stopped: false,
+            held: None,
+        }
+    }
+
+    /// Do not read the body until the returned token is cancelled, hyper only sends `100
+    /// Continue` to the client once the body is read. This way the client waits for the forwarded
+    /// port to send `100 Continue` instead. Forwarded ports that ignore `Expect` still receive the
+    /// body after `delay`, the same way curl stops waiting.
+    pub fn hold_until_continue(&mut self, delay: Duration) -> CancellationToken {
+        let proceed = CancellationToken::new();
+        self.held = Some((
+            Box::pin(proceed.clone().cancelled_owned()),
+            Box::pin(tokio::time::sleep(delay)),
+        ));
+
+        proceed
+    }
+
+    /// Cancelling the token ends the body early, the rest of the body is never read
+    pub fn stop_token(&self) -> CancellationToken {
+        self.stop.clone()
+    }
+}
+
+impl Body for LimitedBody {
+    type Data = Bytes;
+    type Error = BodyError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut project = self.project();
+
+        if *project.stopped || project.stop_requested.as_mut().poll(cx).is_ready() {
+            *project.stopped = true;
+            return Poll::Ready(None);
+        }
+
+        if let Some((proceed, delay)) = project.held {
+            if proceed.as_mut().poll(cx).is_pending() && delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            *project.held = None;
+            // The client only starts sending the body now
+            project
+                .timeout
+                .as_mut()
+                .reset(Instant::now() + project.limits.timeout);
+        }
+
+        match project.inner.poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    *project.read += data.len() as u64;
+                    if *project.read > project.limits.max_size {
+                        return Poll::Ready(Some(Err(BodyError::TooLarge(
+                            project.limits.max_size,
+                        ))));
+                    }
+                }
+
+                project
+                    .timeout
+                    .as_mut()
+                    .reset(Instant::now() + project.limits.timeout);
+
+                Poll::Ready(Some(Ok(frame)))
+            }
+            Poll::Ready(frame) => Poll::Ready(frame.map(|frame| frame.map_err(BodyError::from))),
+            Poll::Pending => match project.timeout.as_mut().poll(cx) {
+                Poll::Ready(()) => Poll::Ready(Some(Err(BodyError::Timeout))),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.stopped || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+pin_project! {
+    /// Streams the response of the forwarded port to the client. When the response announced its
+    /// length, a body that ends early, is longer than announced, or stops sending data for longer
+    /// than the timeout is turned into an error. This closes the connection to the client, instead
+    /// of leaving it waiting for bytes that never arrive.
+    pub struct UpstreamBody {
+        #[pin]
+        inner: Incoming,
+        tunnel: String,
+        expected: Option<u64>,
+        received: u64,
+        timeout: Option<Duration>,
+        // Boxed to keep the body Unpin
+        stalled: Option<Pin<Box<Sleep>>>,
+        // Only time spent waiting on the forwarded port counts, not time spent waiting for a slow
+        // client to read
+        waiting: bool,
+    }
+}
+
+impl UpstreamBody {
+    pub fn new(inner: Incoming, tunnel: impl Into<String>, timeout: Option<Duration>) -> Self {
+        let expected = inner.size_hint().exact();
+        // Bodies without a length, like server-sent events, can be quiet for a long time
+        let timeout = timeout.filter(|_| expected.is_some());
+
+        Self {
+            inner,
+            tunnel: tunnel.into(),
+            expected,
+            received: 0,
+            timeout,
+            stalled: timeout.map(|timeout| Box::pin(tokio::time::sleep(timeout))),
+            waiting: false,
+        }
+    }
+}
+
+impl Body for UpstreamBody {
+    type Data = Bytes;
+    type Error = BodyError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut project = self.project();
+
+        let err = match project.inner.poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    *project.received += data.len() as u64;
+                }
+
+                *project.waiting = false;
+                match *project.expected {
+                    Some(expected) if *project.received > expected => BodyError::TooLong(expected),
+                    _ => return Poll::Ready(Some(Ok(frame))),
+                }
+            }
+            Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err.into()))),
+            Poll::Ready(None) => match *project.expected {
+                Some(expected) if *project.received < expected => BodyError::Incomplete {
+                    expected,
+                    received: *project.received,
+                },
+                _ => return Poll::Ready(None),
+            },
+            Poll::Pending => {
+                let (Some(timeout), Some(stalled)) = (*project.timeout, project.stalled.as_mut())
+                else {
+                    return Poll::Pending;
+                };
+
+                if !*project.waiting {
+                    *project.waiting = true;
+                    stalled.as_mut().reset(Instant::now() + timeout);
+                }
+
+                match stalled.as_mut().poll(cx) {
+                    Poll::Ready(()) => BodyError::Stalled,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        };
+
+        warn!(tunnel = project.tunnel, "Closing connection: {err}");
+        Poll::Ready(Some(Err(err)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
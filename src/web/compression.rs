@@ -0,0 +1,265 @@
+use std::io::Write as _;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Body, Frame};
+use hyper::header::{
+    ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, HeaderValue, VARY,
+};
+use hyper::{HeaderMap, Method, Response, StatusCode};
+use pin_project_lite::pin_project;
+
+use super::body::BodyError;
+
+// Responses smaller than this do not get any smaller by compressing them
+const MIN_SIZE: u64 = 256;
+
+// Favor speed over size, the compression happens for every request
+const BROTLI_QUALITY: u32 = 4;
+const BROTLI_WINDOW: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// Content types that are already compressed, compressing them again only costs CPU
+const COMPRESSED_TYPES: &[&str] = &[
+    "image/",
+    "audio/",
+    "video/",
+    "font/woff",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/zstd",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/pdf",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// Pick the encoding with the highest preference in the `Accept-Encoding` header of the
+    /// client, brotli wins if both are equally preferred
+    pub fn negotiate(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|entry| {
+                let mut params = entry.split(';');
+                let encoding = match params.next()?.trim() {
+                    name if name.eq_ignore_ascii_case("br") => Self::Brotli,
+                    name if name.eq_ignore_ascii_case("gzip") => Self::Gzip,
+                    _ => return None,
+                };
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|quality| quality.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+
+                (quality > 0.0).then_some((encoding, quality))
+            })
+            .max_by(|(a, a_quality), (b, b_quality)| {
+                a_quality
+                    .total_cmp(b_quality)
+                    .then_with(|| (*a == Self::Brotli).cmp(&(*b == Self::Brotli)))
+            })
+            .map(|(encoding, _)| encoding)
+    }
+
+    fn header_value(self) -> HeaderValue {
+        match self {
+            Self::Brotli => HeaderValue::from_static("br"),
+            Self::Gzip => HeaderValue::from_static("gzip"),
+        }
+    }
+}
+
+/// Check if compressing the response is useful and does not break anything
+pub fn should_compress<T: Body>(method: &Method, resp: &Response<T>) -> bool {
+    if method == Method::HEAD
+        || matches!(
+            resp.status(),
+            StatusCode::SWITCHING_PROTOCOLS | StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED
+        )
+    {
+        return false;
+    }
+
+    let headers = resp.headers();
+    // Compressing partial content would change the meaning of the byte range
+    if headers.contains_key(CONTENT_ENCODING) || headers.contains_key(CONTENT_RANGE) {
+        return false;
+    }
+
+    if resp
+        .body()
+        .size_hint()
+        .exact()
+        .is_some_and(|size| size < MIN_SIZE)
+    {
+        return false;
+    }
+
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if content_type.starts_with("image/svg") {
+        return true;
+    }
+
+    !COMPRESSED_TYPES
+        .iter()
+        .any(|compressed| content_type.starts_with(compressed))
+}
+
+/// Compress the body of the response and update the headers to match
+pub fn compress(
+    resp: Response<BoxBody<Bytes, BodyError>>,
+    encoding: Encoding,
+) -> Response<BoxBody<Bytes, BodyError>> {
+    let (mut parts, body) = resp.into_parts();
+
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.remove(ACCEPT_RANGES);
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, encoding.header_value());
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+
+    // The compressed body is no longer byte for byte identical
+    if let Some(etag) = parts.headers.get(ETAG)
+        && !etag.as_bytes().starts_with(b"W/")
+    {
+        let mut weak = b"W/".to_vec();
+        weak.extend_from_slice(etag.as_bytes());
+        if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+            parts.headers.insert(ETAG, weak);
+        }
+    }
+
+    let body = CompressedBody {
+        inner: body,
+        encoder: Some(Encoder::new(encoding)),
+        trailers: None,
+    };
+
+    Response::from_parts(parts, body.boxed())
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Gzip => Self::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::fast())),
+            Encoding::Brotli => Self::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            ))),
+        }
+    }
+
+    /// Compress the data and flush it, so streamed responses are not held back
+    fn compress(&mut self, data: &[u8]) -> Bytes {
+        match self {
+            Self::Gzip(encoder) => {
+                encoder
+                    .write_all(data)
+                    .and_then(|_| encoder.flush())
+                    .expect("writing to memory should not fail");
+                std::mem::take(encoder.get_mut()).into()
+            }
+            Self::Brotli(encoder) => {
+                encoder
+                    .write_all(data)
+                    .and_then(|_| encoder.flush())
+                    .expect("writing to memory should not fail");
+                std::mem::take(encoder.get_mut()).into()
+            }
+        }
+    }
+
+    fn finish(self) -> Bytes {
+        match self {
+            Self::Gzip(encoder) => encoder
+                .finish()
+                .expect("writing to memory should not fail")
+                .into(),
+            Self::Brotli(encoder) => encoder.into_inner().into(),
+        }
+    }
+}
+
+pin_project! {
+    /// Compresses the body while it is being streamed to the client
+    struct CompressedBody {
+        #[pin]
+        inner: BoxBody<Bytes, BodyError>,
+        encoder: Option<Encoder>,
+        // Trailers have to wait until the compressed stream is finished
+        trailers: Option<HeaderMap>,
+    }
+}
+
+impl Body for CompressedBody {
+    type Data = Bytes;
+    type Error = BodyError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let project = self.project();
+
+        let Some(encoder) = project.encoder.as_mut() else {
+            return Poll::Ready(
+                project
+                    .trailers
+                    .take()
+                    .map(|trailers| Ok(Frame::trailers(trailers))),
+            );
+        };
+
+        match ready!(project.inner.poll_frame(cx)) {
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(data) => Poll::Ready(Some(Ok(Frame::data(encoder.compress(&data))))),
+                Err(frame) => {
+                    *project.trailers = frame.into_trailers().ok();
+                    let encoder = project.encoder.take().expect("encoder should be set");
+
+                    Poll::Ready(Some(Ok(Frame::data(encoder.finish()))))
+                }
+            },
+            Some(Err(err)) => Poll::Ready(Some(Err(err))),
+            None => {
+                let encoder = project.encoder.take().expect("encoder should be set");
+
+                Poll::Ready(Some(Ok(Frame::data(encoder.finish()))))
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none() && self.trailers.is_none()
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use super::TrustedProxies;
+
+/// Limits the number of concurrent connections from a single address across all tunnels.
+/// Addresses of trusted proxies are exempt, as all their clients share the same address.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    max: usize,
+    trusted: TrustedProxies,
+    active: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+/// Counts as an active connection until it is dropped
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard {
+    // Not set for trusted addresses, they are not counted
+    slot: Option<(IpAddr, Arc<Mutex<HashMap<IpAddr, usize>>>)>,
+}
+
+impl ConnectionLimit {
+    pub fn new(max: usize, trusted: TrustedProxies) -> Self {
+        Self {
+            max,
+            trusted,
+            active: Default::default(),
+        }
+    }
+
+    /// Returns `None` if the address already has the maximum number of connections
+    pub(crate) fn acquire(&self, ip: IpAddr) -> Option<ConnectionGuard> {
+        let ip = ip.to_canonical();
+        if self.trusted.contains(ip) {
+            return Some(ConnectionGuard { slot: None });
+        }
+
+        let mut active = self.active.lock().expect("lock should not be poisoned");
+        let count = active.entry(ip).or_default();
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+
+        Some(ConnectionGuard {
+            slot: Some((ip, self.active.clone())),
+        })
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let Some((ip, active)) = &self.slot else {
+            return;
+        };
+
+        let mut active = active.lock().expect("lock should not be poisoned");
+        if let Some(count) = active.get_mut(ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(ip);
+            }
+        }
+    }
+}
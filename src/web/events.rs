@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::StreamExt as _;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt as _, StreamBody};
+use hyper::body::Frame;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE, HeaderValue};
+use hyper::{Response, StatusCode};
+use sha2::{Digest as _, Sha256};
+use tokio::select;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use super::body::BodyError;
+use crate::tunnel::TunnelEvent;
+
+/// Comments are send while there are no events, so proxies do not close the connection
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Token that gives access to the admin endpoints, send as a bearer token
+#[derive(Debug, Clone)]
+pub struct AdminToken {
+    hash: [u8; 32],
+}
+
+impl AdminToken {
+    pub fn new(token: impl AsRef<[u8]>) -> Self {
+        Self {
+            hash: Sha256::digest(token).into(),
+        }
+    }
+
+    pub(crate) fn verify(&self, authorization: &HeaderValue) -> bool {
+        let Some(token) = authorization
+            .to_str()
+            .ok()
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token.trim())
+        else {
+            return false;
+        };
+
+        // Comparing the hashes takes the same time no matter how much of the token matched
+        Sha256::digest(token)
+            .iter()
+            .zip(self.hash)
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+    }
+}
+
+/// Format the event as a server-sent event, the name of the event is also its type
+fn format_event(event: &TunnelEvent) -> Option<Bytes> {
+    let data = match serde_json::to_string(event) {
+        Ok(data) => data,
+        Err(err) => {
+            warn!("Failed to serialize event: {err}");
+            return None;
+        }
+    };
+
+    Some(format!("event: {}\ndata: {data}\n\n", event.kind()).into())
+}
+
+/// Stream the events to the client as they happen. Clients that can not keep up skip the events
+/// they missed and are told how many, they never hold up the registry. The stream ends once the
+/// server shuts down, otherwise it would keep the connection open forever.
+pub(crate) fn event_stream(
+    receiver: Receiver<TunnelEvent>,
+    shutdown: CancellationToken,
+) -> Response<BoxBody<Bytes, BodyError>> {
+    let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+    keepalive.reset();
+
+    let stream = futures::stream::unfold(
+        (receiver, keepalive, shutdown),
+        |(mut receiver, mut keepalive, shutdown)| async move {
+            loop {
+                let data = select! {
+                    event = receiver.recv() => match event {
+                        Ok(event) => match format_event(&event) {
+                            Some(data) => data,
+                            None => continue,
+                        },
+                        Err(RecvError::Lagged(skipped)) => {
+                            debug!(skipped, "Event stream is lagging behind");
+                            format!("event: lagged\ndata: {{\"skipped\":{skipped}}}\n\n").into()
+                        }
+                        Err(RecvError::Closed) => return None,
+                    },
+                    _ = keepalive.tick() => Bytes::from_static(b":\n\n"),
+                    _ = shutdown.cancelled() => return None,
+                };
+
+                return Some((data, (receiver, keepalive, shutdown)));
+            }
+        },
+    );
+
+    let body = StreamBody::new(stream.map(|data| Ok::<_, BodyError>(Frame::data(data))));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"))
+        .header(CACHE_CONTROL, HeaderValue::from_static("no-cache"))
+        .body(body.boxed())
+        .expect("configuration should be valid")
+}
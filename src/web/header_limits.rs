@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+const MIN_HEADER_SIZE: usize = 8192;
+
+/// Limits on the headers of requests and of the responses from the forwarded ports, requests that
+/// exceed them are answered with `431 Request Header Fields Too Large`
+#[derive(Debug, Clone, Copy)]
+pub struct HeaderLimits {
+    max_size: usize,
+    max_count: usize,
+    // Clients that take longer to send the headers of a request are disconnected, this stops
+    // slow clients from keeping connections open forever
+    timeout: Duration,
+}
+
+impl HeaderLimits {
+    /// The size is raised to 8 KiB if it is smaller, hyper does not support anything below that
+    pub fn new(max_size: usize, max_count: usize, timeout: Duration) -> Self {
+        Self {
+            max_size: max_size.max(MIN_HEADER_SIZE),
+            max_count,
+            timeout,
+        }
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn max_count(&self) -> usize {
+        self.max_count
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+impl Default for HeaderLimits {
+    fn default() -> Self {
+        Self::new(64 * 1024, 100, Duration::from_secs(30))
+    }
+}
@@ -0,0 +1,70 @@
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt as _, Full};
+use hyper::header::{CONTENT_TYPE, HeaderValue};
+use hyper::{Response, StatusCode};
+
+use super::body::BodyError;
+use super::response::escape;
+use crate::VERSION;
+
+/// Page that is shown when visiting the tunnel domain itself
+#[derive(Debug, Clone)]
+pub struct LandingPage {
+    html: String,
+}
+
+impl LandingPage {
+    pub fn new(html: impl Into<String>) -> Self {
+        Self { html: html.into() }
+    }
+
+    /// Built-in page that explains how to open a tunnel
+    pub fn builtin(domain: &str, ssh_port: u16) -> Self {
+        let name = std::env!("CARGO_PKG_NAME");
+        // The domain includes the port of the web server during development
+        let host = escape(domain.split(':').next().unwrap_or(domain));
+        let port = if ssh_port == 22 {
+            String::new()
+        } else {
+            format!(" -p {ssh_port}")
+        };
+
+        Self::new(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{name}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 40em; margin: 4em auto; padding: 0 1em; color: #222; }}
+pre {{ background: #f4f4f4; padding: 1em; overflow-x: auto; }}
+footer {{ margin-top: 3em; color: #888; font-size: 0.8em; }}
+</style>
+</head>
+<body>
+<h1>{name}</h1>
+<p>Share a local web server through an HTTP tunnel, the tunnel is reachable as a subdomain of {host}.</p>
+<p>Open a tunnel to port 3000 on your machine using:</p>
+<pre>ssh &lt;username&gt;@{host}{port} -tq -R &lt;name&gt;:80:localhost:3000</pre>
+<p>Append <code>-- --help</code> to see all available options.</p>
+<footer>{name} ({VERSION})</footer>
+</body>
+</html>
+"#
+        ))
+    }
+
+    pub(crate) fn response(&self) -> Response<BoxBody<Bytes, BodyError>> {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            )
+            .body(Full::new(Bytes::from(self.html.clone())))
+            .expect("all configuration should be valid")
+            .map(|b| b.map_err(|never| match never {}).boxed())
+    }
+}
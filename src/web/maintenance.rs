@@ -0,0 +1,23 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Shared switch that makes every tunnel respond with 503 while it is enabled
+#[derive(Debug, Clone, Default)]
+pub struct Maintenance {
+    enabled: Arc<AtomicBool>,
+}
+
+impl Maintenance {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Flip the state, returns the new state
+    pub fn toggle(&self) -> bool {
+        !self.enabled.fetch_xor(true, Ordering::Relaxed)
+    }
+}
@@ -1,51 +1,228 @@
 mod auth;
+mod body;
+mod compression;
+mod connection_limit;
+mod events;
+mod header_limits;
+mod landing;
+mod maintenance;
+mod offline;
 mod response;
-
+mod reverse_dns;
+mod sensitive_headers;
+mod tls;
+mod trusted_proxies;
+mod upgrade;
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Deref;
 use std::pin::Pin;
+use std::time::Duration;
 
 use auth::AuthStatus;
-pub use auth::ForwardAuth;
+pub use auth::{AuthProviders, ForwardAuth};
+pub use body::BodyLimits;
+use body::{BodyError, LimitedBody, UpstreamBody};
 use bytes::Bytes;
+use compression::Encoding;
+pub use connection_limit::ConnectionLimit;
+pub use events::AdminToken;
+pub use header_limits::HeaderLimits;
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt as _, Empty};
-use hyper::body::Incoming;
-use hyper::header::{self, HOST, UPGRADE};
-use hyper::{Request, Response, StatusCode, client, server};
-use hyper_util::rt::TokioIo;
-use response::response;
+use hyper::body::{Body as _, Incoming};
+use hyper::header::{
+    self, AUTHORIZATION, EXPECT, HOST, HeaderName, HeaderValue, RETRY_AFTER, WWW_AUTHENTICATE,
+};
+use hyper::{Method, Request, Response, StatusCode, Uri, client, server};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+pub use landing::LandingPage;
+pub use maintenance::Maintenance;
+pub use offline::OfflinePage;
+use response::{DenyReason, forbidden_response, html_response, json_response, response};
+pub use reverse_dns::ReverseDns;
+pub use sensitive_headers::SensitiveHeaders;
+use serde::Serialize;
+pub use tls::{TlsError, load_tls_from_env};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
 use tokio::select;
+use tokio_rustls::TlsAcceptor;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
-use tracing::{debug, error, trace, warn};
+use tracing::{Instrument as _, Span, debug, error, field, info, info_span, trace, warn};
+pub use trusted_proxies::TrustedProxies;
+pub use upgrade::{is_upgrade_request, is_upgraded};
+use uuid::Uuid;
+
+use crate::tunnel::{Cors, InspectedBody, OpenError, Registry, TunnelAccess, WebSocketPolicy};
+use crate::{BUILD_TIME, GIT_HASH, VERSION};
+
+/// Settings that change how requests are handled
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    pub body_limits: BodyLimits,
+    pub header_limits: HeaderLimits,
+    pub reverse_dns: Option<ReverseDns>,
+    // Allow tunnels to compress responses
+    pub compression: bool,
+    pub offline_page: OfflinePage,
+    pub landing_page: LandingPage,
+    // The admin endpoints are disabled if not set
+    pub admin_token: Option<AdminToken>,
+    // Tunnels can override this, requests wait indefinitely if neither is set
+    pub upstream_timeout: Option<Duration>,
+    pub connection_limit: Option<ConnectionLimit>,
+    pub trusted_proxies: TrustedProxies,
+    pub sensitive_headers: SensitiveHeaders,
+}
 
-use crate::tunnel::{Registry, TunnelAccess};
+impl ServiceConfig {
+    pub const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(60);
+}
 
 #[derive(Debug, Clone)]
 pub struct Service {
     registry: Registry,
-    auth: ForwardAuth,
+    auth: AuthProviders,
+    tls: Option<TlsAcceptor>,
+    body_limits: BodyLimits,
+    header_limits: HeaderLimits,
+    reverse_dns: Option<ReverseDns>,
+    // Allow tunnels to compress responses
+    compression: bool,
+    offline_page: OfflinePage,
+    landing_page: LandingPage,
+    upstream_timeout: Option<Duration>,
+    connection_limit: Option<ConnectionLimit>,
+    trusted_proxies: TrustedProxies,
+    sensitive_headers: SensitiveHeaders,
+    admin_token: Option<AdminToken>,
+    maintenance: Maintenance,
     task_tracker: TaskTracker,
+    // Cancelled once the server shuts down, ends the responses that would otherwise never finish
+    shutdown: CancellationToken,
+    // Address of the client, only set on the service that handles a specific connection
+    peer_addr: Option<SocketAddr>,
+}
+
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+pub(crate) const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+pub(crate) const X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+const VERSION_PATH: &str = "/__siranga/version";
+const EVENTS_PATH: &str = "/api/events";
+const SESSIONS_PATH: &str = "/api/sessions";
+
+/// Delay before accepting connections again after running out of resources
+const ACCEPT_MIN_BACKOFF: Duration = Duration::from_millis(10);
+const ACCEPT_MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long the request body is held back waiting for `100 Continue` from the forwarded port
+const CONTINUE_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    git_hash: &'static str,
+    build_time: Option<&'static str>,
 }
 
-pub fn empty() -> BoxBody<Bytes, hyper::Error> {
+pub fn empty() -> BoxBody<Bytes, BodyError> {
     Empty::<Bytes>::new()
         .map_err(|never| match never {})
         .boxed()
 }
 
-fn copy_request_parts<T>(req: Request<T>) -> (Request<T>, Request<BoxBody<Bytes, hyper::Error>>) {
+/// Endpoints below the domain that require the admin token
+#[derive(Debug)]
+enum AdminRoute {
+    Events,
+    // Sessions of a single user if set, otherwise of everyone
+    ListSessions(Option<String>),
+    CloseSessions(String),
+}
+
+impl AdminRoute {
+    fn from_request(method: &Method, path: &str) -> Option<Self> {
+        if path == EVENTS_PATH {
+            return (method == Method::GET).then_some(Self::Events);
+        }
+
+        let user = match path.strip_prefix(SESSIONS_PATH)? {
+            "" | "/" => None,
+            user => Some(user.strip_prefix('/')?.to_owned()),
+        };
+
+        match (method, user) {
+            (&Method::GET, user) => Some(Self::ListSessions(user)),
+            (&Method::DELETE, Some(user)) => Some(Self::CloseSessions(user)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ClosedSessions {
+    closed: usize,
+}
+
+/// Split the request into a copy that can still be upgraded and the request that gets forwarded
+/// together with the body
+fn copy_request_parts<T>(req: Request<T>) -> (Request<()>, Request<T>) {
     let (parts, body) = req.into_parts();
-    let req = Request::from_parts(parts.clone(), body);
-    let forwarded_req = Request::from_parts(parts, empty());
+    let req = Request::from_parts(parts.clone(), ());
+    let forwarded_req = Request::from_parts(parts, body);
 
     (req, forwarded_req)
 }
 
-fn copy_response_parts<T>(
-    resp: Response<T>,
-) -> (Response<T>, Response<BoxBody<Bytes, hyper::Error>>) {
+/// Check if the error is caused by one of the sides closing the connection, which is expected to
+/// happen and not worth warning about
+fn is_disconnect(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<std::io::Error>()
+            && matches!(
+                err.kind(),
+                ErrorKind::UnexpectedEof
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+            )
+        {
+            return true;
+        }
+
+        source = err.source();
+    }
+
+    false
+}
+
+/// Wait for the response of the upstream, `None` if it did not arrive in time
+async fn with_timeout<F: Future>(timeout: Option<Duration>, response: F) -> Option<F::Output> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, response).await.ok(),
+        None => Some(response.await),
+    }
+}
+
+/// Errors that only affect the connection that was being accepted, the listener itself is fine
+fn is_connection_error(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+    )
+}
+
+fn copy_response_parts<T>(resp: Response<T>) -> (Response<T>, Response<BoxBody<Bytes, BodyError>>) {
     let (parts, body) = resp.into_parts();
     let resp = Response::from_parts(parts.clone(), body);
     let forwarded_resp = Response::from_parts(parts, empty());
@@ -54,39 +231,141 @@ fn copy_response_parts<T>(
 }
 
 impl Service {
-    pub fn new(registry: Registry, auth: ForwardAuth) -> Self {
+    pub fn new(
+        registry: Registry,
+        auth: impl Into<AuthProviders>,
+        tls: Option<TlsAcceptor>,
+        config: ServiceConfig,
+    ) -> Self {
         Self {
             registry,
-            auth,
+            auth: auth.into(),
+            tls,
+            body_limits: config.body_limits,
+            header_limits: config.header_limits,
+            reverse_dns: config.reverse_dns,
+            compression: config.compression,
+            offline_page: config.offline_page,
+            landing_page: config.landing_page,
+            admin_token: config.admin_token,
+            upstream_timeout: config.upstream_timeout,
+            connection_limit: config.connection_limit,
+            trusted_proxies: config.trusted_proxies,
+            sensitive_headers: config.sensitive_headers,
+            maintenance: Default::default(),
             task_tracker: Default::default(),
+            shutdown: Default::default(),
+            peer_addr: None,
         }
     }
 
-    pub async fn handle_connection(&self, listener: &TcpListener) -> std::io::Result<()> {
-        let (stream, _) = listener.accept().await?;
+    async fn log_access(
+        &self,
+        peer: Option<IpAddr>,
+        tunnel: &str,
+        method: &Method,
+        path: &str,
+        status: StatusCode,
+    ) {
+        let hostname = match (&self.reverse_dns, peer) {
+            (Some(reverse_dns), Some(ip)) => reverse_dns.lookup(ip).await,
+            _ => None,
+        };
 
+        info!(
+            target: "access",
+            tunnel,
+            %method,
+            path,
+            status = status.as_u16(),
+            peer = peer.map(|ip| ip.to_string()),
+            hostname,
+            "Request"
+        );
+    }
+
+    /// Handle to the maintenance switch of this service
+    pub fn maintenance(&self) -> Maintenance {
+        self.maintenance.clone()
+    }
+
+    async fn serve_connection<S>(self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         let io = TokioIo::new(stream);
         let connection = server::conn::http1::Builder::new()
             .preserve_header_case(true)
             .title_case_headers(true)
-            .serve_connection(io, self.clone())
+            .max_buf_size(self.header_limits.max_size())
+            .max_headers(self.header_limits.max_count())
+            .timer(TokioTimer::new())
+            .header_read_timeout(self.header_limits.timeout())
+            .serve_connection(io, self)
             .with_upgrades();
 
+        if let Err(err) = connection.await {
+            error!("Failed to serve connection: {err:?}");
+        }
+    }
+
+    pub async fn handle_connection(&self, listener: &TcpListener) -> std::io::Result<()> {
+        let (stream, peer_addr) = listener.accept().await?;
+
+        let guard = match &self.connection_limit {
+            Some(limit) => match limit.acquire(peer_addr.ip()) {
+                Some(guard) => Some(guard),
+                None => {
+                    debug!(%peer_addr, "Dropped connection, address has too many connections");
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
+
+        let mut service = self.clone();
+        service.peer_addr = Some(peer_addr);
         self.task_tracker.spawn(async move {
-            if let Err(err) = connection.await {
-                error!("Failed to serve connection: {err:?}");
+            // Released once the connection is closed
+            let _guard = guard;
+
+            if let Some(tls) = &service.tls {
+                let handshake = tokio::time::timeout(Duration::from_secs(10), tls.accept(stream));
+                match handshake.await {
+                    Ok(Ok(stream)) => service.serve_connection(stream).await,
+                    Ok(Err(err)) => debug!("TLS handshake failed: {err}"),
+                    Err(_) => debug!("TLS handshake timed out"),
+                }
+            } else {
+                service.serve_connection(stream).await;
             }
         });
 
         Ok(())
     }
 
-    pub async fn serve(self, listener: TcpListener, token: CancellationToken) {
+    pub async fn serve(mut self, listener: TcpListener, token: CancellationToken) {
+        self.shutdown = token.clone();
+
+        let mut backoff = ACCEPT_MIN_BACKOFF;
         loop {
             select! {
                 res = self.handle_connection(&listener) => {
-                    if let Err(err) = res {
-                        error!("Failed to accept connection: {err}")
+                    match res {
+                        Ok(()) => backoff = ACCEPT_MIN_BACKOFF,
+                        Err(err) if is_connection_error(&err) => {
+                            debug!("Connection failed before it was accepted: {err}");
+                        }
+                        Err(err) => {
+                            // Most likely out of file descriptors or memory, accepting again right
+                            // away would fail in the same way
+                            error!(?backoff, "Failed to accept connection: {err}");
+                            select! {
+                                _ = tokio::time::sleep(backoff) => {}
+                                _ = token.cancelled() => break,
+                            }
+                            backoff = (backoff * 2).min(ACCEPT_MAX_BACKOFF);
+                        }
                     }
                 }
                 _ = token.cancelled() => {
@@ -107,12 +386,37 @@ impl Service {
 }
 
 impl hyper::service::Service<Request<Incoming>> for Service {
-    type Response = Response<BoxBody<Bytes, hyper::Error>>;
+    type Response = Response<BoxBody<Bytes, BodyError>>;
     type Error = hyper::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
-    fn call(&self, req: Request<Incoming>) -> Self::Future {
-        trace!("{:#?}", req);
+    fn call(&self, mut req: Request<Incoming>) -> Self::Future {
+        trace!(
+            method = %req.method(),
+            uri = %req.uri(),
+            version = ?req.version(),
+            headers = %self.sensitive_headers.redact(req.headers()),
+            "Incoming request"
+        );
+
+        // Keep the id supplied by the client, so the request can be followed through every hop
+        let request_id = match req.headers().get(X_REQUEST_ID) {
+            Some(request_id) => request_id.clone(),
+            None => {
+                let request_id = HeaderValue::from_str(&Uuid::new_v4().to_string())
+                    .expect("uuid should be a valid header value");
+                req.headers_mut().insert(X_REQUEST_ID, request_id.clone());
+                request_id
+            }
+        };
+        let span = info_span!(
+            "request",
+            request_id = request_id.to_str().unwrap_or_default(),
+            tunnel = field::Empty,
+            user = field::Empty,
+            status = field::Empty,
+        );
+        let _enter = span.enter();
 
         let Some(authority) = req
             .uri()
@@ -125,35 +429,196 @@ impl hyper::service::Service<Request<Incoming>> for Service {
                     .and_then(|h| h.to_str().ok().map(|s| s.to_owned()))
             })
         else {
-            let resp = response(
+            let mut resp = response(
                 StatusCode::BAD_REQUEST,
                 "Missing or invalid authority or host header",
             );
+            resp.headers_mut().insert(X_REQUEST_ID, request_id);
 
             return Box::pin(async { Ok(resp) });
         };
 
         debug!(authority, "Tunnel request");
+        span.record("tunnel", &authority);
+
+        let method = req.method().clone();
+        let path = req.uri().path().to_owned();
+        let tunnel = authority.clone();
+        let access_log = self.clone();
+        let client_ip = self.peer_addr.map(|peer_addr| {
+            self.trusted_proxies
+                .client_ip(peer_addr.ip(), req.headers())
+        });
 
         let s = self.clone();
-        Box::pin(async move {
+        let resp = async move {
+            // Tunnels are always below the domain, so the domain itself can not collide with them
+            if authority == s.registry.domain()
+                && req.method() == Method::GET
+                && req.uri().path() == VERSION_PATH
+            {
+                let info = VersionInfo {
+                    version: VERSION,
+                    git_hash: GIT_HASH,
+                    build_time: BUILD_TIME,
+                };
+
+                return Ok(json_response(StatusCode::OK, &info));
+            }
+
+            if authority == s.registry.domain()
+                && let Some(route) = AdminRoute::from_request(req.method(), req.uri().path())
+                && let Some(admin_token) = &s.admin_token
+            {
+                if !req
+                    .headers()
+                    .get(AUTHORIZATION)
+                    .is_some_and(|value| admin_token.verify(value))
+                {
+                    debug!("Invalid admin token");
+                    let mut resp = response(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                    resp.headers_mut()
+                        .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+
+                    return Ok(resp);
+                }
+
+                let resp = match route {
+                    AdminRoute::Events => {
+                        debug!("Streaming events");
+                        events::event_stream(s.registry.subscribe(), s.shutdown.clone())
+                    }
+                    AdminRoute::ListSessions(user) => {
+                        let sessions = s.registry.sessions().list(user.as_deref());
+                        json_response(StatusCode::OK, &sessions)
+                    }
+                    AdminRoute::CloseSessions(user) => {
+                        let closed = s.registry.sessions().close_user(&user).await;
+                        info!(user, closed, "Closed sessions of user");
+                        json_response(StatusCode::OK, &ClosedSessions { closed })
+                    }
+                };
+
+                return Ok(resp);
+            }
+
+            if authority == s.registry.domain() {
+                return Ok(s.landing_page.response());
+            }
+
+            if s.maintenance.is_enabled() {
+                debug!(tunnel = authority, "Maintenance mode is enabled");
+                let mut resp = html_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Down for maintenance, be right back",
+                );
+                resp.headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from_static("60"));
+
+                return Ok(resp);
+            }
+
             let Some(entry) = s.registry.get(&authority).await else {
+                if s.registry.is_offline(&authority).await {
+                    debug!(tunnel = authority, "Owner of tunnel is offline");
+
+                    return Ok(s.offline_page.response());
+                }
+
                 debug!(tunnel = authority, "Unknown tunnel");
-                let resp = response(StatusCode::NOT_FOUND, "Unknown tunnel");
+                let resp = html_response(StatusCode::NOT_FOUND, "Unknown tunnel");
 
                 return Ok(resp);
             };
 
+            if let Some(client_ip) = client_ip
+                && !entry.get_ip_filter().await.is_allowed(client_ip)
+            {
+                debug!(tunnel = authority, %client_ip, "Address is not allowed");
+                // The visitor is not authenticated yet, so they do not get to know why
+                return Ok(forbidden_response(None));
+            }
+
+            // Preflight requests never carry credentials, so they are answered before checking
+            // access. This does not give access to the tunnel itself.
+            let cors = entry.get_cors().await.clone();
+            if let Some(cors) = &cors
+                && Cors::is_preflight(req.method(), req.headers())
+            {
+                debug!(tunnel = authority, "Answering CORS preflight");
+                let mut resp = Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(empty())
+                    .expect("configuration should be valid");
+                cors.apply_preflight(req.headers(), resp.headers_mut());
+
+                return Ok(resp);
+            }
+
+            if let Some(basic_auth) = entry.get_basic_auth().await.deref() {
+                if !req
+                    .headers()
+                    .get(AUTHORIZATION)
+                    .is_some_and(|value| basic_auth.verify(value))
+                {
+                    debug!(tunnel = authority, "Invalid basic auth credentials");
+                    let mut resp = html_response(StatusCode::UNAUTHORIZED, "Invalid credentials");
+                    resp.headers_mut().insert(
+                        WWW_AUTHENTICATE,
+                        HeaderValue::from_static(r#"Basic realm="siranga", charset="UTF-8""#),
+                    );
+
+                    return Ok(resp);
+                }
+
+                // The credentials are meant for us, so they should not be passed along
+                req.headers_mut().remove(AUTHORIZATION);
+            }
+
             if !entry.is_public().await {
-                let user = match s.auth.check(req.method(), req.headers()).await {
+                let provider = entry.get_auth_provider().await;
+                let Some(auth) = s.auth.get(provider.as_deref()) else {
+                    // Tunnels can only pick providers that exist, but access should never be
+                    // granted by accident
+                    error!(tunnel = authority, provider, "Unknown auth provider");
+                    let resp = html_response(
+                        StatusCode::FORBIDDEN,
+                        "Unexpected error during authentication",
+                    );
+
+                    return Ok(resp);
+                };
+
+                let user = match auth
+                    .check(req.method(), req.uri(), &authority, req.headers())
+                    .await
+                {
                     Ok(AuthStatus::Authenticated(user)) => user,
                     Ok(AuthStatus::Unauthenticated(location)) => {
+                        // Only a trusted proxy in front of us knows how the visitor connected
+                        let forwarded_proto = s
+                            .peer_addr
+                            .filter(|peer_addr| s.trusted_proxies.contains(peer_addr.ip()))
+                            .and_then(|_| req.headers().get(X_FORWARDED_PROTO))
+                            .and_then(|proto| proto.to_str().ok());
+                        let scheme = match forwarded_proto {
+                            _ if s.tls.is_some() => "https",
+                            Some(proto) if proto.trim().eq_ignore_ascii_case("https") => "https",
+                            _ => "http",
+                        };
+                        let path_and_query = req
+                            .uri()
+                            .path_and_query()
+                            .map_or("/", |path_and_query| path_and_query.as_str());
+                        let original_url = format!("{scheme}://{authority}{path_and_query}");
+                        let location = auth.preserve_redirect(location, &original_url);
+
                         let resp = Response::builder()
                             .status(StatusCode::FOUND)
                             .header(header::LOCATION, location)
                             .body(
                                 Empty::new()
-                                    // NOTE: I have NO idea why this is able to convert from Innfallible to hyper::Error
+                                    // NOTE: I have NO idea why this is able to convert from Innfallible to BodyError
                                     .map_err(|never| match never {})
                                     .boxed(),
                             )
@@ -162,16 +627,13 @@ impl hyper::service::Service<Request<Incoming>> for Service {
                         return Ok(resp);
                     }
                     Ok(AuthStatus::Unauthorized) => {
-                        let resp = response(
-                            StatusCode::FORBIDDEN,
-                            "You do not have permission to access this tunnel",
-                        );
-
-                        return Ok(resp);
+                        debug!(tunnel = authority, "Denied by authorization provider");
+                        // The provider only denies access after the visitor has logged in
+                        return Ok(forbidden_response(Some(DenyReason::Provider)));
                     }
                     Err(err) => {
                         error!("Unexpected error during authentication: {err}");
-                        let resp = response(
+                        let resp = html_response(
                             StatusCode::FORBIDDEN,
                             "Unexpected error during authentication",
                         );
@@ -181,49 +643,293 @@ impl hyper::service::Service<Request<Incoming>> for Service {
                 };
 
                 trace!("Tunnel is getting accessed by {user:?}");
+                Span::current().record("user", user.username());
 
                 if let TunnelAccess::Private(owner) = entry.get_access().await.deref() {
                     if !user.is(owner) {
-                        let resp = response(
-                            StatusCode::FORBIDDEN,
-                            "You do not have permission to access this tunnel",
-                        );
-
-                        return Ok(resp);
+                        debug!(tunnel = authority, "Visitor is not the owner of the tunnel");
+                        return Ok(forbidden_response(Some(DenyReason::NotOwner)));
                     }
                 }
             }
 
-            let io = match entry.open().await {
+            // Reject requests that announce a body that is too large before opening the tunnel
+            if req
+                .body()
+                .size_hint()
+                .exact()
+                .is_some_and(|size| size > s.body_limits.max_size())
+            {
+                let resp = html_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "The request body is too large",
+                );
+
+                return Ok(resp);
+            }
+
+            let http2 = entry.is_http2().await;
+            if http2 && upgrade::is_upgrade_request(req.headers()) {
+                let resp = html_response(
+                    StatusCode::NOT_IMPLEMENTED,
+                    "Upgrading connections is not supported for HTTP/2 tunnels",
+                );
+
+                return Ok(resp);
+            }
+
+            // Checked before the request is forwarded, so the upstream never sees the upgrade
+            if WebSocketPolicy::is_websocket(req.headers())
+                && let Err(err) = entry.get_websocket_policy().await.check(req.headers())
+            {
+                debug!(tunnel = authority, "Rejected WebSocket: {err}");
+                let resp = html_response(StatusCode::FORBIDDEN, err.to_string());
+
+                return Ok(resp);
+            }
+
+            let io = match entry.open(req.uri().path()).await {
                 Ok(io) => io,
+                Err(err @ OpenError::Tls(_)) => {
+                    warn!(tunnel = authority, "Failed to open tunnel: {err}");
+                    let resp = html_response(
+                        StatusCode::BAD_GATEWAY,
+                        "Failed to establish a TLS connection with the forwarded port",
+                    );
+
+                    return Ok(resp);
+                }
                 Err(err) => {
                     warn!(tunnel = authority, "Failed to open tunnel: {err}");
-                    let resp = response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to open tunnel");
+                    let resp =
+                        html_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to open tunnel");
 
                     return Ok(resp);
                 }
             };
 
-            let (mut sender, conn) = client::conn::http1::Builder::new()
-                .preserve_header_case(true)
-                .title_case_headers(true)
-                .handshake(io)
-                .await?;
+            let encoding = if s.compression && entry.is_compressed().await {
+                Encoding::negotiate(req.headers())
+            } else {
+                None
+            };
 
-            let conn = conn.with_upgrades();
-            s.task_tracker.spawn(async move {
-                if let Err(err) = conn.await {
-                    warn!(runnel = authority, "Connection failed: {err}");
+            let (mut req, forwarded_req) = copy_request_parts(req);
+            let mut forwarded_req = forwarded_req.map(|body| LimitedBody::new(body, s.body_limits));
+            let stop_body = forwarded_req.body().stop_token();
+            let expects_continue = forwarded_req
+                .headers()
+                .get(EXPECT)
+                .is_some_and(|expect| expect.as_bytes().eq_ignore_ascii_case(b"100-continue"));
+            if expects_continue && !http2 {
+                // Relay `100 Continue` from the forwarded port, the client only sends the body
+                // once the forwarded port is ready for it
+                let proceed = forwarded_req.body_mut().hold_until_continue(CONTINUE_DELAY);
+                hyper::ext::on_informational(&mut forwarded_req, move |resp| {
+                    if resp.status() == StatusCode::CONTINUE {
+                        proceed.cancel();
+                    }
+                });
+            } else {
+                // Hyper already answers `Expect: 100-continue` by sending `100 Continue` to the
+                // client once we start reading the body, the upstream should not try to do the same
+                forwarded_req.headers_mut().remove(EXPECT);
+            }
+            if let Some(peer_addr) = s.peer_addr {
+                let forwarded_for = s
+                    .trusted_proxies
+                    .forwarded_for(peer_addr.ip(), forwarded_req.headers());
+                if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+                    forwarded_req.headers_mut().insert(X_FORWARDED_FOR, value);
                 }
-            });
+            }
+            // Replaced so clients can not pretend to be someone else
+            if let Some(client_ip) = client_ip {
+                forwarded_req.headers_mut().insert(
+                    X_REAL_IP,
+                    HeaderValue::from_str(&client_ip.to_string())
+                        .expect("ip address should be a valid header value"),
+                );
+            }
+            if s.tls.is_some() {
+                forwarded_req
+                    .headers_mut()
+                    .insert(X_FORWARDED_PROTO, HeaderValue::from_static("https"));
+            }
+            entry
+                .get_header_rules()
+                .await
+                .apply_request(forwarded_req.headers_mut());
+            let mut forwarded_req = match entry.get_inspector().await {
+                Some(inspector) => inspector.capture(forwarded_req),
+                None => forwarded_req.map(InspectedBody::new),
+            };
 
-            let (mut req, forwarded_req) = copy_request_parts(req);
+            debug!(
+                tunnel = authority,
+                headers = %s.sensitive_headers.redact(forwarded_req.headers()),
+                "Forwarding request"
+            );
+
+            let upstream_timeout = entry
+                .get_upstream_timeout()
+                .await
+                .unwrap_or(s.upstream_timeout);
+            // Closes the connection to the upstream, only once established connections are
+            // upgraded they are no longer affected
+            let give_up = CancellationToken::new();
+            let tunnel = authority.clone();
+            let resp = if http2 {
+                // HTTP/2 requires the scheme and authority to be part of the uri
+                let path_and_query = forwarded_req
+                    .uri()
+                    .path_and_query()
+                    .map_or("/", |path_and_query| path_and_query.as_str());
+                let scheme = if entry.has_upstream_tls().await {
+                    "https"
+                } else {
+                    "http"
+                };
+                match Uri::builder()
+                    .scheme(scheme)
+                    .authority(authority.as_str())
+                    .path_and_query(path_and_query)
+                    .build()
+                {
+                    Ok(uri) => *forwarded_req.uri_mut() = uri,
+                    Err(err) => {
+                        debug!(tunnel = authority, "Invalid uri: {err}");
+                        return Ok(response(StatusCode::BAD_REQUEST, "Invalid uri"));
+                    }
+                }
 
-            let resp = sender.send_request(forwarded_req).await?;
+                let (mut sender, conn) = client::conn::http2::Builder::new(TokioExecutor::new())
+                    .max_header_list_size(
+                        u32::try_from(s.header_limits.max_size()).unwrap_or(u32::MAX),
+                    )
+                    .handshake(io)
+                    .await?;
+
+                let give_up = give_up.clone();
+                s.task_tracker.spawn(async move {
+                    select! {
+                        result = conn => match result {
+                            Err(err) if is_disconnect(&err) => {
+                                trace!(tunnel, "Connection closed: {err}");
+                            }
+                            Err(err) => warn!(tunnel, "Connection failed: {err}"),
+                            Ok(()) => {}
+                        },
+                        _ = give_up.cancelled() => {
+                            trace!(tunnel, "Closed connection that did not respond in time");
+                        }
+                    }
+                });
+
+                entry.stats().add_request();
+                with_timeout(upstream_timeout, sender.send_request(forwarded_req)).await
+            } else {
+                let (mut sender, conn) = client::conn::http1::Builder::new()
+                    .preserve_header_case(true)
+                    .title_case_headers(true)
+                    .max_buf_size(s.header_limits.max_size())
+                    .max_headers(s.header_limits.max_count())
+                    .handshake(io)
+                    .await?;
+
+                let conn = conn.with_upgrades();
+                let body_stopped = stop_body.clone();
+                let give_up = give_up.clone();
+                s.task_tracker.spawn(async move {
+                    select! {
+                        result = conn => match result {
+                            Err(err) if is_disconnect(&err) => {
+                                trace!(tunnel, "Connection closed: {err}");
+                            }
+                            // Ending the body early is expected to break the connection when the
+                            // length of the body was announced
+                            Err(err) if body_stopped.is_cancelled() => {
+                                trace!(tunnel, "Connection closed after stopping the body: {err}");
+                            }
+                            Err(err) => warn!(tunnel, "Connection failed: {err}"),
+                            Ok(()) => {}
+                        },
+                        _ = give_up.cancelled() => {
+                            trace!(tunnel, "Closed connection that did not respond in time");
+                        }
+                    }
+                });
+
+                entry.stats().add_request();
+                let resp = with_timeout(upstream_timeout, sender.send_request(forwarded_req)).await;
+                // The upstream responded without waiting for the rest of the body, e.g. to reject
+                // a large upload. Continuing to send the body only wastes bandwidth and hangs if
+                // the upstream stops reading. HTTP/2 is left alone, as streaming in both
+                // directions at the same time is common there.
+                if let Some(Ok(resp)) = &resp
+                    && resp.status().as_u16() >= 300
+                {
+                    trace!(tunnel = authority, "Stopped forwarding the request body");
+                    stop_body.cancel();
+                }
 
-            if req.headers().contains_key(UPGRADE)
-                && req.headers().get(UPGRADE) == resp.headers().get(UPGRADE)
-            {
+                resp
+            };
+
+            let Some(resp) = resp else {
+                debug!(
+                    tunnel = authority,
+                    ?upstream_timeout,
+                    "Forwarded port did not respond in time"
+                );
+                give_up.cancel();
+                let resp = html_response(
+                    StatusCode::GATEWAY_TIMEOUT,
+                    "The forwarded port did not respond in time",
+                );
+
+                return Ok(resp);
+            };
+            let mut resp = match resp {
+                Ok(resp) => resp,
+                Err(err) => {
+                    let body_error = std::error::Error::source(&err)
+                        .and_then(|source| source.downcast_ref::<BodyError>());
+                    let resp = match body_error {
+                        Some(BodyError::TooLarge(_)) => html_response(
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                            "The request body is too large",
+                        ),
+                        Some(BodyError::Timeout) => html_response(
+                            StatusCode::REQUEST_TIMEOUT,
+                            "Timed out while receiving the request body",
+                        ),
+                        _ if err.is_parse_too_large() => html_response(
+                            StatusCode::BAD_GATEWAY,
+                            "The response headers are too large",
+                        ),
+                        _ => return Err(err),
+                    };
+                    debug!(tunnel = authority, "Failed to forward request body: {err}");
+
+                    return Ok(resp);
+                }
+            };
+            debug!(
+                tunnel = authority,
+                status = resp.status().as_u16(),
+                headers = %s.sensitive_headers.redact(resp.headers()),
+                "Received response"
+            );
+            entry
+                .get_header_rules()
+                .await
+                .apply_response(resp.headers_mut());
+            if let Some(cors) = &cors {
+                cors.apply(resp.headers_mut());
+            }
+
+            if upgrade::is_upgraded(req.headers(), resp.status(), resp.headers()) {
                 let (mut resp, forwarded_resp) = copy_response_parts(resp);
 
                 debug!("UPGRADE established");
@@ -246,10 +952,11 @@ impl hyper::service::Service<Request<Incoming>> for Service {
                                                 "Received {rx} bytes and send {tx} bytes over upgraded tunnel"
                                             );
                                         }
+                                        Err(err) if is_disconnect(&err) => {
+                                            trace!("Upgraded connection closed: {err}");
+                                        }
                                         Err(err) => {
-                                            // Likely due to channel being closed
-                                            // TODO: Show warning if not channel closed, otherwise ignore
-                                            debug!("Upgraded connection error: {err:?}");
+                                            warn!("Upgraded connection error: {err:?}");
                                         }
                                     }
                                 }
@@ -270,7 +977,34 @@ impl hyper::service::Service<Request<Incoming>> for Service {
 
             trace!("{resp:#?}");
 
+            // Upstreams that lie about the length of the body would otherwise leave the client
+            // waiting, or end up as a truncated but seemingly complete compressed response
+            let resp =
+                resp.map(|body| UpstreamBody::new(body, authority.clone(), upstream_timeout));
+
+            if let Some(encoding) = encoding
+                && compression::should_compress(req.method(), &resp)
+            {
+                trace!(?encoding, "Compressing response");
+                return Ok(compression::compress(resp.map(|b| b.boxed()), encoding));
+            }
+
             Ok(resp.map(|b| b.boxed()))
-        })
+        };
+
+        Box::pin(
+            async move {
+                let mut resp = resp.await?;
+                resp.headers_mut().insert(X_REQUEST_ID, request_id);
+                Span::current().record("status", resp.status().as_u16());
+
+                access_log
+                    .log_access(client_ip, &tunnel, &method, &path, resp.status())
+                    .await;
+
+                Ok(resp)
+            }
+            .instrument(span.clone()),
+        )
     }
 }
@@ -0,0 +1,50 @@
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt as _, Full};
+use hyper::header::{CONTENT_TYPE, HeaderValue};
+use hyper::{Response, StatusCode};
+
+use super::body::BodyError;
+use super::response::html_response;
+
+/// Response for tunnels whose owner disconnected, so visitors can tell them apart from tunnels
+/// that never existed
+#[derive(Debug, Clone)]
+pub struct OfflinePage {
+    status: StatusCode,
+    // Uses the default error page if not set
+    html: Option<String>,
+}
+
+impl OfflinePage {
+    pub const DEFAULT_STATUS: StatusCode = StatusCode::SERVICE_UNAVAILABLE;
+
+    pub fn new(status: StatusCode, html: Option<String>) -> Self {
+        Self { status, html }
+    }
+
+    pub(crate) fn response(&self) -> Response<BoxBody<Bytes, BodyError>> {
+        let Some(html) = &self.html else {
+            return html_response(
+                self.status,
+                "The owner of this tunnel is offline, try again later",
+            );
+        };
+
+        Response::builder()
+            .status(self.status)
+            .header(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            )
+            .body(Full::new(Bytes::from(html.clone())))
+            .expect("all configuration should be valid")
+            .map(|b| b.map_err(|never| match never {}).boxed())
+    }
+}
+
+impl Default for OfflinePage {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_STATUS, None)
+    }
+}
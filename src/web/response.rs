@@ -1,15 +1,146 @@
 use bytes::Bytes;
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt as _, Full};
+use hyper::header::{CONTENT_TYPE, HeaderName, HeaderValue};
 use hyper::{Response, StatusCode};
+use serde::Serialize;
+
+use super::body::BodyError;
+use crate::VERSION;
 
 pub fn response(
     status_code: StatusCode,
     body: impl Into<String>,
-) -> Response<BoxBody<Bytes, hyper::Error>> {
+) -> Response<BoxBody<Bytes, BodyError>> {
     Response::builder()
         .status(status_code)
+        .header(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        )
         .body(Full::new(Bytes::from(body.into())))
         .expect("all configuration should be valid")
         .map(|b| b.map_err(|never| match never {}).boxed())
 }
+
+pub fn json_response(
+    status_code: StatusCode,
+    body: &impl Serialize,
+) -> Response<BoxBody<Bytes, BodyError>> {
+    let body = serde_json::to_vec(body).expect("body should be serializable");
+
+    Response::builder()
+        .status(status_code)
+        .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+        .body(Full::new(Bytes::from(body)))
+        .expect("all configuration should be valid")
+        .map(|b| b.map_err(|never| match never {}).boxed())
+}
+
+pub fn escape(text: &str) -> String {
+    text.chars()
+        .fold(String::with_capacity(text.len()), |mut escaped, c| {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&#39;"),
+                c => escaped.push(c),
+            }
+            escaped
+        })
+}
+
+/// Error page that is shown to users visiting a tunnel
+pub fn html_response(
+    status_code: StatusCode,
+    message: impl AsRef<str>,
+) -> Response<BoxBody<Bytes, BodyError>> {
+    let status = format!(
+        "{} {}",
+        status_code.as_u16(),
+        status_code.canonical_reason().unwrap_or_default()
+    );
+    let name = std::env!("CARGO_PKG_NAME");
+    let message = escape(message.as_ref());
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{status}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 40em; margin: 4em auto; padding: 0 1em; color: #222; }}
+footer {{ margin-top: 3em; color: #888; font-size: 0.8em; }}
+</style>
+</head>
+<body>
+<h1>{status}</h1>
+<p>{message}</p>
+<footer>{name} ({VERSION})</footer>
+</body>
+</html>
+"#
+    );
+
+    Response::builder()
+        .status(status_code)
+        .header(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        )
+        .body(Full::new(Bytes::from(body)))
+        .expect("all configuration should be valid")
+        .map(|b| b.map_err(|never| match never {}).boxed())
+}
+
+const X_SIRANGA_DENY_REASON: HeaderName = HeaderName::from_static("x-siranga-deny-reason");
+
+/// Why an authenticated visitor is not allowed to access a tunnel, only shown to visitors that
+/// are logged in so it can not be used to probe tunnels
+#[derive(Debug, Clone, Copy)]
+pub enum DenyReason {
+    // The tunnel is private and the visitor is not the owner
+    NotOwner,
+    // The authorization provider does not allow the visitor to access the tunnel
+    Provider,
+}
+
+impl DenyReason {
+    fn code(self) -> &'static str {
+        match self {
+            Self::NotOwner => "not-owner",
+            Self::Provider => "provider",
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            Self::NotOwner => {
+                "This tunnel is private, ask the owner to make it protected or to transfer it to you"
+            }
+            Self::Provider => "The authorization provider does not allow you to access this tunnel",
+        }
+    }
+}
+
+/// Error page for visitors that are not allowed to access a tunnel, without a reason the message
+/// is kept generic
+pub fn forbidden_response(reason: Option<DenyReason>) -> Response<BoxBody<Bytes, BodyError>> {
+    let Some(reason) = reason else {
+        return html_response(
+            StatusCode::FORBIDDEN,
+            "You do not have permission to access this tunnel",
+        );
+    };
+
+    let mut resp = html_response(StatusCode::FORBIDDEN, reason.message());
+    resp.headers_mut().insert(
+        X_SIRANGA_DENY_REASON,
+        HeaderValue::from_static(reason.code()),
+    );
+    resp
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+use tracing::trace;
+
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+const CACHE_TTL: Duration = Duration::from_secs(3600);
+const MAX_CACHE_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    hostname: Option<String>,
+    expires: Instant,
+}
+
+/// Cached reverse DNS lookups for the access log.
+///
+/// Lookups happen in the background, a request whose address has not been looked up yet is
+/// logged without a hostname instead of waiting for the lookup.
+#[derive(Debug, Clone, Default)]
+pub struct ReverseDns {
+    cache: Arc<RwLock<HashMap<IpAddr, Option<CacheEntry>>>>,
+}
+
+impl ReverseDns {
+    /// Returns the cached hostname, starts a lookup if the address is not cached yet
+    pub async fn lookup(&self, ip: IpAddr) -> Option<String> {
+        let now = Instant::now();
+        match self.cache.read().await.get(&ip) {
+            Some(Some(entry)) if entry.expires > now => return entry.hostname.clone(),
+            // Lookup is still in progress
+            Some(None) => return None,
+            _ => {}
+        }
+
+        let mut cache = self.cache.write().await;
+        if cache.len() >= MAX_CACHE_SIZE {
+            cache.retain(|_, entry| entry.as_ref().is_none_or(|entry| entry.expires > now));
+            if cache.len() >= MAX_CACHE_SIZE {
+                cache.clear();
+            }
+        }
+        cache.insert(ip, None);
+        drop(cache);
+
+        let cache = self.cache.clone();
+        tokio::spawn(async move {
+            let lookup = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip));
+            let hostname = match tokio::time::timeout(LOOKUP_TIMEOUT, lookup).await {
+                Ok(Ok(Ok(hostname))) if hostname != ip.to_string() => Some(hostname),
+                Ok(Ok(Ok(_))) => None,
+                Ok(Ok(Err(err))) => {
+                    trace!(%ip, "Reverse lookup failed: {err}");
+                    None
+                }
+                Ok(Err(err)) => {
+                    trace!(%ip, "Reverse lookup panicked: {err}");
+                    None
+                }
+                Err(_) => {
+                    trace!(%ip, "Reverse lookup timed out");
+                    None
+                }
+            };
+
+            cache.write().await.insert(
+                ip,
+                Some(CacheEntry {
+                    hostname,
+                    expires: Instant::now() + CACHE_TTL,
+                }),
+            );
+        });
+
+        None
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use hyper::HeaderMap;
+use hyper::header::{AUTHORIZATION, COOKIE, HeaderName, PROXY_AUTHORIZATION, SET_COOKIE};
+
+/// Headers whose values are never written to the logs, as they contain credentials
+#[derive(Debug, Clone)]
+pub struct SensitiveHeaders {
+    names: Arc<HashSet<HeaderName>>,
+}
+
+impl Default for SensitiveHeaders {
+    fn default() -> Self {
+        Self {
+            names: Arc::new(Self::BUILTIN.iter().cloned().collect()),
+        }
+    }
+}
+
+impl SensitiveHeaders {
+    /// Always redacted, additional headers can only be added on top of these
+    pub const BUILTIN: &[HeaderName] = &[AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE];
+
+    pub fn new(extra: impl IntoIterator<Item = HeaderName>) -> Self {
+        Self {
+            names: Arc::new(Self::BUILTIN.iter().cloned().chain(extra).collect()),
+        }
+    }
+
+    /// Format the headers for logging with the values of sensitive headers replaced. Nothing is
+    /// formatted until the log line is actually written.
+    pub(crate) fn redact<'a>(&'a self, headers: &'a HeaderMap) -> Redacted<'a> {
+        Redacted {
+            headers,
+            sensitive: self,
+        }
+    }
+}
+
+pub(crate) struct Redacted<'a> {
+    headers: &'a HeaderMap,
+    sensitive: &'a SensitiveHeaders,
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        for (name, value) in self.headers {
+            if self.sensitive.names.contains(name) {
+                map.entry(name, &format_args!("<redacted>"));
+            } else {
+                map.entry(name, value);
+            }
+        }
+
+        map.finish()
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::{self, ServerConfig};
+use tracing::{debug, trace};
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("Missing environment variable: {0}")]
+    MissingEnvironmentVariable(&'static str),
+    #[error("Invalid entry in TLS_SNI_CERTS, expected <host>=<cert>:<key>: {0}")]
+    InvalidSniEntry(String),
+    #[error("Failed to read '{0}': {1}")]
+    Pem(String, rustls::pki_types::pem::Error),
+    #[error(transparent)]
+    Rustls(#[from] rustls::Error),
+}
+
+/// Picks the certificate based on the server name the client requested, falling back to wildcard
+/// certificates and finally the default certificate.
+#[derive(Debug)]
+struct SniResolver {
+    default: Arc<CertifiedKey>,
+    certs: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let Some(name) = client_hello.server_name() else {
+            return Some(self.default.clone());
+        };
+        let name = name.to_ascii_lowercase();
+
+        if let Some(key) = self.certs.get(&name) {
+            trace!(name, "Using certificate");
+            return Some(key.clone());
+        }
+
+        if let Some((_, parent)) = name.split_once('.')
+            && let Some(key) = self.certs.get(&format!("*.{parent}"))
+        {
+            trace!(name, "Using wildcard certificate");
+            return Some(key.clone());
+        }
+
+        Some(self.default.clone())
+    }
+}
+
+fn load_certified_key(
+    provider: &CryptoProvider,
+    cert: &str,
+    key: &str,
+) -> Result<Arc<CertifiedKey>, TlsError> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| TlsError::Pem(cert.into(), err))?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|err| TlsError::Pem(key.into(), err))?;
+    let key = provider.key_provider.load_private_key(key)?;
+
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
+
+/// Create a TLS acceptor if `TLS_CERT` and `TLS_KEY` are set.
+///
+/// Additional certificates can be provided through `TLS_SNI_CERTS` as a comma separated list of
+/// `<host>=<cert>:<key>`, where host can be a wildcard like `*.example.com`.
+pub fn load_tls_from_env() -> Result<Option<TlsAcceptor>, TlsError> {
+    let (cert, key) = match (std::env::var("TLS_CERT"), std::env::var("TLS_KEY")) {
+        (Ok(cert), Ok(key)) => (cert, key),
+        (Err(_), Err(_)) => return Ok(None),
+        (Err(_), Ok(_)) => return Err(TlsError::MissingEnvironmentVariable("TLS_CERT")),
+        (Ok(_), Err(_)) => return Err(TlsError::MissingEnvironmentVariable("TLS_KEY")),
+    };
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let default = load_certified_key(&provider, &cert, &key)?;
+
+    let mut certs = HashMap::new();
+    if let Ok(entries) = std::env::var("TLS_SNI_CERTS") {
+        for entry in entries.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((host, (cert, key))) = entry
+                .split_once('=')
+                .and_then(|(host, files)| Some((host, files.split_once(':')?)))
+            else {
+                return Err(TlsError::InvalidSniEntry(entry.into()));
+            };
+
+            debug!(host, cert, "Loading certificate");
+            certs.insert(
+                host.to_ascii_lowercase(),
+                load_certified_key(&provider, cert, key)?,
+            );
+        }
+    }
+
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(SniResolver { default, certs }));
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Some(TlsAcceptor::from(Arc::new(config))))
+}
@@ -0,0 +1,83 @@
+use std::net::IpAddr;
+
+use hyper::HeaderMap;
+use ipnet::IpNet;
+
+use super::{X_FORWARDED_FOR, X_REAL_IP};
+
+/// Proxies, like a load balancer in front of siranga, that are trusted to tell us the address of
+/// the client. The headers of everyone else are ignored, as they can be made up by the client.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    nets: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    pub fn new(nets: Vec<IpNet>) -> Self {
+        Self { nets }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Address of the client that made the request. For trusted proxies this is the last address
+    /// in `X-Forwarded-For` that is not also a trusted proxy, or `X-Real-IP` if there is no
+    /// `X-Forwarded-For`.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let peer = peer.to_canonical();
+        if !self.contains(peer) {
+            return peer;
+        }
+
+        let forwarded: Vec<_> = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+
+        let mut client = None;
+        for entry in forwarded.into_iter().rev() {
+            // Everything before an invalid entry can not be trusted either
+            let Ok(ip) = entry.trim().parse::<IpAddr>() else {
+                break;
+            };
+
+            let ip = ip.to_canonical();
+            client = Some(ip);
+            if !self.contains(ip) {
+                break;
+            }
+        }
+
+        client
+            .or_else(|| {
+                headers
+                    .get(X_REAL_IP)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse::<IpAddr>().ok())
+                    .map(|ip| ip.to_canonical())
+            })
+            .unwrap_or(peer)
+    }
+
+    /// Value of `X-Forwarded-For` to send to the upstream, the existing chain is only kept if it
+    /// was sent by a trusted proxy
+    pub fn forwarded_for(&self, peer: IpAddr, headers: &HeaderMap) -> String {
+        let peer = peer.to_canonical();
+        if !self.contains(peer) {
+            return peer.to_string();
+        }
+
+        headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(str::to_owned)
+            .chain(std::iter::once(peer.to_string()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
@@ -0,0 +1,35 @@
+use hyper::header::{CONNECTION, HeaderName, UPGRADE};
+use hyper::{HeaderMap, StatusCode};
+
+/// Comma separated tokens of every value of the header
+fn tokens<'a>(headers: &'a HeaderMap, name: &HeaderName) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+/// Check if the client asked to upgrade the connection
+pub fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    tokens(headers, &CONNECTION).any(|token| token.eq_ignore_ascii_case("upgrade"))
+        && tokens(headers, &UPGRADE).next().is_some()
+}
+
+/// Check if the upstream agreed to switch to one of the protocols the client asked for, header
+/// names and values are compared case-insensitively as servers do not always keep the casing
+pub fn is_upgraded(
+    request_headers: &HeaderMap,
+    status: StatusCode,
+    response_headers: &HeaderMap,
+) -> bool {
+    if status != StatusCode::SWITCHING_PROTOCOLS || !is_upgrade_request(request_headers) {
+        return false;
+    }
+
+    tokens(response_headers, &UPGRADE).any(|accepted| {
+        tokens(request_headers, &UPGRADE).any(|requested| requested.eq_ignore_ascii_case(accepted))
+    })
+}
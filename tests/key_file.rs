@@ -0,0 +1,152 @@
+//! Reading the keys of users from disk instead of LDAP
+
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use rand::rngs::OsRng;
+use russh::keys::{Algorithm, PrivateKey, PublicKey};
+use siranga::key_file::{KeyFile, KeyFileError, parse_authorized_keys, parse_key_file};
+use uuid::Uuid;
+
+fn random_key() -> PublicKey {
+    PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+        .unwrap()
+        .public_key()
+        .clone()
+}
+
+fn openssh(key: &PublicKey) -> String {
+    key.to_openssh().unwrap()
+}
+
+/// Path in the temporary directory that is not used yet
+fn temp_path() -> PathBuf {
+    std::env::temp_dir().join(format!("siranga-{}", Uuid::new_v4()))
+}
+
+#[test]
+fn parses_key_file() {
+    let (alice, bob) = (random_key(), random_key());
+    let contents = format!(
+        "# Comments and empty lines are skipped\n\nalice {}\n  bob   {}\nalice {}\n",
+        openssh(&alice),
+        openssh(&bob),
+        openssh(&bob),
+    );
+
+    let keys = parse_key_file(&contents).unwrap();
+
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys["alice"], [alice, bob.clone()]);
+    assert_eq!(keys["bob"], [bob]);
+}
+
+#[test]
+fn reports_line_of_invalid_key() {
+    let contents = format!(
+        "alice {}\n\nbob ssh-ed25519 invalid\n",
+        openssh(&random_key())
+    );
+
+    let err = parse_key_file(&contents).unwrap_err();
+
+    assert!(matches!(err, KeyFileError::InvalidKey(3, _)), "{err}");
+}
+
+#[test]
+fn reports_line_without_key() {
+    let err = parse_key_file("alice\n").unwrap_err();
+
+    assert!(matches!(err, KeyFileError::MissingKey(1)), "{err}");
+}
+
+#[test]
+fn parses_authorized_keys() {
+    let key = random_key();
+    let contents = format!("# alice\n{} alice@laptop\n", openssh(&key));
+
+    assert_eq!(parse_authorized_keys(&contents).unwrap(), [key]);
+}
+
+#[tokio::test]
+async fn looks_up_user_in_file() {
+    let key = random_key();
+    let path = temp_path();
+    std::fs::write(&path, format!("alice {}\n", openssh(&key))).unwrap();
+
+    let key_file = KeyFile::open(&path).unwrap();
+
+    assert_eq!(key_file.get_ssh_keys("alice").await.unwrap(), [key]);
+    assert!(key_file.get_ssh_keys("bob").await.unwrap().is_empty());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn picks_up_changes_to_file() {
+    let (old, new) = (random_key(), random_key());
+    let path = temp_path();
+    std::fs::write(&path, format!("alice {}\n", openssh(&old))).unwrap();
+    let key_file = KeyFile::open(&path).unwrap();
+
+    // The modification time is set explicitly, as the file might be written within the
+    // resolution of the filesystem
+    let touch = |offset| {
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(offset))
+            .unwrap();
+    };
+
+    std::fs::write(&path, format!("alice {}\n", openssh(&new))).unwrap();
+    touch(10);
+    assert_eq!(key_file.get_ssh_keys("alice").await.unwrap(), [new.clone()]);
+
+    // Mistakes should not lock everyone out
+    std::fs::write(&path, "alice ssh-ed25519 invalid\n").unwrap();
+    touch(20);
+    assert_eq!(key_file.get_ssh_keys("alice").await.unwrap(), [new]);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn rejects_invalid_file_at_startup() {
+    let path = temp_path();
+    std::fs::write(&path, "alice ssh-ed25519 invalid\n").unwrap();
+
+    assert!(KeyFile::open(&path).is_err());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn looks_up_user_in_directory() {
+    let key = random_key();
+    let path = temp_path();
+    std::fs::create_dir(&path).unwrap();
+    std::fs::write(
+        path.join("alice"),
+        format!("{} alice@laptop\n", openssh(&key)),
+    )
+    .unwrap();
+
+    let key_file = KeyFile::open(&path).unwrap();
+
+    assert_eq!(key_file.get_ssh_keys("alice").await.unwrap(), [key]);
+    assert!(key_file.get_ssh_keys("bob").await.unwrap().is_empty());
+    for user in ["", "../alice", ".hidden", "alice/keys"] {
+        assert!(
+            matches!(
+                key_file.get_ssh_keys(user).await,
+                Err(KeyFileError::InvalidUser(_))
+            ),
+            "{user}"
+        );
+    }
+
+    std::fs::remove_dir_all(path).unwrap();
+}
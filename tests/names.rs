@@ -0,0 +1,64 @@
+//! Turning forwarded addresses into the names of tunnels
+
+use siranga::tunnel::NamePolicy;
+
+#[test]
+fn single_label_is_accepted() {
+    assert_eq!(
+        NamePolicy::Reject.apply("hello", Some("alice")).unwrap(),
+        "hello"
+    );
+}
+
+#[test]
+fn labels_below_own_name_are_accepted() {
+    let policy = NamePolicy::Reject;
+
+    assert_eq!(
+        policy.apply("foo.alice", Some("alice")).unwrap(),
+        "foo.alice"
+    );
+    assert_eq!(
+        policy.apply("bar.foo.alice", Some("alice")).unwrap(),
+        "bar.foo.alice"
+    );
+    assert_eq!(policy.apply("*.alice", Some("alice")).unwrap(), "*.alice");
+}
+
+#[test]
+fn labels_below_other_user_are_rejected() {
+    let policy = NamePolicy::Reject;
+
+    assert!(policy.apply("foo.alice", Some("bob")).is_err());
+    assert!(policy.apply("foo.bar", Some("bob")).is_err());
+    assert!(policy.apply("alice.bob.carol", Some("bob")).is_err());
+    assert!(policy.apply("*.alice", Some("bob")).is_err());
+}
+
+#[test]
+fn labels_are_not_checked_without_owner() {
+    assert_eq!(
+        NamePolicy::Reject.apply("foo.bar", None).unwrap(),
+        "foo.bar"
+    );
+}
+
+#[test]
+fn sanitized_labels_are_checked_against_owner() {
+    let policy = NamePolicy::Sanitize;
+
+    assert_eq!(
+        policy.apply("My_App.Alice", Some("alice")).unwrap(),
+        "my-app.alice"
+    );
+    assert!(policy.apply("My_App.Bob", Some("alice")).is_err());
+}
+
+#[test]
+fn invalid_labels_are_rejected() {
+    let policy = NamePolicy::Reject;
+
+    assert!(policy.apply("My_App", Some("alice")).is_err());
+    assert!(policy.apply("foo..alice", Some("alice")).is_err());
+    assert!(policy.apply("foo.*.alice", Some("alice")).is_err());
+}
@@ -0,0 +1,42 @@
+//! Restricting which ports users are allowed to forward
+
+use siranga::ssh::PortPolicy;
+
+#[test]
+fn empty_policy_allows_every_port() {
+    let policy: PortPolicy = "".parse().unwrap();
+
+    assert!(policy.is_allowed(22));
+    assert!(policy.is_allowed(65535));
+}
+
+#[test]
+fn range_includes_boundaries() {
+    let policy: PortPolicy = "1024-65535".parse().unwrap();
+
+    assert!(!policy.is_allowed(80));
+    assert!(!policy.is_allowed(1023));
+    assert!(policy.is_allowed(1024));
+    assert!(policy.is_allowed(3000));
+    assert!(policy.is_allowed(65535));
+}
+
+#[test]
+fn list_of_ports_and_ranges() {
+    let policy: PortPolicy = "80, 443,8000-8080".parse().unwrap();
+
+    assert!(policy.is_allowed(80));
+    assert!(policy.is_allowed(443));
+    assert!(policy.is_allowed(8000));
+    assert!(policy.is_allowed(8080));
+    assert!(!policy.is_allowed(81));
+    assert!(!policy.is_allowed(8081));
+}
+
+#[test]
+fn rejects_invalid_policies() {
+    assert!("80-".parse::<PortPolicy>().is_err());
+    assert!("http".parse::<PortPolicy>().is_err());
+    assert!("65536".parse::<PortPolicy>().is_err());
+    assert!("8080-80".parse::<PortPolicy>().is_err());
+}
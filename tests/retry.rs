@@ -0,0 +1,81 @@
+//! Retrying to open a channel to the client after a transient failure
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use russh::ChannelOpenFailure;
+use siranga::tunnel::RetryPolicy;
+
+/// Stands in for the session handle, failing to open a channel the first `failures` times
+struct MockHandle {
+    failures: u32,
+    reason: ChannelOpenFailure,
+    attempts: AtomicU32,
+}
+
+impl MockHandle {
+    fn new(failures: u32, reason: ChannelOpenFailure) -> Self {
+        Self {
+            failures,
+            reason,
+            attempts: AtomicU32::new(0),
+        }
+    }
+
+    async fn open(&self) -> Result<&'static str, russh::Error> {
+        let attempt = self.attempts.fetch_add(1, Ordering::Relaxed);
+        if attempt < self.failures {
+            Err(russh::Error::ChannelOpenFailure(self.reason))
+        } else {
+            Ok("channel")
+        }
+    }
+
+    fn attempts(&self) -> u32 {
+        self.attempts.load(Ordering::Relaxed)
+    }
+}
+
+fn policy() -> RetryPolicy {
+    RetryPolicy::new(2, Duration::from_millis(1))
+}
+
+#[tokio::test]
+async fn succeeds_after_transient_failure() {
+    let handle = MockHandle::new(1, ChannelOpenFailure::ResourceShortage);
+
+    let result = policy().run(|| handle.open()).await;
+
+    assert!(matches!(result, Ok("channel")));
+    assert_eq!(handle.attempts(), 2);
+}
+
+#[tokio::test]
+async fn gives_up_after_retries() {
+    let handle = MockHandle::new(u32::MAX, ChannelOpenFailure::ResourceShortage);
+
+    let result = policy().run(|| handle.open()).await;
+
+    assert!(matches!(
+        result,
+        Err(russh::Error::ChannelOpenFailure(
+            ChannelOpenFailure::ResourceShortage
+        ))
+    ));
+    assert_eq!(handle.attempts(), 3);
+}
+
+#[tokio::test]
+async fn fails_fast_when_port_can_not_be_reached() {
+    let handle = MockHandle::new(1, ChannelOpenFailure::ConnectFailed);
+
+    let result = policy().run(|| handle.open()).await;
+
+    assert!(matches!(
+        result,
+        Err(russh::Error::ChannelOpenFailure(
+            ChannelOpenFailure::ConnectFailed
+        ))
+    ));
+    assert_eq!(handle.attempts(), 1);
+}
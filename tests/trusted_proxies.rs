@@ -0,0 +1,61 @@
+//! Finding the address of the client behind a load balancer
+
+use std::net::IpAddr;
+
+use hyper::HeaderMap;
+use siranga::web::TrustedProxies;
+
+fn proxies() -> TrustedProxies {
+    TrustedProxies::new(vec!["10.0.0.0/8".parse().unwrap()])
+}
+
+fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    pairs
+        .iter()
+        .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+        .collect()
+}
+
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().unwrap()
+}
+
+#[test]
+fn ignores_headers_of_untrusted_peer() {
+    let headers = headers(&[("x-forwarded-for", "1.2.3.4"), ("x-real-ip", "1.2.3.4")]);
+
+    assert_eq!(proxies().client_ip(ip("5.6.7.8"), &headers), ip("5.6.7.8"));
+    assert_eq!(proxies().forwarded_for(ip("5.6.7.8"), &headers), "5.6.7.8");
+}
+
+#[test]
+fn skips_trusted_proxies_in_chain() {
+    let headers = headers(&[("x-forwarded-for", "6.6.6.6, 1.2.3.4, 10.0.0.2")]);
+
+    assert_eq!(proxies().client_ip(ip("10.0.0.1"), &headers), ip("1.2.3.4"));
+    assert_eq!(
+        proxies().forwarded_for(ip("10.0.0.1"), &headers),
+        "6.6.6.6, 1.2.3.4, 10.0.0.2, 10.0.0.1"
+    );
+}
+
+#[test]
+fn stops_at_invalid_entry() {
+    let headers = headers(&[("x-forwarded-for", "6.6.6.6, garbage, 10.0.0.2")]);
+
+    assert_eq!(
+        proxies().client_ip(ip("10.0.0.1"), &headers),
+        ip("10.0.0.2")
+    );
+}
+
+#[test]
+fn falls_back_to_real_ip() {
+    let headers = headers(&[("x-real-ip", "1.2.3.4")]);
+
+    assert_eq!(proxies().client_ip(ip("10.0.0.1"), &headers), ip("1.2.3.4"));
+    assert_eq!(
+        proxies().client_ip(ip("10.0.0.1"), &HeaderMap::new()),
+        ip("10.0.0.1")
+    );
+}
@@ -0,0 +1,759 @@
+//! Opens tunnels over ssh and sends requests through them, the same way a user would
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt as _, Empty, Full};
+use hyper::body::Incoming;
+use hyper::header::{AUTHORIZATION, HOST, HeaderName};
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use rand::rngs::OsRng;
+use russh::Channel;
+use russh::client::{self, Msg};
+use russh::keys::{Algorithm, PrivateKey, PrivateKeyWithHashAlg, PublicKey};
+use siranga::ssh::{KeyProvider, KeyProviderError, Server, SessionConfig};
+use siranga::tunnel::{NameGenerator, Registry};
+use siranga::web::{
+    AdminToken, AuthProviders, BodyLimits, ForwardAuth, HeaderLimits, LandingPage, OfflinePage,
+    SensitiveHeaders, Service, ServiceConfig, TrustedProxies,
+};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+const DOMAIN: &str = "tunnel.test";
+const UPSTREAM_RESPONSE: &str = "Hello from upstream";
+const GENERATED_NAME: &str = "generated";
+const ADMIN_TOKEN: &str = "admin-secret";
+/// Auth provider that tunnels can pick instead of the default one
+const PARTNER_PROVIDER: &str = "partner";
+const PARTNER_VISITOR: &str = "alice";
+/// Auth provider that denies every visitor
+const DENY_PROVIDER: &str = "deny";
+
+/// Hands out the keys of a single user instead of looking them up in a directory
+#[derive(Debug)]
+struct StaticKeys {
+    user: String,
+    key: PublicKey,
+}
+
+impl KeyProvider for StaticKeys {
+    fn get_ssh_keys<'a>(
+        &'a self,
+        user: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<PublicKey>, KeyProviderError>> + Send + 'a>> {
+        let keys = if user == self.user {
+            vec![self.key.clone()]
+        } else {
+            vec![]
+        };
+
+        Box::pin(async move { Ok(keys) })
+    }
+}
+
+/// Always picks the same name, so tests know where a tunnel without a name ends up
+#[derive(Debug)]
+struct FixedName;
+
+impl NameGenerator for FixedName {
+    fn generate(&self) -> String {
+        GENERATED_NAME.into()
+    }
+}
+
+/// Serve every connection accepted on the listener with the same response
+async fn serve_fixed(listener: TcpListener, headers: Vec<(HeaderName, &'static str)>) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            return;
+        };
+
+        let headers = headers.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |_req| {
+                let mut resp = Response::new(Full::new(Bytes::from(UPSTREAM_RESPONSE)));
+                for (name, value) in &headers {
+                    resp.headers_mut()
+                        .insert(name.clone(), value.parse().expect("value should be valid"));
+                }
+
+                async move { Ok::<_, Infallible>(resp) }
+            });
+
+            let _ = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+async fn spawn_fixed(headers: Vec<(HeaderName, &'static str)>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve_fixed(listener, headers));
+
+    addr
+}
+
+/// Answers every request with 403 Forbidden, like an auth provider that denies the visitor
+async fn spawn_denying() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let service = service_fn(|_req| async {
+                    let mut resp = Response::new(Empty::<Bytes>::new());
+                    *resp.status_mut() = StatusCode::FORBIDDEN;
+                    Ok::<_, Infallible>(resp)
+                });
+
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    addr
+}
+
+/// Announces a longer body than it sends and then keeps the connection open, like an upstream
+/// that hangs halfway through a response
+async fn spawn_lying_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\nshort")
+                    .await;
+
+                let _keep_open = stream;
+                std::future::pending::<()>().await;
+            });
+        }
+    });
+
+    addr
+}
+
+/// Responds with the body of the request, reading the body makes hyper send `100 Continue`
+async fn spawn_echo() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let service = service_fn(|req: Request<Incoming>| async move {
+                    let body = req.into_body().collect().await?.to_bytes();
+                    Ok::<_, hyper::Error>(Response::new(Full::new(body)))
+                });
+
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    addr
+}
+
+/// Accepts connections but never answers, like a forwarded port that hangs
+async fn spawn_silent_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let _keep_open = stream;
+                std::future::pending::<()>().await;
+            });
+        }
+    });
+
+    addr
+}
+
+/// Rejects every request as soon as the headers arrive, without reading the body
+async fn spawn_rejecting_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let _ = read_until(&mut stream, "\r\n\r\n").await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 413 Payload Too Large\r\ncontent-length: 0\r\n\r\n")
+                    .await;
+
+                let _keep_open = stream;
+                std::future::pending::<()>().await;
+            });
+        }
+    });
+
+    addr
+}
+
+/// Read from the stream until the data contains `needle`
+async fn read_until(stream: &mut TcpStream, needle: &str) -> String {
+    let mut data = Vec::new();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        let mut buf = [0; 4096];
+        while !String::from_utf8_lossy(&data).contains(needle) {
+            let read = stream.read(&mut buf).await.unwrap();
+            assert_ne!(read, 0, "connection closed before receiving {needle:?}");
+            data.extend_from_slice(&buf[..read]);
+        }
+    })
+    .await
+    .expect("should not time out");
+
+    String::from_utf8_lossy(&data).into_owned()
+}
+
+/// Connects forwarded connections to the upstream, like `ssh -R` does
+struct Client {
+    upstream: SocketAddr,
+}
+
+impl client::Handler for Client {
+    type Error = russh::Error;
+
+    async fn check_server_key(&mut self, _key: &PublicKey) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    async fn server_channel_open_forwarded_tcpip(
+        &mut self,
+        channel: Channel<Msg>,
+        _connected_address: &str,
+        _connected_port: u32,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        let upstream = self.upstream;
+        tokio::spawn(async move {
+            let mut upstream = TcpStream::connect(upstream).await?;
+            let mut channel = channel.into_stream();
+            tokio::io::copy_bidirectional(&mut channel, &mut upstream).await
+        });
+
+        Ok(())
+    }
+}
+
+struct Harness {
+    ssh_addr: SocketAddr,
+    http_addr: SocketAddr,
+    user: String,
+    key: PrivateKey,
+    registry: Registry,
+    token: CancellationToken,
+}
+
+impl Harness {
+    /// Start the ssh server and web service on ephemeral ports, the default auth endpoint always
+    /// says the visitor is `visitor` and the partner endpoint says it is [`PARTNER_VISITOR`]
+    async fn start(user: &str, visitor: &'static str) -> Self {
+        Self::start_with(user, visitor, |_| {}).await
+    }
+
+    /// Same as [`Self::start`], but allows changing the configuration of the web service
+    async fn start_with(
+        user: &str,
+        visitor: &'static str,
+        configure: impl FnOnce(&mut ServiceConfig),
+    ) -> Self {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let host_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let token = CancellationToken::new();
+
+        let auth_addr = spawn_fixed(vec![(ForwardAuth::DEFAULT_USER_HEADER, visitor)]).await;
+        let auth = ForwardAuth::new(
+            format!("http://{auth_addr}"),
+            ForwardAuth::DEFAULT_TIMEOUT,
+            None,
+            ForwardAuth::DEFAULT_USER_HEADER,
+        );
+        let partner_addr =
+            spawn_fixed(vec![(ForwardAuth::DEFAULT_USER_HEADER, PARTNER_VISITOR)]).await;
+        let partner = ForwardAuth::new(
+            format!("http://{partner_addr}"),
+            ForwardAuth::DEFAULT_TIMEOUT,
+            None,
+            ForwardAuth::DEFAULT_USER_HEADER,
+        );
+        let deny_addr = spawn_denying().await;
+        let deny = ForwardAuth::new(
+            format!("http://{deny_addr}"),
+            ForwardAuth::DEFAULT_TIMEOUT,
+            None,
+            ForwardAuth::DEFAULT_USER_HEADER,
+        );
+        let auth = AuthProviders::new(
+            auth,
+            [
+                (PARTNER_PROVIDER.to_owned(), partner),
+                (DENY_PROVIDER.to_owned(), deny),
+            ],
+        );
+
+        let registry = Registry::new(DOMAIN, FixedName, None, false);
+
+        let keys = StaticKeys {
+            user: user.into(),
+            key: key.public_key().clone(),
+        };
+        let ssh = Server::new(
+            keys,
+            registry.clone(),
+            SessionConfig {
+                auth_providers: vec![PARTNER_PROVIDER.into(), DENY_PROVIDER.into()],
+                ..Default::default()
+            },
+            token.clone(),
+        );
+        let ssh_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ssh_addr = ssh_listener.local_addr().unwrap();
+        tokio::spawn(ssh.serve(vec![host_key], ssh_listener));
+
+        let mut config = ServiceConfig {
+            body_limits: BodyLimits::default(),
+            header_limits: HeaderLimits::default(),
+            reverse_dns: None,
+            compression: false,
+            offline_page: OfflinePage::default(),
+            landing_page: LandingPage::builtin(DOMAIN, ssh_addr.port()),
+            admin_token: Some(AdminToken::new(ADMIN_TOKEN)),
+            upstream_timeout: Some(ServiceConfig::DEFAULT_UPSTREAM_TIMEOUT),
+            connection_limit: None,
+            trusted_proxies: TrustedProxies::default(),
+            sensitive_headers: SensitiveHeaders::default(),
+        };
+        configure(&mut config);
+        let service = Service::new(registry.clone(), auth, None, config);
+        let http_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let http_addr = http_listener.local_addr().unwrap();
+        tokio::spawn(service.serve(http_listener, token.clone()));
+
+        Self {
+            ssh_addr,
+            http_addr,
+            user: user.into(),
+            key,
+            registry,
+            token,
+        }
+    }
+
+    /// Log in, run the command and forward `name` to a trivial upstream
+    async fn open_tunnel(&self, command: &str, name: &str) -> client::Handle<Client> {
+        let upstream = spawn_fixed(vec![]).await;
+
+        self.open_tunnel_to(command, name, upstream).await
+    }
+
+    /// Same as [`Self::open_tunnel`], but forwards to the given upstream
+    async fn open_tunnel_to(
+        &self,
+        command: &str,
+        name: &str,
+        upstream: SocketAddr,
+    ) -> client::Handle<Client> {
+        let mut session = self.connect(command, upstream).await;
+        session.tcpip_forward(name, 80).await.unwrap();
+
+        session
+    }
+
+    /// Log in and run the command, without forwarding anything yet
+    async fn connect(&self, command: &str, upstream: SocketAddr) -> client::Handle<Client> {
+        let mut session = client::connect(
+            Arc::new(client::Config::default()),
+            self.ssh_addr,
+            Client { upstream },
+        )
+        .await
+        .unwrap();
+
+        let auth = session
+            .authenticate_publickey(
+                &self.user,
+                PrivateKeyWithHashAlg::new(Arc::new(self.key.clone()), None),
+            )
+            .await
+            .unwrap();
+        assert!(auth.success(), "authentication should succeed");
+
+        let channel = session.channel_open_session().await.unwrap();
+        channel.exec(true, command).await.unwrap();
+
+        session
+    }
+
+    /// Send a request to the tunnel through the web service
+    async fn get(&self, name: &str) -> (StatusCode, Bytes) {
+        let req = Request::builder()
+            .uri("/")
+            .header(HOST, format!("{name}.{DOMAIN}"))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+
+        self.send(req).await
+    }
+
+    /// Call one of the admin endpoints
+    async fn admin(&self, method: Method, path: &str) -> (StatusCode, Bytes) {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(HOST, DOMAIN)
+            .header(AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+
+        self.send(req).await
+    }
+
+    async fn send(&self, req: Request<Empty<Bytes>>) -> (StatusCode, Bytes) {
+        let resp = self.request(req).await;
+        let status = resp.status();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+
+        (status, body)
+    }
+
+    /// Send the request, without waiting for the body of the response
+    async fn request(&self, req: Request<Empty<Bytes>>) -> Response<Incoming> {
+        let stream = TcpStream::connect(self.http_addr).await.unwrap();
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+
+        tokio::time::timeout(Duration::from_secs(10), sender.send_request(req))
+            .await
+            .expect("request should not time out")
+            .unwrap()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+#[tokio::test]
+async fn public_tunnel_reaches_upstream() {
+    let harness = Harness::start("alice", "bob").await;
+    let _session = harness.open_tunnel("--public", "hello").await;
+
+    let (status, body) = harness.get("hello").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, UPSTREAM_RESPONSE);
+}
+
+#[tokio::test]
+async fn private_tunnel_denies_other_users() {
+    let harness = Harness::start("alice", "bob").await;
+    let _session = harness.open_tunnel("", "secret").await;
+
+    let (status, body) = harness.get("secret").await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_ne!(body, UPSTREAM_RESPONSE);
+}
+
+#[tokio::test]
+async fn tunnel_uses_selected_auth_provider() {
+    let harness = Harness::start(PARTNER_VISITOR, "bob").await;
+    let _session = harness
+        .open_tunnel(&format!("--auth {PARTNER_PROVIDER}"), "secret")
+        .await;
+
+    let (status, body) = harness.get("secret").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, UPSTREAM_RESPONSE);
+}
+
+#[tokio::test]
+async fn response_shorter_than_announced_does_not_hang() {
+    let harness = Harness::start("alice", "bob").await;
+    let upstream = spawn_lying_upstream().await;
+    let _session = harness
+        .open_tunnel_to("--public --timeout 1s", "liar", upstream)
+        .await;
+
+    let req = Request::builder()
+        .uri("/")
+        .header(HOST, format!("liar.{DOMAIN}"))
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    let resp = harness.request(req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body = tokio::time::timeout(Duration::from_secs(10), resp.into_body().collect())
+        .await
+        .expect("client should not be left waiting");
+    assert!(body.is_err(), "body should be cut off");
+}
+
+#[tokio::test]
+async fn loopback_ipv4_gets_generated_name() {
+    let harness = Harness::start("alice", "bob").await;
+    let _session = harness.open_tunnel("--public", "127.0.0.1").await;
+
+    let (status, body) = harness.get(GENERATED_NAME).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, UPSTREAM_RESPONSE);
+}
+
+#[tokio::test]
+async fn loopback_ipv6_gets_generated_name() {
+    let harness = Harness::start("alice", "bob").await;
+    let _session = harness.open_tunnel("--public", "::1").await;
+
+    let (status, body) = harness.get(GENERATED_NAME).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, UPSTREAM_RESPONSE);
+}
+
+#[tokio::test]
+async fn admin_closes_sessions_of_user() {
+    let harness = Harness::start("alice", "bob").await;
+    let _session = harness.open_tunnel("--public", "hello").await;
+
+    let (status, body) = harness.admin(Method::GET, "/api/sessions/alice").await;
+    assert_eq!(status, StatusCode::OK);
+    let sessions: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(sessions.as_array().map(Vec::len), Some(1), "{sessions}");
+    assert_eq!(sessions[0]["user"], "alice");
+
+    let (status, body) = harness.admin(Method::DELETE, "/api/sessions/alice").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, r#"{"closed":1}"#);
+
+    // The tunnel is removed in the background once the session is gone
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while harness.get("hello").await.0 != StatusCode::NOT_FOUND {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("tunnel should be closed");
+
+    let (_, body) = harness.admin(Method::GET, "/api/sessions").await;
+    assert_eq!(body, "[]");
+}
+
+#[tokio::test]
+async fn client_waits_for_continue_before_sending_body() {
+    let harness = Harness::start("alice", "bob").await;
+    let upstream = spawn_echo().await;
+    let _session = harness.open_tunnel_to("--public", "upload", upstream).await;
+
+    let mut stream = TcpStream::connect(harness.http_addr).await.unwrap();
+    stream
+        .write_all(
+            format!(
+                "POST / HTTP/1.1\r\nhost: upload.{DOMAIN}\r\ncontent-length: 5\r\n\
+                expect: 100-continue\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    let interim = read_until(&mut stream, "\r\n\r\n").await;
+    assert!(interim.starts_with("HTTP/1.1 100 Continue"), "{interim}");
+
+    stream.write_all(b"hello").await.unwrap();
+    let resp = read_until(&mut stream, "hello").await;
+    assert!(resp.starts_with("HTTP/1.1 200 OK"), "{resp}");
+}
+
+#[tokio::test]
+async fn early_response_stops_upload() {
+    let harness = Harness::start("alice", "bob").await;
+    let upstream = spawn_rejecting_upstream().await;
+    let _session = harness.open_tunnel_to("--public", "upload", upstream).await;
+
+    let mut stream = TcpStream::connect(harness.http_addr).await.unwrap();
+    stream
+        .write_all(
+            format!("POST / HTTP/1.1\r\nhost: upload.{DOMAIN}\r\ncontent-length: 1048576\r\n\r\n")
+                .as_bytes(),
+        )
+        .await
+        .unwrap();
+    stream.write_all(&[0; 4096]).await.unwrap();
+
+    // The rest of the body is never sent, the response should arrive regardless
+    let resp = read_until(&mut stream, "\r\n\r\n").await;
+    assert!(resp.starts_with("HTTP/1.1 413 Payload Too Large"), "{resp}");
+}
+
+#[tokio::test]
+async fn reconnect_after_disconnect_reuses_name() {
+    let harness = Harness::start("alice", "bob").await;
+
+    for _ in 0..2 {
+        let session = harness.open_tunnel("--public", "hello").await;
+        let (status, body) = harness.get("hello").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, UPSTREAM_RESPONSE);
+
+        session
+            .disconnect(russh::Disconnect::ByApplication, "", "en")
+            .await
+            .unwrap();
+        drop(session);
+
+        // Removed as soon as the server notices the session is gone
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while harness.get("hello").await.0 != StatusCode::NOT_FOUND {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("tunnel should be removed");
+    }
+
+    let (_, body) = harness.admin(Method::GET, "/api/sessions").await;
+    assert_eq!(body, "[]");
+}
+
+#[tokio::test]
+async fn revoked_pool_stays_protected_after_handover() {
+    let harness = Harness::start("alice", "bob").await;
+    let command = format!("--public --pool --auth {DENY_PROVIDER}");
+    let primary = harness.open_tunnel(&command, "hello").await;
+    let _backend = harness.open_tunnel(&command, "hello").await;
+
+    let (status, _) = harness.get("hello").await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(harness.registry.revoke_public_access().await, 2);
+
+    primary
+        .disconnect(russh::Disconnect::ByApplication, "", "en")
+        .await
+        .unwrap();
+    drop(primary);
+
+    // Wait for the backend to take over
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let (_, body) = harness.admin(Method::GET, "/api/sessions").await;
+            let sessions: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            if sessions.as_array().unwrap().len() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("primary session should be removed");
+
+    let (status, body) = harness.get("hello").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_ne!(body, UPSTREAM_RESPONSE);
+}
+
+#[tokio::test]
+async fn names_below_other_user_are_refused() {
+    let harness = Harness::start("alice", "bob").await;
+    let upstream = spawn_fixed(vec![]).await;
+    let mut session = harness.connect("--public", upstream).await;
+
+    assert!(session.tcpip_forward("foo.bob", 80).await.is_err());
+    assert!(session.tcpip_forward("*.bob", 80).await.is_err());
+    let (status, _) = harness.get("foo.bob").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    session.tcpip_forward("foo.alice", 80).await.unwrap();
+    let (status, body) = harness.get("foo.alice").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, UPSTREAM_RESPONSE);
+}
+
+#[tokio::test]
+async fn slow_client_is_disconnected() {
+    let harness = Harness::start_with("alice", "bob", |config| {
+        config.header_limits = HeaderLimits::new(64 * 1024, 100, Duration::from_millis(500));
+    })
+    .await;
+
+    let mut stream = TcpStream::connect(harness.http_addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nhost: ").await.unwrap();
+
+    // Trickle in the headers without ever finishing them
+    tokio::time::timeout(Duration::from_secs(10), async {
+        let mut buf = [0; 1024];
+        loop {
+            if stream.write_all(b"x").await.is_err() {
+                return;
+            }
+
+            match tokio::time::timeout(Duration::from_millis(100), stream.read(&mut buf)).await {
+                Ok(Ok(0) | Err(_)) => return,
+                Ok(Ok(_)) | Err(_) => {}
+            }
+        }
+    })
+    .await
+    .expect("slow client should be disconnected");
+}
+
+#[tokio::test]
+async fn event_stream_ends_on_shutdown() {
+    let harness = Harness::start("alice", "bob").await;
+
+    let req = Request::builder()
+        .uri("/api/events")
+        .header(HOST, DOMAIN)
+        .header(AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    let resp = harness.request(req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    harness.token.cancel();
+
+    tokio::time::timeout(Duration::from_secs(5), resp.into_body().collect())
+        .await
+        .expect("event stream should end once the server shuts down")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn upstream_that_does_not_respond_times_out() {
+    let harness = Harness::start("alice", "bob").await;
+    let upstream = spawn_silent_upstream().await;
+    let _session = harness
+        .open_tunnel_to("--public --timeout 1s", "slow", upstream)
+        .await;
+
+    let (status, _) = harness.get("slow").await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+}
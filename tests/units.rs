@@ -0,0 +1,31 @@
+//! Formatting of byte counters shown to users
+
+use siranga::Unit;
+
+#[test]
+fn small_values_have_no_prefix() {
+    assert_eq!(Unit::new(999, "B").to_string(), "999 B");
+    assert_eq!(Unit::new(1000, "B").to_string(), "1000 B");
+    assert_eq!(Unit::binary(1023, "B").to_string(), "1023 B");
+}
+
+#[test]
+fn large_values_get_prefix() {
+    assert_eq!(Unit::new(10001, "B").to_string(), "10 kB");
+    assert_eq!(Unit::new(12_000_000_000, "B").to_string(), "12 GB");
+}
+
+#[test]
+fn binary_prefixes_use_powers_of_1024() {
+    assert_eq!(Unit::binary(1024, "B").to_string(), "1 KiB");
+    assert_eq!(Unit::binary(1048576, "B").to_string(), "1 MiB");
+    assert_eq!(Unit::new(1048576, "B").to_string(), "1048 kB");
+}
+
+#[test]
+fn binary_prefixes_keep_one_decimal() {
+    assert_eq!(Unit::binary(1536, "B").to_string(), "1.5 KiB");
+    assert_eq!(Unit::binary(1999, "B").to_string(), "2 KiB");
+    assert_eq!(Unit::binary(2_136_746_229, "B").to_string(), "2 GiB");
+    assert_eq!(Unit::binary(1_900_000_000, "B").to_string(), "1.8 GiB");
+}
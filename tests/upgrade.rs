@@ -0,0 +1,99 @@
+//! Detecting connection upgrades regardless of header casing and layout
+
+use hyper::header::{CONNECTION, HeaderName, HeaderValue, UPGRADE};
+use hyper::{HeaderMap, StatusCode};
+use siranga::web::{is_upgrade_request, is_upgraded};
+
+fn headers(entries: &[(HeaderName, &'static str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in entries {
+        headers.append(name, HeaderValue::from_static(value));
+    }
+    headers
+}
+
+#[test]
+fn connection_token_is_case_insensitive() {
+    for connection in ["upgrade", "Upgrade", "UPGRADE"] {
+        let request = headers(&[(CONNECTION, connection), (UPGRADE, "websocket")]);
+
+        assert!(is_upgrade_request(&request), "{connection}");
+    }
+}
+
+#[test]
+fn connection_token_in_list() {
+    let request = headers(&[(CONNECTION, "keep-alive, Upgrade"), (UPGRADE, "websocket")]);
+
+    assert!(is_upgrade_request(&request));
+}
+
+#[test]
+fn connection_token_in_separate_header() {
+    let request = headers(&[
+        (CONNECTION, "keep-alive"),
+        (CONNECTION, "Upgrade"),
+        (UPGRADE, "websocket"),
+    ]);
+
+    assert!(is_upgrade_request(&request));
+}
+
+#[test]
+fn upgrade_requires_both_headers() {
+    assert!(!is_upgrade_request(&headers(&[(UPGRADE, "websocket")])));
+    assert!(!is_upgrade_request(&headers(&[(CONNECTION, "Upgrade")])));
+    assert!(!is_upgrade_request(&headers(&[
+        (CONNECTION, "keep-alive"),
+        (UPGRADE, "websocket"),
+    ])));
+}
+
+#[test]
+fn upgraded_protocol_is_case_insensitive() {
+    let request = headers(&[(CONNECTION, "Upgrade"), (UPGRADE, "websocket")]);
+    let response = headers(&[(CONNECTION, "upgrade"), (UPGRADE, "WebSocket")]);
+
+    assert!(is_upgraded(
+        &request,
+        StatusCode::SWITCHING_PROTOCOLS,
+        &response
+    ));
+}
+
+#[test]
+fn upgraded_to_one_of_multiple_protocols() {
+    let request = headers(&[
+        (CONNECTION, "keep-alive, Upgrade"),
+        (UPGRADE, "h2c"),
+        (UPGRADE, "websocket"),
+    ]);
+    let response = headers(&[(UPGRADE, "websocket")]);
+
+    assert!(is_upgraded(
+        &request,
+        StatusCode::SWITCHING_PROTOCOLS,
+        &response
+    ));
+}
+
+#[test]
+fn not_upgraded_to_other_protocol_or_status() {
+    let request = headers(&[(CONNECTION, "Upgrade"), (UPGRADE, "websocket")]);
+
+    assert!(!is_upgraded(
+        &request,
+        StatusCode::SWITCHING_PROTOCOLS,
+        &headers(&[(UPGRADE, "h2c")])
+    ));
+    assert!(!is_upgraded(
+        &request,
+        StatusCode::OK,
+        &headers(&[(UPGRADE, "websocket")])
+    ));
+    assert!(!is_upgraded(
+        &headers(&[(UPGRADE, "websocket")]),
+        StatusCode::SWITCHING_PROTOCOLS,
+        &headers(&[(UPGRADE, "websocket")])
+    ));
+}